@@ -0,0 +1,231 @@
+use alloy::{
+    primitives::{Address, B256, Bytes, U256, address, aliases::U192, keccak256},
+    providers::{Provider, ProviderBuilder},
+    rpc::types::erc4337,
+    signers::Signer,
+    sol,
+    sol_types::SolCall,
+};
+use anyhow::{Context, Result, anyhow};
+use reqwest::Url;
+use serde::Deserialize;
+use tokio::time::{Duration, Instant};
+
+use crate::{config::Config, redeem};
+
+/// The ERC-4337 v0.7 EntryPoint, deployed at the same address on every chain.
+pub const ENTRY_POINT_V07: Address = address!("0x0000000071727De22E5E9d8BAf0edAc6f37da032");
+
+/// How often the bundler is asked for a user operation's receipt.
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Placeholder signature for gas estimation: well-formed, so accounts that recover an ECDSA
+/// signer reject it as invalid instead of reverting.
+const DUMMY_SIGNATURE: [u8; 65] = alloy::primitives::hex!(
+    "fffffffffffffffffffffffffffffff0000000000000000000000000000000007aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa1c"
+);
+
+sol!(
+    #[allow(missing_docs)]
+    #[sol(rpc)]
+    contract EntryPoint {
+        struct PackedUserOperation {
+            address sender;
+            uint256 nonce;
+            bytes initCode;
+            bytes callData;
+            bytes32 accountGasLimits;
+            uint256 preVerificationGas;
+            bytes32 gasFees;
+            bytes paymasterAndData;
+            bytes signature;
+        }
+        function getNonce(address sender, uint192 key) external view returns (uint256 nonce);
+        function getUserOpHash(PackedUserOperation calldata userOp) external view returns (bytes32);
+    }
+);
+
+sol!(
+    #[allow(missing_docs)]
+    contract SmartAccount {
+        function execute(address dest, uint256 value, bytes calldata func) external;
+    }
+);
+
+/// An ERC-4337 bundler, submitting redemptions as user operations of a smart account owned by
+/// the executor key. The account must expose `execute(address,uint256,bytes)` and accept an
+/// EIP-191 signature of the user operation hash (as `SimpleAccount` does), and pays for gas
+/// itself; the module sees the account as the caller.
+pub struct Bundler {
+    pub url: Url,
+    pub entry_point: Address,
+    pub account: Address,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GasEstimate {
+    pre_verification_gas: U256,
+    verification_gas_limit: U256,
+    call_gas_limit: U256,
+}
+
+#[derive(Debug, Deserialize)]
+struct Receipt {
+    success: bool,
+    receipt: ReceiptTransaction,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ReceiptTransaction {
+    transaction_hash: B256,
+}
+
+impl Bundler {
+    /// Sends a call of `data` on `to` from the smart account and waits until it is included,
+    /// failing if it reverted or is still pending after `tx.timeout`. Returns the hash of the
+    /// bundle transaction.
+    pub async fn send(&self, config: &Config, to: Address, data: &Bytes) -> Result<B256> {
+        let reader = ProviderBuilder::new().connect_client(config.rpc());
+        let entry_point = EntryPoint::new(self.entry_point, &reader);
+        let bundler = config.rpc_client(&self.url);
+
+        // Each call gets its own nonce key, so concurrent redemptions do not compete for one
+        // sequence, while a retry of the same call picks up where the last attempt left off.
+        let key = U192::from_be_slice(&keccak256([to.as_slice(), data].concat())[..24]);
+        let nonce = entry_point.getNonce(self.account, key).call().await?;
+        let fees = reader.estimate_eip1559_fees().await?;
+        let mut op = erc4337::PackedUserOperation {
+            sender: self.account,
+            nonce,
+            factory: None,
+            factory_data: None,
+            call_data: SmartAccount::executeCall {
+                dest: to,
+                value: U256::ZERO,
+                func: data.clone(),
+            }
+            .abi_encode()
+            .into(),
+            call_gas_limit: U256::ZERO,
+            verification_gas_limit: U256::ZERO,
+            pre_verification_gas: U256::ZERO,
+            max_fee_per_gas: U256::from(fees.max_fee_per_gas),
+            max_priority_fee_per_gas: U256::from(fees.max_priority_fee_per_gas),
+            paymaster: None,
+            paymaster_verification_gas_limit: None,
+            paymaster_post_op_gas_limit: None,
+            paymaster_data: None,
+            signature: DUMMY_SIGNATURE.into(),
+        };
+
+        let estimate: GasEstimate = bundler
+            .request("eth_estimateUserOperationGas", (&op, self.entry_point))
+            .await
+            .context("Bundler failed to estimate the user operation")?;
+        let call_gas = u64::try_from(estimate.call_gas_limit)?;
+        op.call_gas_limit =
+            U256::from(redeem::gas_limit(config, call_gas).map_err(|e| anyhow!(e))?);
+        op.verification_gas_limit = estimate.verification_gas_limit;
+        op.pre_verification_gas = estimate.pre_verification_gas;
+
+        let hash = entry_point.getUserOpHash(pack(&op)).call().await?;
+        op.signature = config
+            .signer()?
+            .sign_message(hash.as_slice())
+            .await?
+            .as_bytes()
+            .into();
+        let user_op_hash: B256 = bundler
+            .request("eth_sendUserOperation", (&op, self.entry_point))
+            .await
+            .context("Bundler refused the user operation")?;
+        tracing::info!("Sent user operation {user_op_hash}");
+        self.wait(config, user_op_hash).await
+    }
+
+    async fn wait(&self, config: &Config, user_op_hash: B256) -> Result<B256> {
+        let bundler = config.rpc_client(&self.url);
+        let deadline = config.tx.timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            tokio::time::sleep(POLL_INTERVAL).await;
+            let receipt: Option<Receipt> = match bundler
+                .request("eth_getUserOperationReceipt", (user_op_hash,))
+                .await
+            {
+                Ok(receipt) => receipt,
+                Err(e) => {
+                    tracing::debug!("Failed to poll user operation {user_op_hash}: {e}");
+                    None
+                }
+            };
+            match receipt {
+                Some(receipt) if receipt.success => return Ok(receipt.receipt.transaction_hash),
+                Some(receipt) => {
+                    return Err(anyhow!(
+                        "User operation {user_op_hash} reverted in {}",
+                        receipt.receipt.transaction_hash
+                    ));
+                }
+                None if deadline.is_some_and(|deadline| Instant::now() >= deadline) => {
+                    return Err(anyhow!("User operation {user_op_hash} still pending"));
+                }
+                None => {}
+            }
+        }
+    }
+}
+
+/// The EntryPoint's on-chain encoding of `op`, with gas limits and fees packed in pairs.
+fn pack(op: &erc4337::PackedUserOperation) -> EntryPoint::PackedUserOperation {
+    fn pair(high: U256, low: U256) -> B256 {
+        B256::from((high << 128) | low)
+    }
+    EntryPoint::PackedUserOperation {
+        sender: op.sender,
+        nonce: op.nonce,
+        initCode: Bytes::new(),
+        callData: op.call_data.clone(),
+        accountGasLimits: pair(op.verification_gas_limit, op.call_gas_limit),
+        preVerificationGas: op.pre_verification_gas,
+        gasFees: pair(op.max_priority_fee_per_gas, op.max_fee_per_gas),
+        paymasterAndData: Bytes::new(),
+        signature: op.signature.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pack() {
+        let op = erc4337::PackedUserOperation {
+            sender: Address::ZERO,
+            nonce: U256::ZERO,
+            factory: None,
+            factory_data: None,
+            call_data: Bytes::new(),
+            call_gas_limit: U256::from(0x20000),
+            verification_gas_limit: U256::from(0x10000),
+            pre_verification_gas: U256::ZERO,
+            max_fee_per_gas: U256::from(2),
+            max_priority_fee_per_gas: U256::from(1),
+            paymaster: None,
+            paymaster_verification_gas_limit: None,
+            paymaster_post_op_gas_limit: None,
+            paymaster_data: None,
+            signature: Bytes::new(),
+        };
+        let packed = pack(&op);
+        assert_eq!(
+            packed.accountGasLimits.to_string(),
+            "0x0000000000000000000000000001000000000000000000000000000000020000"
+        );
+        assert_eq!(
+            packed.gasFees.to_string(),
+            "0x0000000000000000000000000000000100000000000000000000000000000002"
+        );
+    }
+}
@@ -0,0 +1,98 @@
+use anyhow::{Context, Result, anyhow};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+/// Pathfinder requests and the responses they got, kept in a JSON file so that searches can
+/// be replayed later without the network, e.g. in tests.
+#[derive(Debug)]
+pub struct Cassette {
+    path: PathBuf,
+    interactions: Mutex<Vec<Interaction>>,
+}
+
+/// One request and its response, as sent and received.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Interaction {
+    pub request: Value,
+    pub response: Value,
+}
+
+impl Cassette {
+    /// The cassette at `path` to record to, empty if there is none yet.
+    pub fn open(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self {
+                path: path.to_path_buf(),
+                interactions: Mutex::default(),
+            });
+        }
+        Self::load(path)
+    }
+
+    /// The cassette at `path` to replay, which must exist.
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read cassette {}", path.display()))?;
+        let interactions = serde_json::from_str(&contents)
+            .with_context(|| format!("Failed to parse cassette {}", path.display()))?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            interactions: Mutex::new(interactions),
+        })
+    }
+
+    /// The response recorded for `request`, if any.
+    pub fn replay(&self, request: &Value) -> Option<Value> {
+        self.interactions
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|interaction| interaction.request == *request)
+            .map(|interaction| interaction.response.clone())
+    }
+
+    /// Records `response` to `request`, replacing any earlier recording of it, and saves the
+    /// cassette.
+    pub fn record(&self, request: Value, response: Value) -> Result<()> {
+        let mut interactions = self
+            .interactions
+            .lock()
+            .map_err(|_| anyhow!("Cassette {} is poisoned", self.path.display()))?;
+        interactions.retain(|interaction| interaction.request != request);
+        interactions.push(Interaction { request, response });
+        std::fs::write(&self.path, serde_json::to_string_pretty(&*interactions)?)
+            .with_context(|| format!("Failed to write cassette {}", self.path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_record_and_replay() {
+        let path =
+            std::env::temp_dir().join(format!("redeem-rs-cassette-{}.json", std::process::id()));
+        assert!(Cassette::load(&path).is_err());
+        let cassette = Cassette::open(&path).unwrap();
+        cassette
+            .record(json!({"Source": "a"}), json!({"maxFlow": "1"}))
+            .unwrap();
+        cassette
+            .record(json!({"Source": "a"}), json!({"maxFlow": "2"}))
+            .unwrap();
+
+        let replayed = Cassette::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            replayed.replay(&json!({"Source": "a"})),
+            Some(json!({"maxFlow": "2"}))
+        );
+        assert_eq!(replayed.replay(&json!({"Source": "b"})), None);
+    }
+}
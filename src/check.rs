@@ -0,0 +1,103 @@
+use alloy::providers::{Provider, ProviderBuilder};
+use anyhow::{Result, anyhow};
+use reqwest::Url;
+
+use crate::{cli::ConfigArgs, config::Config, fetch, redeem};
+
+/// The result of one configuration check.
+pub struct Check {
+    pub name: &'static str,
+    pub result: Result<String>,
+}
+
+/// Loads the configuration and verifies every endpoint and credential it references.
+pub async fn check_config(args: &ConfigArgs) -> Vec<Check> {
+    let config = match Config::load(args) {
+        Ok(config) => config,
+        Err(e) => {
+            return vec![Check {
+                name: "load",
+                result: Err(e),
+            }];
+        }
+    };
+    let mut checks = vec![Check {
+        name: "load",
+        result: Ok(match &config.path {
+            Some(path) => format!("{} on {}", path.display(), config.network),
+            None => format!("no config file, {}", config.network),
+        }),
+    }];
+    checks.push(Check {
+        name: "signer",
+        result: config
+            .executor()
+            .map(|executor| format!("executor {executor}")),
+    });
+    checks.push(Check {
+        name: "rpc",
+        result: check_chain_id(&config, &config.rpc_url).await,
+    });
+    for url in &config.rpc_fallbacks {
+        checks.push(Check {
+            name: "rpc fallback",
+            result: check_chain_id(&config, url).await,
+        });
+    }
+    if let Some(send_rpc_url) = &config.send_rpc_url {
+        checks.push(Check {
+            name: "send rpc",
+            result: check_chain_id(&config, send_rpc_url).await,
+        });
+    }
+    if let Some(ws) = &config.ws {
+        checks.push(Check {
+            name: "websocket",
+            result: ws
+                .provider(config.chain_id)
+                .await
+                .map(|_| format!("{} (chain {})", ws.url, config.chain_id)),
+        });
+    }
+    checks.push(Check {
+        name: "circles rpc",
+        result: check_chain_id(&config, &config.circles_rpc_url).await,
+    });
+    for &module in &config.modules {
+        checks.push(Check {
+            name: "module",
+            result: redeem::check_module(&config, module).await,
+        });
+    }
+    if let Some(health_url) = &config.api_health_url {
+        checks.push(Check {
+            name: "indexer health",
+            result: fetch::check_health(&config, health_url)
+                .await
+                .map(|version| match version {
+                    Some(version) => format!("{health_url} (API {version})"),
+                    None => health_url.to_string(),
+                })
+                .map_err(Into::into),
+        });
+    }
+    checks.push(Check {
+        name: "indexer",
+        result: fetch::fetch_redeemable_subscriptions(&config, &Default::default())
+            .await
+            .map(|subscriptions| format!("{} redeemable subscriptions", subscriptions.len())),
+    });
+    checks
+}
+
+async fn check_chain_id(config: &Config, url: &Url) -> Result<String> {
+    let expected = config.chain_id;
+    let chain_id = ProviderBuilder::new()
+        .connect_client(config.rpc_client(url))
+        .get_chain_id()
+        .await?;
+    if chain_id != expected {
+        return Err(anyhow!("{url} is on chain {chain_id}, expected {expected}"));
+    }
+    Ok(format!("{url} (chain {chain_id})"))
+}
@@ -0,0 +1,563 @@
+use alloy::{
+    primitives::{Address, U256, aliases::U192},
+    rpc::client::RpcClient,
+};
+use anyhow::Result;
+use circles_pathfinder::{FindPathParams, PathData, TransferStep, create_flow_matrix};
+use clap::ValueEnum;
+use reqwest::{Client, Url};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::fetch::RateLimiter;
+use crate::pathfinder::{self, InsufficientFlow};
+
+/// A client for the Circles RPC: path searches, and the balance and trust queries behind
+/// preflight checks, local pathfinding and explaining why no path was found. Clones share the
+/// HTTP client and the request-id counter of one [`RpcClient`], so calls from anywhere in the
+/// pipeline can run at the same time without their JSON-RPC ids colliding, and one rate limit
+/// spaces them all out.
+#[derive(Clone)]
+pub struct CirclesClient {
+    url: Url,
+    client: RpcClient,
+    api: PathfinderApi,
+    rate_limit: Arc<RateLimiter>,
+}
+
+/// The JSON-RPC method that paths are searched with.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum PathfinderApi {
+    /// `circlesV2_findPath` of the Circles RPC.
+    #[default]
+    V2,
+    /// `compute_transfer` of the legacy Circles V1 pathfinder, which only takes the endpoints
+    /// and amount, and ignores the token and wrapping settings.
+    V1,
+}
+
+impl PathfinderApi {
+    fn method(self) -> &'static str {
+        match self {
+            PathfinderApi::V2 => "circlesV2_findPath",
+            PathfinderApi::V1 => "compute_transfer",
+        }
+    }
+
+    /// The parameters of [`method`](Self::method) for `params`.
+    fn params(self, params: &FindPathParams) -> serde_json::Result<serde_json::Value> {
+        match self {
+            PathfinderApi::V2 => serde_json::to_value((FindPathRequest::from(params),)),
+            PathfinderApi::V1 => serde_json::to_value(ComputeTransferRequest::from(params)),
+        }
+    }
+}
+
+/// One token held by an account, as listed by `circles_getTokenBalances`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TokenBalance {
+    pub token_address: Address,
+    /// The avatar that minted the token.
+    pub token_owner: Address,
+    #[serde(with = "decimal")]
+    pub atto_circles: U256,
+    #[serde(default)]
+    pub is_wrapped: bool,
+    #[serde(default)]
+    pub is_group: bool,
+}
+
+/// Who an account trusts and is trusted by, as listed by `circles_getTrustRelations`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TrustRelations {
+    /// Avatars whose tokens the account accepts.
+    #[serde(default, deserialize_with = "addresses")]
+    pub trusts: Vec<Address>,
+    /// Avatars that accept the account's token.
+    #[serde(default, deserialize_with = "addresses")]
+    pub trusted_by: Vec<Address>,
+}
+
+/// An address list, given either as an array or as an object keyed by address (with the
+/// trust limit as value).
+fn addresses<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Address>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Addresses {
+        List(Vec<Address>),
+        Map(HashMap<Address, serde_json::Value>),
+    }
+    Ok(match Addresses::deserialize(deserializer)? {
+        Addresses::List(list) => list,
+        Addresses::Map(map) => map.into_keys().collect(),
+    })
+}
+
+/// What `circles_getAvatarInfo` says an avatar is.
+#[derive(Debug, Clone, Deserialize)]
+struct AvatarInfo {
+    /// The registration event, such as `CrcV2_RegisterHuman` or `CrcV2_RegisterGroup`.
+    #[serde(rename = "type", default)]
+    kind: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct Amount(#[serde(with = "decimal")] U256);
+
+impl CirclesClient {
+    /// A client for the Circles RPC at `url`, searching paths with `api` and sending at most
+    /// one request (or batch) every `min_interval`.
+    pub fn new(http: Client, url: Url, api: PathfinderApi, min_interval: Duration) -> Self {
+        Self {
+            client: RpcClient::new_http_with_client(http, url.clone()),
+            url,
+            api,
+            rate_limit: Arc::new(RateLimiter::new("Circles RPC", min_interval)),
+        }
+    }
+
+    /// A path for `params` with the configured [`PathfinderApi`], with its flow matrix prepared
+    /// for the contract.
+    pub async fn find_path(
+        &self,
+        params: &FindPathParams,
+    ) -> Result<PathData, Box<dyn std::error::Error>> {
+        path_data_json(params, self.find_path_json(params).await?)
+    }
+
+    /// The pathfinder's response for `params`, as the RPC sent it.
+    pub async fn find_path_json(
+        &self,
+        params: &FindPathParams,
+    ) -> Result<serde_json::Value, Box<dyn std::error::Error>> {
+        let request = self.api.params(params)?;
+        self.rate_limit.acquire().await;
+        Ok(self.client.request(self.api.method(), request).await?)
+    }
+
+    /// A path for each of `searches`, in order, all searched with a single JSON-RPC batch
+    /// request. The whole batch fails only if the request does.
+    pub async fn find_paths(
+        &self,
+        searches: &[&FindPathParams],
+    ) -> Result<Vec<Result<PathData, Box<dyn std::error::Error>>>, Box<dyn std::error::Error>> {
+        if searches.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut batch = self.client.new_batch();
+        // The batch matches each response to its call by JSON-RPC id.
+        let waiters = searches
+            .iter()
+            .map(|params| -> Result<_, Box<dyn std::error::Error>> {
+                let request = self.api.params(params)?;
+                Ok(batch.add_call::<_, FindPathResponse>(self.api.method(), &request)?)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        tracing::debug!(
+            "Finding {} paths in one batch on {}",
+            searches.len(),
+            self.url
+        );
+        self.rate_limit.acquire().await;
+        batch.send().await?;
+        let results = futures::future::join_all(waiters).await;
+        Ok(searches
+            .iter()
+            .zip(results)
+            .map(|(params, result)| path_data(params, result?))
+            .collect())
+    }
+
+    /// The balance of `address` in atto-circles, over all the tokens it holds.
+    pub async fn total_balance(&self, address: Address) -> Result<U256> {
+        self.rate_limit.acquire().await;
+        let Amount(balance) = self
+            .client
+            .request("circlesV2_getTotalBalance", (address, false))
+            .await?;
+        Ok(balance)
+    }
+
+    /// Every token `address` holds, with its balance.
+    pub async fn token_balances(&self, address: Address) -> Result<Vec<TokenBalance>> {
+        self.rate_limit.acquire().await;
+        Ok(self
+            .client
+            .request("circles_getTokenBalances", (address,))
+            .await?)
+    }
+
+    /// Whom `address` trusts and is trusted by.
+    pub async fn trust_relations(&self, address: Address) -> Result<TrustRelations> {
+        self.rate_limit.acquire().await;
+        Ok(self
+            .client
+            .request("circles_getTrustRelations", (address,))
+            .await?)
+    }
+
+    /// Whether `address` is registered as a Circles group, which mints its token for the tokens
+    /// of its members.
+    pub async fn is_group(&self, address: Address) -> Result<bool> {
+        self.rate_limit.acquire().await;
+        let info: Option<AvatarInfo> = self
+            .client
+            .request("circles_getAvatarInfo", (address,))
+            .await?;
+        Ok(info.is_some_and(|info| info.kind.contains("Group")))
+    }
+
+    /// How many of the tokens `from` holds `to` accepts directly (its own, or one it trusts),
+    /// and how many tokens `from` holds.
+    pub async fn accepted_tokens(&self, from: Address, to: Address) -> Result<(usize, usize)> {
+        let (balances, relations) =
+            futures::try_join!(self.token_balances(from), self.trust_relations(to))?;
+        let accepted = balances
+            .iter()
+            .filter(|balance| !balance.atto_circles.is_zero())
+            .filter(|balance| {
+                balance.token_owner == to || relations.trusts.contains(&balance.token_owner)
+            })
+            .count();
+        Ok((accepted, balances.len()))
+    }
+}
+
+/// Parameters of `circlesV2_findPath`, as the Circles RPC names them.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct FindPathRequest {
+    source: Address,
+    sink: Address,
+    #[serde(with = "decimal")]
+    target_flow: U256,
+    with_wrap: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    from_tokens: Option<Vec<Address>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    to_tokens: Option<Vec<Address>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    excluded_from_tokens: Option<Vec<Address>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    excluded_to_tokens: Option<Vec<Address>>,
+}
+
+impl From<&FindPathParams> for FindPathRequest {
+    fn from(params: &FindPathParams) -> Self {
+        FindPathRequest {
+            source: params.from,
+            sink: params.to,
+            target_flow: params.target_flow,
+            with_wrap: params.use_wrapped_balances.unwrap_or(false),
+            from_tokens: params.from_tokens.clone(),
+            to_tokens: params.to_tokens.clone(),
+            excluded_from_tokens: params.exclude_from_tokens.clone(),
+            excluded_to_tokens: params.exclude_to_tokens.clone(),
+        }
+    }
+}
+
+/// Parameters of the V1 pathfinder's `compute_transfer`.
+#[derive(Debug, Clone, Serialize)]
+struct ComputeTransferRequest {
+    from: Address,
+    to: Address,
+    #[serde(with = "decimal")]
+    value: U256,
+}
+
+impl From<&FindPathParams> for ComputeTransferRequest {
+    fn from(params: &FindPathParams) -> Self {
+        ComputeTransferRequest {
+            from: params.from,
+            to: params.to,
+            value: params.target_flow,
+        }
+    }
+}
+
+/// Paths found recently, reused for identical searches (same endpoints, target flow and token
+/// settings) for `path_cache_ttl`, so that retries and back-to-back runs skip the pathfinder.
+#[derive(Default)]
+pub struct PathCache {
+    ttl: Duration,
+    entries: Mutex<HashMap<FindPathRequest, (Instant, PathData)>>,
+}
+
+impl PathCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: Mutex::default(),
+        }
+    }
+
+    /// The path found for `params` less than `ttl` ago, if any.
+    pub fn get(&self, params: &FindPathParams) -> Option<PathData> {
+        let entries = self.entries.lock().ok()?;
+        let (found, path_data) = entries.get(&FindPathRequest::from(params))?;
+        (found.elapsed() < self.ttl).then(|| path_data.clone())
+    }
+
+    /// Remembers the path found for `params`, dropping those that have expired.
+    pub fn store(&self, params: &FindPathParams, path_data: &PathData) {
+        if self.ttl.is_zero() {
+            return;
+        }
+        if let Ok(mut entries) = self.entries.lock() {
+            entries.retain(|_, (found, _)| found.elapsed() < self.ttl);
+            entries.insert(
+                FindPathRequest::from(params),
+                (Instant::now(), path_data.clone()),
+            );
+        }
+    }
+
+    /// Forgets every path, so that the next searches go to the pathfinder.
+    pub fn clear(&self) {
+        if let Ok(mut entries) = self.entries.lock() {
+            entries.clear();
+        }
+    }
+}
+
+/// A `circlesV2_findPath` result, or a V1 `compute_transfer` one under its own field names.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct FindPathResponse {
+    #[serde(with = "decimal", alias = "maxFlowValue")]
+    max_flow: U256,
+    #[serde(alias = "transferSteps")]
+    transfers: Vec<Transfer>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Transfer {
+    from: Address,
+    to: Address,
+    #[serde(alias = "token_owner")]
+    token_owner: Address,
+    value: U192,
+}
+
+/// Serde adapter for amounts in atto-circles, which the Circles RPC and the indexer exchange as
+/// decimal strings. Hex strings and plain numbers are accepted as well.
+pub mod decimal {
+    use alloy::primitives::U256;
+    use serde::{Deserialize, Deserializer, Serializer, de::Error};
+    use std::str::FromStr;
+
+    pub fn serialize<S: Serializer>(value: &U256, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(value)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<U256, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Amount {
+            String(String),
+            Number(u64),
+        }
+        match Amount::deserialize(deserializer)? {
+            Amount::String(value) => U256::from_str(&value)
+                .map_err(|e| D::Error::custom(format!("invalid amount {value:?}: {e}"))),
+            Amount::Number(value) => Ok(U256::from(value)),
+        }
+    }
+}
+
+/// The `circlesV2_findPath` request for `params`, as sent to the RPC.
+pub fn find_path_request(params: &FindPathParams) -> serde_json::Result<serde_json::Value> {
+    serde_json::to_value(FindPathRequest::from(params))
+}
+
+/// [`path_data`] for a pathfinder response as the RPC sent it.
+pub fn path_data_json(
+    params: &FindPathParams,
+    response: serde_json::Value,
+) -> Result<PathData, Box<dyn std::error::Error>> {
+    path_data(params, serde_json::from_value(response)?)
+}
+
+/// The flow matrix for the transfers the pathfinder found for `params`, or
+/// [`InsufficientFlow`] if its `maxFlow` is short of the target.
+fn path_data(
+    params: &FindPathParams,
+    response: FindPathResponse,
+) -> Result<PathData, Box<dyn std::error::Error>> {
+    tracing::debug!(
+        "Path {} -> {} carries {} of {}",
+        params.from,
+        params.to,
+        response.max_flow,
+        params.target_flow
+    );
+    if response.max_flow < params.target_flow {
+        return Err(InsufficientFlow {
+            max_flow: response.max_flow,
+            target: params.target_flow,
+        }
+        .into());
+    }
+    let transfers: Vec<TransferStep> = response
+        .transfers
+        .into_iter()
+        .map(|transfer| TransferStep {
+            from_address: transfer.from,
+            to_address: transfer.to,
+            token_owner: transfer.token_owner,
+            value: transfer.value,
+        })
+        .collect();
+    flow_matrix(params, &transfers)
+}
+
+/// The flow matrix for `transfers` from `params.from` to `params.to`, prepared for the
+/// contract. Fails with [`InvalidPath`](pathfinder::InvalidPath) if they are inconsistent, and
+/// with [`InsufficientFlow`] if they carry less than `params.target_flow` to the sink.
+pub fn flow_matrix(
+    params: &FindPathParams,
+    transfers: &[TransferStep],
+) -> Result<PathData, Box<dyn std::error::Error>> {
+    pathfinder::validate(params, transfers)?;
+    let matrix = create_flow_matrix(
+        params.from,
+        params.to,
+        U192::uint_try_from(params.target_flow)?,
+        transfers,
+    )
+    .map_err(pathfinder::typed)?;
+    Ok(PathData::from_flow_matrix(matrix))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_path_request() {
+        let params = FindPathParams {
+            from: Address::with_last_byte(1),
+            to: Address::with_last_byte(2),
+            target_flow: U256::from(1000),
+            use_wrapped_balances: Some(true),
+            from_tokens: None,
+            to_tokens: Some(vec![Address::with_last_byte(2)]),
+            exclude_from_tokens: None,
+            exclude_to_tokens: None,
+            simulated_balances: None,
+            simulated_trusts: None,
+            max_transfers: None,
+        };
+        let request = serde_json::to_value(FindPathRequest::from(&params)).unwrap();
+        assert_eq!(
+            request["Source"],
+            "0x0000000000000000000000000000000000000001"
+        );
+        assert_eq!(request["TargetFlow"], "1000");
+        assert_eq!(request["WithWrap"], true);
+        assert_eq!(
+            request["ToTokens"][0],
+            "0x0000000000000000000000000000000000000002"
+        );
+        assert!(request.get("FromTokens").is_none());
+
+        assert_eq!(PathfinderApi::V2.params(&params).unwrap()[0], request);
+        let request = PathfinderApi::V1.params(&params).unwrap();
+        assert_eq!(
+            request["from"],
+            "0x0000000000000000000000000000000000000001"
+        );
+        assert_eq!(request["value"], "1000");
+        assert!(request.get("to_tokens").is_none());
+    }
+
+    #[test]
+    fn test_deserialize_find_path_response() {
+        let json = r#"{
+            "maxFlow": "1000",
+            "transfers": [{
+                "from": "0x0000000000000000000000000000000000000001",
+                "to": "0x0000000000000000000000000000000000000002",
+                "tokenOwner": "0x0000000000000000000000000000000000000001",
+                "value": "1000"
+            }]
+        }"#;
+        let response: FindPathResponse = serde_json::from_str(json).unwrap();
+        assert_eq!(response.max_flow, U256::from(1000));
+        assert_eq!(
+            response.transfers[0].token_owner,
+            Address::with_last_byte(1)
+        );
+        assert_eq!(response.transfers[0].value, U192::from(1000));
+    }
+
+    #[test]
+    fn test_deserialize_compute_transfer_response() {
+        let json = r#"{
+            "maxFlowValue": "1000",
+            "final": true,
+            "transferSteps": [{
+                "from": "0x0000000000000000000000000000000000000001",
+                "to": "0x0000000000000000000000000000000000000002",
+                "token_owner": "0x0000000000000000000000000000000000000001",
+                "value": "1000"
+            }]
+        }"#;
+        let response: FindPathResponse = serde_json::from_str(json).unwrap();
+        assert_eq!(response.max_flow, U256::from(1000));
+        assert_eq!(
+            response.transfers[0].token_owner,
+            Address::with_last_byte(1)
+        );
+    }
+
+    #[test]
+    fn test_deserialize_trust_relations() {
+        let list = r#"{
+            "user": "0x0000000000000000000000000000000000000001",
+            "trusts": ["0x0000000000000000000000000000000000000002"],
+            "trustedBy": []
+        }"#;
+        let relations: TrustRelations = serde_json::from_str(list).unwrap();
+        assert_eq!(relations.trusts, vec![Address::with_last_byte(2)]);
+        assert!(relations.trusted_by.is_empty());
+
+        let map = r#"{"trusts": {"0x0000000000000000000000000000000000000002": 100}}"#;
+        let relations: TrustRelations = serde_json::from_str(map).unwrap();
+        assert_eq!(relations.trusts, vec![Address::with_last_byte(2)]);
+    }
+
+    #[test]
+    fn test_deserialize_avatar_info() {
+        let info: AvatarInfo = serde_json::from_str(
+            r#"{"avatar": "0x0000000000000000000000000000000000000001", "type": "CrcV2_RegisterGroup"}"#,
+        )
+        .unwrap();
+        assert!(info.kind.contains("Group"));
+    }
+
+    #[test]
+    fn test_decimal() {
+        #[derive(Debug, Serialize, Deserialize)]
+        struct Amount(#[serde(with = "decimal")] U256);
+        let parse = |json| serde_json::from_str::<Amount>(json).map(|amount| amount.0);
+        assert_eq!(
+            parse(r#""10000000000000000""#).unwrap(),
+            U256::from(10u64.pow(16))
+        );
+        assert_eq!(parse(r#""0x2710""#).unwrap(), U256::from(10_000));
+        assert_eq!(parse("42").unwrap(), U256::from(42));
+        assert!(parse(r#""ten""#).is_err());
+        let json = serde_json::to_string(&Amount(U256::from(10u64.pow(16)))).unwrap();
+        assert_eq!(json, r#""10000000000000000""#);
+    }
+}
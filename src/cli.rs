@@ -0,0 +1,581 @@
+use crate::circles::PathfinderApi;
+use crate::config::{BundlerLayer, HttpLayer, Layer, RetryLayer, SafeLayer, SignerLayer, TxLayer};
+use crate::export::ExportFormat;
+use crate::network::Network;
+use crate::pathfinder::Backend;
+use crate::redeem::RedeemableSubscription;
+use crate::state::StateBackend;
+use alloy::primitives::{Address, B256, U256};
+use clap::{ArgAction, Args, Parser, Subcommand, ValueEnum};
+use std::path::PathBuf;
+use std::time::Duration;
+
+#[derive(Debug, Parser)]
+#[command(
+    version,
+    about = "Redeem Circles subscriptions via the SubscriptionModule"
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    #[command(flatten)]
+    pub config: ConfigArgs,
+
+    /// Build and estimate every redemption without sending any transaction.
+    #[arg(long, global = true)]
+    pub dry_run: bool,
+
+    /// Show each redemption with its gas estimate and ask for confirmation before sending.
+    #[arg(long, global = true, conflicts_with = "dry_run")]
+    pub interactive: bool,
+
+    /// Increase log verbosity (-v: debug, -vv: trace).
+    #[arg(short, long, global = true, action = ArgAction::Count, conflicts_with = "quiet")]
+    pub verbose: u8,
+
+    /// Decrease log verbosity (-q: warnings, -qq: errors only).
+    #[arg(short, long, global = true, action = ArgAction::Count)]
+    pub quiet: u8,
+
+    /// Format for per-subscription results.
+    #[arg(long, global = true, value_enum, default_value_t = OutputFormat::Text)]
+    pub output: OutputFormat,
+}
+
+/// Configuration file selection and per-invocation overrides.
+#[derive(Debug, Clone, Default, Args)]
+pub struct ConfigArgs {
+    /// Config file to load (defaults to `redeem.toml` if present).
+    #[arg(long, env = "CONFIG", global = true)]
+    pub config: Option<PathBuf>,
+    /// Named `[profile.<name>]` section of the config file to apply.
+    #[arg(long, env = "PROFILE", global = true)]
+    pub profile: Option<String>,
+    /// Network whose RPC, chain ID, module and pathfinder defaults to use.
+    #[arg(long, env = "NETWORK", global = true, value_enum)]
+    pub network: Option<Network>,
+    /// SubIndexer redeemable endpoint.
+    #[arg(long, env = "API_URL", global = true)]
+    pub api_url: Option<String>,
+    /// Indexer health endpoint checked before every run.
+    #[arg(long, env = "API_HEALTH_URL", global = true)]
+    pub api_health_url: Option<String>,
+    /// Minimum time between two indexer requests (e.g. `500ms`); faster requests are delayed.
+    #[arg(long, env = "API_MIN_INTERVAL", global = true, value_parser = humantime::parse_duration)]
+    pub api_min_interval: Option<Duration>,
+    /// Fetch from the indexer in pages of this many subscriptions.
+    #[arg(long, env = "API_PAGE_SIZE", global = true)]
+    pub api_page_size: Option<usize>,
+    /// Attempts per indexer request before giving up.
+    #[arg(long, env = "FETCH_ATTEMPTS", global = true)]
+    pub fetch_attempts: Option<u32>,
+    /// Delay before the first indexer retry, doubled for each further one (e.g. `2s`).
+    #[arg(long, env = "FETCH_BACKOFF", global = true, value_parser = humantime::parse_duration)]
+    pub fetch_backoff: Option<Duration>,
+    /// Proxy for indexer and RPC traffic (`http://`, `https://`, `socks5://` or `socks5h://`).
+    #[arg(long, env = "PROXY", global = true)]
+    pub proxy: Option<String>,
+    /// Gnosis Chain RPC used to send transactions.
+    #[arg(long, env = "RPC_URL", global = true)]
+    pub rpc_url: Option<String>,
+    /// Further RPCs failed over to when `--rpc-url` errors or times out, comma-separated.
+    #[arg(
+        long = "rpc-fallback",
+        env = "RPC_FALLBACKS",
+        value_delimiter = ',',
+        global = true
+    )]
+    pub rpc_fallbacks: Vec<String>,
+    /// How long a failed RPC is avoided (e.g. `30s`), doubled on every further failure.
+    #[arg(long, env = "RPC_COOLDOWN", global = true, value_parser = humantime::parse_duration)]
+    pub rpc_cooldown: Option<Duration>,
+    /// Private RPC that signed transactions are sent to; reads still use `--rpc-url`.
+    #[arg(long, env = "SEND_RPC_URL", global = true)]
+    pub send_rpc_url: Option<String>,
+    /// WebSocket RPC that pending transactions are watched through instead of polling.
+    #[arg(long, env = "WS_URL", global = true)]
+    pub ws_url: Option<String>,
+    /// Circles RPC used for pathfinding.
+    #[arg(long, env = "CIRCLES_RPC_URL", global = true)]
+    pub circles_rpc_url: Option<String>,
+    /// Block explorer that transaction links point to.
+    #[arg(long, env = "EXPLORER_URL", global = true)]
+    pub explorer_url: Option<String>,
+    /// Engine that searches transfer paths.
+    #[arg(long, env = "PATHFINDER", global = true, value_enum)]
+    pub pathfinder: Option<Backend>,
+    /// Pathfinder method of the Circles RPC: `v2` for `circlesV2_findPath`, `v1` for the legacy
+    /// `compute_transfer`.
+    #[arg(long, env = "PATHFINDER_API", global = true, value_enum)]
+    pub pathfinder_api: Option<PathfinderApi>,
+    /// Total time allowed for each execution RPC request (e.g. `10s`).
+    #[arg(long, env = "RPC_TIMEOUT", global = true, value_parser = humantime::parse_duration)]
+    pub rpc_timeout: Option<Duration>,
+    /// Time allowed for a path search on the Circles RPC (e.g. `30s`).
+    #[arg(long, env = "CIRCLES_RPC_TIMEOUT", global = true, value_parser = humantime::parse_duration)]
+    pub circles_rpc_timeout: Option<Duration>,
+    /// Most requests per second sent to the Circles RPC (e.g. `5`); faster ones are delayed.
+    #[arg(long, env = "CIRCLES_RPC_RATE_LIMIT", global = true)]
+    pub circles_rpc_rate_limit: Option<f64>,
+    /// Time allowed for one pathfinder search, whatever the backend (e.g. `30s`).
+    #[arg(long, env = "PATHFINDER_TIMEOUT", global = true, value_parser = humantime::parse_duration)]
+    pub pathfinder_timeout: Option<Duration>,
+    /// Cassette file the `record` and `replay` pathfinders write and read.
+    #[arg(long, env = "PATHFINDER_CASSETTE", global = true)]
+    pub pathfinder_cassette: Option<PathBuf>,
+    /// Pathfinder failures in a row after which searches are suspended; `0` never does.
+    #[arg(long, env = "PATHFINDER_MAX_FAILURES", global = true)]
+    pub pathfinder_max_failures: Option<u32>,
+    /// How long searches are suspended after too many pathfinder failures (e.g. `60s`).
+    #[arg(long, env = "PATHFINDER_COOLDOWN", global = true, value_parser = humantime::parse_duration)]
+    pub pathfinder_cooldown: Option<Duration>,
+    /// Reject paths with more transfers than this.
+    #[arg(long, env = "MAX_PATH_TRANSFERS", global = true)]
+    pub max_path_transfers: Option<usize>,
+    /// Reject paths moving the tokens of more distinct owners than this.
+    #[arg(long, env = "MAX_PATH_TOKEN_OWNERS", global = true)]
+    pub max_path_token_owners: Option<usize>,
+    /// Tokens no path may spend, comma-separated.
+    #[arg(
+        long,
+        env = "EXCLUDE_FROM_TOKENS",
+        value_delimiter = ',',
+        global = true
+    )]
+    pub exclude_from_tokens: Vec<Address>,
+    /// Tokens no path may deliver, comma-separated.
+    #[arg(long, env = "EXCLUDE_TO_TOKENS", value_delimiter = ',', global = true)]
+    pub exclude_to_tokens: Vec<Address>,
+    /// Only redeem subscriptions on this SubscriptionModule.
+    #[arg(long, env = "MODULE_ADDRESS", global = true)]
+    pub module_address: Option<Address>,
+    /// Further SubscriptionModules to redeem on, comma-separated.
+    #[arg(long = "module", env = "MODULES", value_delimiter = ',', global = true)]
+    pub modules: Vec<Address>,
+    /// Wall-clock budget per run (e.g. `10m`); remaining subscriptions are skipped.
+    #[arg(long, env = "MAX_RUNTIME", global = true, value_parser = humantime::parse_duration)]
+    pub max_runtime: Option<Duration>,
+    /// Redemptions processed at the same time.
+    #[arg(long, env = "CONCURRENCY", global = true)]
+    pub concurrency: Option<usize>,
+    /// Redemptions combined into one Multicall3 transaction.
+    #[arg(long, env = "BATCH_SIZE", global = true)]
+    pub batch_size: Option<usize>,
+    /// Blocks to wait for after sending before a redemption counts as done; 0 does not wait.
+    #[arg(long, env = "CONFIRMATIONS", global = true)]
+    pub confirmations: Option<u64>,
+    /// How long a sent redemption may stay pending before it is rebroadcast (e.g. `2m`).
+    #[arg(long, env = "TX_TIMEOUT", global = true, value_parser = humantime::parse_duration)]
+    pub tx_timeout: Option<Duration>,
+    /// Refuse redemptions whose buffered gas estimate exceeds this limit.
+    #[arg(long, env = "GAS_CAP", global = true)]
+    pub gas_cap: Option<u64>,
+    /// Derivation path of the executor accounts when signing with `MNEMONIC`.
+    #[arg(long, env = "HD_PATH", global = true)]
+    pub hd_path: Option<String>,
+    /// Which account derived from `MNEMONIC` is the executor.
+    #[arg(long, env = "ACCOUNT_INDEX", global = true)]
+    pub account_index: Option<u32>,
+    /// Encrypted JSON keystore holding the executor key.
+    #[arg(long, env = "KEYSTORE", global = true)]
+    pub keystore: Option<PathBuf>,
+    /// File whose first line is the keystore password; prompted for when unset.
+    #[arg(long, env = "KEYSTORE_PASSWORD_FILE", global = true)]
+    pub keystore_password_file: Option<PathBuf>,
+    /// Remote signing service (such as Web3Signer) that signs the executor's transactions.
+    #[arg(long, env = "REMOTE_SIGNER_URL", global = true)]
+    pub remote_signer_url: Option<String>,
+    /// The executor address whose key the remote signer holds.
+    #[arg(long, env = "REMOTE_SIGNER_ADDRESS", global = true)]
+    pub remote_signer_address: Option<Address>,
+    /// Send redemptions as ERC-4337 user operations through this bundler.
+    #[arg(long, env = "BUNDLER_URL", global = true)]
+    pub bundler_url: Option<String>,
+    /// Smart account, owned by the executor key, that sends user operations.
+    #[arg(long, env = "SMART_ACCOUNT", global = true)]
+    pub smart_account: Option<Address>,
+    /// Redeem through this Safe, owned by the executor key.
+    #[arg(long, env = "SAFE_ADDRESS", global = true)]
+    pub safe: Option<Address>,
+    /// Safe Transaction Service that redemptions are proposed to when the Safe needs several
+    /// confirmations.
+    #[arg(long, env = "SAFE_SERVICE_URL", global = true)]
+    pub safe_service_url: Option<String>,
+    /// Halt all redemptions (kill switch).
+    #[arg(long, env = "PAUSED", global = true)]
+    pub paused: bool,
+    /// Halt all redemptions while this file exists (kill switch).
+    #[arg(long, env = "PAUSE_FILE", global = true)]
+    pub pause_file: Option<PathBuf>,
+    /// Warn when the executor's balance drops below this many xDAI (e.g. `0.5`).
+    #[arg(long, env = "MIN_BALANCE", global = true)]
+    pub min_balance: Option<String>,
+    /// Halt all redemptions while the executor's balance is below `--min-balance`.
+    #[arg(long, env = "PAUSE_ON_LOW_BALANCE", global = true)]
+    pub pause_on_low_balance: bool,
+    /// Remember the last fetch here and only fetch subscriptions that became redeemable since.
+    #[arg(long, env = "STATE_FILE", global = true)]
+    pub state_file: Option<PathBuf>,
+    /// How the state file is stored: `json`, or `sqlite` to also keep a ledger of attempts.
+    #[arg(long, env = "STATE_BACKEND", global = true, value_enum)]
+    pub state_backend: Option<StateBackend>,
+    /// Write indexer fetch metrics to this file in the Prometheus text format.
+    #[arg(long, env = "METRICS_FILE", global = true)]
+    pub metrics_file: Option<PathBuf>,
+    /// Read subscriptions from this JSON (indexer format) or CSV file instead of the indexer.
+    #[arg(long, visible_alias = "from-file", env = "SOURCE_FILE", global = true)]
+    pub source_file: Option<PathBuf>,
+    /// Reuse fetched subscriptions for this long (e.g. `30s`) instead of re-querying the source.
+    #[arg(long, env = "CACHE_TTL", global = true, value_parser = humantime::parse_duration)]
+    pub cache_ttl: Option<Duration>,
+    /// Reuse found paths for identical searches for this long (e.g. `2m`).
+    #[arg(long, env = "PATH_CACHE_TTL", global = true, value_parser = humantime::parse_duration)]
+    pub path_cache_ttl: Option<Duration>,
+    /// Search a path again if it is older than this (e.g. `30s`) when the redemption is sent.
+    #[arg(long, env = "MAX_PATH_AGE", global = true, value_parser = humantime::parse_duration)]
+    pub max_path_age: Option<Duration>,
+    /// Indexer server-sent events endpoint; the daemon redeems announced subscriptions
+    /// immediately instead of waiting for the next poll.
+    #[arg(long, env = "STREAM_URL", global = true)]
+    pub stream_url: Option<String>,
+    /// Redeem without first checking each subscription against the chain.
+    #[arg(long, env = "NO_VERIFY", global = true)]
+    pub no_verify: bool,
+    /// Redeem trusted subscriptions without a path when pathfinding fails, if the module allows.
+    #[arg(long, env = "UNTRUSTED_FALLBACK", global = true)]
+    pub untrusted_fallback: bool,
+    /// Skip pathfinding when the recipient accepts none of the subscriber's tokens.
+    #[arg(long, env = "TRUST_PREFLIGHT", global = true)]
+    pub trust_preflight: bool,
+    /// Redeem the periods the available flow covers when it falls short of the amount due.
+    #[arg(long, env = "PARTIAL_REDEMPTION", global = true)]
+    pub partial_redemption: bool,
+    /// Smallest share of the due periods a partial redemption may cover (e.g. `0.5`).
+    #[arg(long, env = "PARTIAL_MIN_FRACTION", global = true)]
+    pub partial_min_fraction: Option<f64>,
+    /// Search again for this share of the flow found when it falls short (e.g. `0.95`).
+    #[arg(long, env = "REDUCE_TARGET", global = true)]
+    pub reduce_target: Option<f64>,
+    /// Query this subgraph with GraphQL instead of the indexer.
+    #[arg(long, env = "SUBGRAPH_URL", global = true)]
+    pub subgraph_url: Option<String>,
+    /// Discover subscriptions from the modules' event logs, scanned from this block on,
+    /// instead of the indexer.
+    #[arg(long, env = "LOGS_FROM_BLOCK", global = true)]
+    pub logs_from_block: Option<u64>,
+    /// Most blocks covered by one `eth_getLogs` request when scanning logs.
+    #[arg(long, env = "LOGS_BLOCK_RANGE", global = true)]
+    pub logs_block_range: Option<u64>,
+}
+
+impl ConfigArgs {
+    /// The environment and flag layer of the configuration.
+    pub fn layer(&self) -> Layer {
+        Layer {
+            network: self.network,
+            api_url: self.api_url.clone(),
+            api_page_size: self.api_page_size,
+            api_health_url: self.api_health_url.clone(),
+            api_min_interval: self.api_min_interval,
+            rpc_url: self.rpc_url.clone(),
+            rpc_fallbacks: (!self.rpc_fallbacks.is_empty()).then(|| self.rpc_fallbacks.clone()),
+            rpc_cooldown: self.rpc_cooldown,
+            send_rpc_url: self.send_rpc_url.clone(),
+            ws_url: self.ws_url.clone(),
+            circles_rpc_url: self.circles_rpc_url.clone(),
+            explorer_url: self.explorer_url.clone(),
+            pathfinder: self.pathfinder,
+            pathfinder_api: self.pathfinder_api,
+            rpc_timeout: self.rpc_timeout,
+            circles_rpc_timeout: self.circles_rpc_timeout,
+            circles_rpc_rate_limit: self.circles_rpc_rate_limit,
+            pathfinder_timeout: self.pathfinder_timeout,
+            pathfinder_cassette: self.pathfinder_cassette.clone(),
+            pathfinder_max_failures: self.pathfinder_max_failures,
+            pathfinder_cooldown: self.pathfinder_cooldown,
+            max_path_transfers: self.max_path_transfers,
+            max_path_token_owners: self.max_path_token_owners,
+            exclude_from_tokens: (!self.exclude_from_tokens.is_empty())
+                .then(|| self.exclude_from_tokens.clone()),
+            exclude_to_tokens: (!self.exclude_to_tokens.is_empty())
+                .then(|| self.exclude_to_tokens.clone()),
+            module_address: self.module_address,
+            modules: (!self.modules.is_empty()).then(|| self.modules.clone()),
+            max_runtime: self.max_runtime,
+            concurrency: self.concurrency,
+            batch_size: self.batch_size,
+            confirmations: self.confirmations,
+            paused: self.paused.then_some(true),
+            pause_file: self.pause_file.clone(),
+            min_balance: self.min_balance.clone(),
+            pause_on_low_balance: self.pause_on_low_balance.then_some(true),
+            state_file: self.state_file.clone(),
+            state_backend: self.state_backend,
+            metrics_file: self.metrics_file.clone(),
+            source_file: self.source_file.clone(),
+            cache_ttl: self.cache_ttl,
+            path_cache_ttl: self.path_cache_ttl,
+            max_path_age: self.max_path_age,
+            stream_url: self.stream_url.clone(),
+            verify: self.no_verify.then_some(false),
+            untrusted_fallback: self.untrusted_fallback.then_some(true),
+            trust_preflight: self.trust_preflight.then_some(true),
+            partial_redemption: self.partial_redemption.then_some(true),
+            partial_min_fraction: self.partial_min_fraction,
+            reduce_target: self.reduce_target,
+            subgraph_url: self.subgraph_url.clone(),
+            logs_from_block: self.logs_from_block,
+            logs_block_range: self.logs_block_range,
+            retry: RetryLayer {
+                attempts: self.fetch_attempts,
+                backoff: self.fetch_backoff,
+                jitter: None,
+            },
+            http: HttpLayer {
+                proxy: self.proxy.clone(),
+                ..Default::default()
+            },
+            tx: TxLayer {
+                timeout: self.tx_timeout,
+                gas_cap: self.gas_cap,
+                ..Default::default()
+            },
+            signer: SignerLayer {
+                hd_path: self.hd_path.clone(),
+                account_index: self.account_index,
+                keystore: self.keystore.clone(),
+                password_file: self.keystore_password_file.clone(),
+                remote_url: self.remote_signer_url.clone(),
+                remote_address: self.remote_signer_address,
+                ..Default::default()
+            },
+            bundler: BundlerLayer {
+                url: self.bundler_url.clone(),
+                account: self.smart_account,
+                ..Default::default()
+            },
+            safe: SafeLayer {
+                address: self.safe,
+                service_url: self.safe_service_url.clone(),
+            },
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum OutputFormat {
+    /// Human-readable log lines.
+    Text,
+    /// One JSON object per subscription on stdout.
+    Json,
+}
+
+impl Cli {
+    /// Log filter for the requested verbosity; dependencies stay at `warn` unless `-vvv`.
+    pub fn log_filter(&self) -> String {
+        let level = match i16::from(self.verbose) - i16::from(self.quiet) {
+            ..=-2 => "error",
+            -1 => "warn",
+            0 => "info",
+            1 => "debug",
+            _ => "trace",
+        };
+        let dependencies = if self.verbose >= 3 { "debug" } else { "warn" };
+        format!("{dependencies},redeem_rs={level}")
+    }
+
+    /// The subcommand to execute, defaulting to a full `run` when none is given.
+    pub fn command(&self) -> Command {
+        self.command
+            .clone()
+            .unwrap_or(Command::Run(RunArgs::default()))
+    }
+}
+
+#[derive(Debug, Clone, Subcommand)]
+pub enum Command {
+    /// Fetch all redeemable subscriptions and redeem them.
+    Run(RunArgs),
+    /// List redeemable subscriptions without redeeming.
+    List(FilterArgs),
+    /// Run every pending redemption through `eth_call` and report which would revert.
+    Simulate(FilterArgs),
+    /// Redeem a single subscription, e.g. to retry after a failed run.
+    Redeem(RedeemArgs),
+    /// Write unsigned redemption transactions to files for an offline signer.
+    Export(ExportArgs),
+    /// List the executor accounts derived from `MNEMONIC` and their balances.
+    Accounts {
+        /// Number of accounts to list.
+        #[arg(long, default_value_t = 5)]
+        count: u32,
+    },
+    /// Inspect the configuration.
+    #[command(subcommand)]
+    Config(ConfigCommand),
+    /// Query the pathfinder for a transfer path and print its transfers, flow matrix and
+    /// calldata size.
+    Path {
+        /// Sender address.
+        from: Address,
+        /// Receiver address.
+        to: Address,
+        /// Amount in atto-circles.
+        amount: U256,
+        /// Also write the transfers as a Graphviz DOT graph to this file.
+        #[arg(long)]
+        dot: Option<PathBuf>,
+    },
+}
+
+#[derive(Debug, Clone, Subcommand)]
+pub enum ConfigCommand {
+    /// Validate the configuration, RPC endpoints, signer and indexer.
+    Check,
+}
+
+#[derive(Debug, Clone, Default, Args)]
+pub struct RunArgs {
+    /// Keep running and poll the indexer at this interval (e.g. `60s`, `5m`).
+    #[arg(long, env = "INTERVAL", value_parser = parse_interval)]
+    pub interval: Option<Duration>,
+    /// Show a live terminal dashboard instead of log output.
+    #[arg(long)]
+    pub tui: bool,
+    #[command(flatten)]
+    pub filter: FilterArgs,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct ExportArgs {
+    /// Output directory (`files`) or file (`safe`).
+    #[arg(long)]
+    pub out: PathBuf,
+    /// Output format.
+    #[arg(long, value_enum, default_value_t = ExportFormat::Files)]
+    pub format: ExportFormat,
+    /// Address (or Safe) that will send the transactions; used for gas estimation.
+    /// Defaults to the configured signer.
+    #[arg(long)]
+    pub from: Option<Address>,
+    #[command(flatten)]
+    pub filter: FilterArgs,
+}
+
+/// Narrows the fetched subscriptions down to the ones to act on.
+#[derive(Debug, Clone, Default, Args)]
+pub struct FilterArgs {
+    /// Only subscriptions paying this recipient (repeatable).
+    #[arg(long)]
+    pub recipient: Vec<Address>,
+    /// Only subscriptions paid by this subscriber (repeatable).
+    #[arg(long)]
+    pub subscriber: Vec<Address>,
+    /// Only subscriptions whose total due (amount × periods) is at least this.
+    #[arg(long)]
+    pub min_amount: Option<U256>,
+    /// Process at most this many subscriptions.
+    #[arg(long)]
+    pub max_count: Option<usize>,
+}
+
+#[derive(Debug, Clone, Args)]
+#[group(required = true, multiple = false)]
+pub struct RedeemArgs {
+    /// Subscription ID (bytes32) to look up in the indexer.
+    #[arg(long)]
+    pub id: Option<B256>,
+    /// Full subscription as indexer JSON, bypassing the indexer.
+    #[arg(long, value_parser = parse_subscription)]
+    pub json: Option<RedeemableSubscription>,
+}
+
+fn parse_interval(s: &str) -> Result<Duration, String> {
+    match humantime::parse_duration(s).map_err(|e| e.to_string())? {
+        Duration::ZERO => Err("interval must be positive".into()),
+        interval => Ok(interval),
+    }
+}
+
+fn parse_subscription(s: &str) -> Result<RedeemableSubscription, serde_json::Error> {
+    serde_json::from_str(s)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_command_is_run() {
+        let cli = Cli::parse_from(["redeem-rs"]);
+        assert!(matches!(
+            cli.command(),
+            Command::Run(RunArgs {
+                interval: None,
+                tui: false,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_parse_interval() {
+        let cli = Cli::parse_from(["redeem-rs", "run", "--interval", "90s"]);
+        match cli.command() {
+            Command::Run(args) => assert_eq!(args.interval, Some(Duration::from_secs(90))),
+            other => panic!("unexpected command: {other:?}"),
+        }
+        assert!(Cli::try_parse_from(["redeem-rs", "run", "--interval", "0s"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_path() {
+        let cli = Cli::parse_from([
+            "redeem-rs",
+            "path",
+            "0xcf6dc192dc292d5f2789da2db02d6dd4f41f4214",
+            "0x6b69683c8897e3d18e74b1ba117b49f80423da5d",
+            "10000000000000000",
+        ]);
+        match cli.command() {
+            Command::Path { amount, .. } => {
+                assert_eq!(amount, U256::from(10_000_000_000_000_000u64))
+            }
+            other => panic!("unexpected command: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_redeem_requires_id_or_json() {
+        assert!(Cli::try_parse_from(["redeem-rs", "redeem"]).is_err());
+        let cli = Cli::try_parse_from([
+            "redeem-rs",
+            "redeem",
+            "--id",
+            "0x50ede65601819b8885dc3dbf4676204fcd318c26b8281d82af20f69d55b4ca75",
+        ])
+        .unwrap();
+        assert!(matches!(
+            cli.command(),
+            Command::Redeem(RedeemArgs {
+                id: Some(_),
+                json: None
+            })
+        ));
+    }
+
+    #[test]
+    fn test_log_filter() {
+        let filter = |args: &[&str]| {
+            Cli::parse_from(std::iter::once("redeem-rs").chain(args.iter().copied())).log_filter()
+        };
+        assert_eq!(filter(&[]), "warn,redeem_rs=info");
+        assert_eq!(filter(&["-v"]), "warn,redeem_rs=debug");
+        assert_eq!(filter(&["-vv"]), "warn,redeem_rs=trace");
+        assert_eq!(filter(&["-vvv"]), "debug,redeem_rs=trace");
+        assert_eq!(filter(&["-qq"]), "warn,redeem_rs=error");
+        assert!(Cli::try_parse_from(["redeem-rs", "-v", "-q"]).is_err());
+    }
+}
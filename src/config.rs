@@ -0,0 +1,1907 @@
+use alloy::{
+    network::EthereumWallet,
+    primitives::{Address, U256, utils::parse_ether},
+    rpc::client::RpcClient,
+    signers::local::PrivateKeySigner,
+};
+use anyhow::{Context, Result, anyhow};
+use reqwest::{
+    Client, Url,
+    header::{AUTHORIZATION, HeaderMap, HeaderName, HeaderValue},
+};
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    env,
+    path::{Path, PathBuf},
+    sync::{
+        Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, SystemTime},
+};
+
+use crate::bundler::{Bundler, ENTRY_POINT_V07};
+use crate::circles::{CirclesClient, PathCache, PathfinderApi};
+use crate::cli::ConfigArgs;
+use crate::failover::Failover;
+use crate::fetch::{ConditionalCache, RateLimiter};
+use crate::metrics::{FetchMetrics, PathfinderMetrics};
+use crate::mnemonic::{DEFAULT_HD_PATH, Mnemonic};
+use crate::network::Network;
+use crate::nonce::Nonces;
+use crate::pathfinder::{Backend, CircuitBreaker, Pathfinder};
+use crate::redeem::{RedeemError, RedeemableSubscription};
+use crate::relay::Gelato;
+use crate::remote::RemoteSigner;
+use crate::safe::Safe;
+use crate::state::StateBackend;
+use crate::ws::Ws;
+
+const DEFAULT_CONFIG_FILE: &str = "redeem.toml";
+const DEFAULT_API_URL: &str = "http://localhost:3030/redeemable";
+const DEFAULT_GELATO_URL: &str = "https://api.gelato.digital";
+const DEFAULT_DEDUPE_WINDOW: Duration = Duration::from_secs(3600);
+const DEFAULT_LOGS_BLOCK_RANGE: u64 = 10_000;
+const DEFAULT_SUBGRAPH_QUERY: &str = "{ redeemableSubscriptions { contract_address id recipient subscriber amount periods category } }";
+
+/// One source of configuration; unset fields fall through to the layer below.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Layer {
+    pub network: Option<Network>,
+    pub chain_id: Option<u64>,
+    pub api_url: Option<String>,
+    /// Bearer token sent to the indexer.
+    pub api_token: Option<String>,
+    /// Extra headers sent to the indexer.
+    pub api_headers: Option<HashMap<String, String>>,
+    /// Page size for `limit`/`offset` pagination of the indexer.
+    pub api_page_size: Option<usize>,
+    /// Indexer health endpoint checked before every run.
+    pub api_health_url: Option<String>,
+    /// Minimum time between two requests to the indexer.
+    #[serde(default, with = "humantime_serde")]
+    pub api_min_interval: Option<Duration>,
+    /// Further indexer replicas queried alongside `api_url`.
+    pub api_replicas: Option<Vec<String>>,
+    /// Number of indexers that must list a subscription for it to be redeemed.
+    pub api_quorum: Option<usize>,
+    pub rpc_url: Option<String>,
+    /// Further execution RPCs failed over to when `rpc_url` errors or times out.
+    pub rpc_fallbacks: Option<Vec<String>>,
+    /// How long a failed execution RPC is avoided, doubled on every further failure.
+    #[serde(default, with = "humantime_serde")]
+    pub rpc_cooldown: Option<Duration>,
+    /// Private endpoint that signed transactions are sent to instead of `rpc_url`.
+    pub send_rpc_url: Option<String>,
+    /// WebSocket RPC that pending transactions are watched through instead of polling.
+    pub ws_url: Option<String>,
+    pub circles_rpc_url: Option<String>,
+    /// Block explorer that transaction links point to.
+    pub explorer_url: Option<String>,
+    /// Engine that searches transfer paths.
+    pub pathfinder: Option<Backend>,
+    /// JSON-RPC method that `circles_rpc_url` is asked for paths with.
+    pub pathfinder_api: Option<PathfinderApi>,
+    /// Total time allowed for each request to `rpc_url` or `send_rpc_url`.
+    #[serde(default, with = "humantime_serde")]
+    pub rpc_timeout: Option<Duration>,
+    /// Time allowed for a path search on `circles_rpc_url`; `http.timeout` if unset.
+    #[serde(default, with = "humantime_serde")]
+    pub circles_rpc_timeout: Option<Duration>,
+    /// Most requests per second sent to `circles_rpc_url`; unlimited if unset.
+    pub circles_rpc_rate_limit: Option<f64>,
+    /// Time allowed for one search by the pathfinder, whatever its backend;
+    /// `circles_rpc_timeout` if unset.
+    #[serde(default, with = "humantime_serde")]
+    pub pathfinder_timeout: Option<Duration>,
+    /// JSON file of pathfinder responses for the `record` and `replay` pathfinders.
+    pub pathfinder_cassette: Option<PathBuf>,
+    /// Pathfinder failures in a row after which searches are suspended; 0 never suspends them.
+    pub pathfinder_max_failures: Option<u32>,
+    /// How long searches are suspended after `pathfinder_max_failures`.
+    #[serde(default, with = "humantime_serde")]
+    pub pathfinder_cooldown: Option<Duration>,
+    /// Most transfers a path may have.
+    pub max_path_transfers: Option<usize>,
+    /// Most distinct token owners a path may move tokens of.
+    pub max_path_token_owners: Option<usize>,
+    /// Tokens no path may spend, on top of any `path_rules` exclusions.
+    pub exclude_from_tokens: Option<Vec<Address>>,
+    /// Tokens no path may deliver, on top of any `path_rules` exclusions.
+    pub exclude_to_tokens: Option<Vec<Address>>,
+    pub module_address: Option<Address>,
+    /// Further SubscriptionModule instances redeemed alongside `module_address`.
+    pub modules: Option<Vec<Address>>,
+    #[serde(default, with = "humantime_serde")]
+    pub interval: Option<Duration>,
+    #[serde(default, with = "humantime_serde")]
+    pub max_runtime: Option<Duration>,
+    /// Redemptions processed at the same time.
+    pub concurrency: Option<usize>,
+    /// Redemptions combined into one Multicall3 transaction.
+    pub batch_size: Option<usize>,
+    /// Blocks a redemption must be buried under before it counts as redeemed.
+    pub confirmations: Option<u64>,
+    pub paused: Option<bool>,
+    pub pause_file: Option<PathBuf>,
+    /// Balance, in xDAI, below which the executor is reported as running low.
+    pub min_balance: Option<String>,
+    pub pause_on_low_balance: Option<bool>,
+    pub state_file: Option<PathBuf>,
+    pub state_backend: Option<StateBackend>,
+    /// Prometheus text file the fetch metrics are written to.
+    pub metrics_file: Option<PathBuf>,
+    #[serde(default, with = "humantime_serde")]
+    pub dedupe_window: Option<Duration>,
+    pub source_file: Option<PathBuf>,
+    #[serde(default, with = "humantime_serde")]
+    pub cache_ttl: Option<Duration>,
+    /// How long found paths are reused for identical searches.
+    #[serde(default, with = "humantime_serde")]
+    pub path_cache_ttl: Option<Duration>,
+    /// How long after its search a path may still be submitted.
+    #[serde(default, with = "humantime_serde")]
+    pub max_path_age: Option<Duration>,
+    pub stream_url: Option<String>,
+    pub verify: Option<bool>,
+    /// Redeem trusted subscriptions as untrusted when no path covers the amount.
+    pub untrusted_fallback: Option<bool>,
+    /// Fail trusted subscriptions fast when the recipient accepts none of the subscriber's tokens.
+    pub trust_preflight: Option<bool>,
+    /// Redeem the periods the available flow covers when it falls short of the amount due.
+    pub partial_redemption: Option<bool>,
+    /// Smallest share of the due periods a partial redemption may cover.
+    pub partial_min_fraction: Option<f64>,
+    /// Share of a short flow to search again for.
+    pub reduce_target: Option<f64>,
+    pub subgraph_url: Option<String>,
+    pub subgraph_query: Option<String>,
+    /// Block to start scanning module event logs at, instead of asking the indexer.
+    pub logs_from_block: Option<u64>,
+    /// Most blocks covered by one `eth_getLogs` request.
+    pub logs_block_range: Option<u64>,
+    pub allow: Option<AddressList>,
+    pub deny: Option<AddressList>,
+    /// Pathfinder settings for particular subscribers or recipients.
+    pub path_rules: Option<Vec<PathRule>>,
+    #[serde(default)]
+    pub retry: RetryLayer,
+    #[serde(default)]
+    pub redeem_retry: RedeemRetryLayer,
+    #[serde(default)]
+    pub http: HttpLayer,
+    #[serde(default)]
+    pub tx: TxLayer,
+    #[serde(default)]
+    pub signer: SignerLayer,
+    #[serde(default)]
+    pub gelato: GelatoLayer,
+    #[serde(default)]
+    pub bundler: BundlerLayer,
+    #[serde(default)]
+    pub safe: SafeLayer,
+}
+
+/// Subscriber and recipient addresses for an allow or deny list.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct AddressList {
+    #[serde(default)]
+    pub subscribers: Vec<Address>,
+    #[serde(default)]
+    pub recipients: Vec<Address>,
+}
+
+/// Pathfinder settings for the subscriptions of one subscriber, one recipient, or both.
+/// Unset fields keep the pathfinder's defaults.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct PathRule {
+    pub subscriber: Option<Address>,
+    pub recipient: Option<Address>,
+    pub use_wrapped_balances: Option<bool>,
+    /// Tokens the subscriber may pay with.
+    pub from_tokens: Option<Vec<Address>>,
+    /// Tokens the recipient accepts.
+    pub to_tokens: Option<Vec<Address>>,
+    pub exclude_from_tokens: Option<Vec<Address>>,
+    pub exclude_to_tokens: Option<Vec<Address>>,
+    /// The Circles group whose token pays the subscriptions; the path ends in it unless
+    /// `to_tokens` says otherwise.
+    pub group: Option<Address>,
+}
+
+impl PathRule {
+    /// Whether the rule applies to a path from `subscriber` to `recipient`.
+    pub fn matches(&self, subscriber: Address, recipient: Address) -> bool {
+        self.subscriber.is_none_or(|address| address == subscriber)
+            && self.recipient.is_none_or(|address| address == recipient)
+    }
+
+    /// `self` with every field it leaves unset taken from `other`.
+    fn or(self, other: &PathRule) -> PathRule {
+        PathRule {
+            subscriber: self.subscriber.or(other.subscriber),
+            recipient: self.recipient.or(other.recipient),
+            use_wrapped_balances: self.use_wrapped_balances.or(other.use_wrapped_balances),
+            from_tokens: self.from_tokens.or_else(|| other.from_tokens.clone()),
+            to_tokens: self.to_tokens.or_else(|| other.to_tokens.clone()),
+            exclude_from_tokens: self
+                .exclude_from_tokens
+                .or_else(|| other.exclude_from_tokens.clone()),
+            exclude_to_tokens: self
+                .exclude_to_tokens
+                .or_else(|| other.exclude_to_tokens.clone()),
+            group: self.group.or(other.group),
+        }
+    }
+}
+
+/// Retry policy for indexer requests.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct RetryLayer {
+    pub attempts: Option<u32>,
+    #[serde(default, with = "humantime_serde")]
+    pub backoff: Option<Duration>,
+    pub jitter: Option<bool>,
+}
+
+/// How often a failed redemption is retried, per class of failure.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct RedeemRetryLayer {
+    pub pathfinding: Option<u32>,
+    pub stale_path: Option<u32>,
+    pub simulation: Option<u32>,
+    pub submission: Option<u32>,
+    pub timeout: Option<u32>,
+    pub reverted: Option<u32>,
+    pub confirmation: Option<u32>,
+    #[serde(default, with = "humantime_serde")]
+    pub backoff: Option<Duration>,
+}
+
+/// Timeouts and limits for outbound HTTP.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct HttpLayer {
+    #[serde(default, with = "humantime_serde")]
+    pub connect_timeout: Option<Duration>,
+    #[serde(default, with = "humantime_serde")]
+    pub timeout: Option<Duration>,
+    #[serde(default, with = "humantime_serde")]
+    pub read_timeout: Option<Duration>,
+    pub max_response_size: Option<usize>,
+    pub max_redirects: Option<usize>,
+    /// Proxy for all indexer and RPC traffic, e.g. `http://proxy:3128` or `socks5h://127.0.0.1:9050`.
+    pub proxy: Option<String>,
+}
+
+/// How sent redemptions are watched until they are mined.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct TxLayer {
+    #[serde(default, with = "humantime_serde")]
+    pub timeout: Option<Duration>,
+    pub rebroadcasts: Option<u32>,
+    pub max_bumps: Option<u32>,
+    /// Highest max fee per gas a replacement may offer, in gwei.
+    pub fee_ceiling: Option<u64>,
+    /// Factor applied to gas estimates to leave headroom.
+    pub gas_multiplier: Option<f64>,
+    /// Highest gas limit a single redemption may use.
+    pub gas_cap: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct SignerLayer {
+    pub private_key: Option<String>,
+    /// BIP-39 mnemonic the executor is derived from, instead of a private key.
+    pub mnemonic: Option<String>,
+    pub hd_path: Option<String>,
+    /// Index of the executor below `hd_path`.
+    pub account_index: Option<u32>,
+    /// Encrypted JSON keystore holding the executor key, instead of a private key.
+    pub keystore: Option<PathBuf>,
+    /// File whose first line is the keystore password.
+    pub password_file: Option<PathBuf>,
+    /// Keystore password, only read from the environment.
+    #[serde(skip)]
+    pub keystore_password: Option<String>,
+    /// Remote signing service (such as Web3Signer) that holds the executor key instead.
+    pub remote_url: Option<String>,
+    pub remote_address: Option<Address>,
+}
+
+/// Gelato Relay, used to send redemptions instead of the executor once a key is set.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct GelatoLayer {
+    pub url: Option<String>,
+    pub api_key: Option<String>,
+}
+
+/// ERC-4337 bundler, used to send redemptions from a smart account once a URL is set.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct BundlerLayer {
+    pub url: Option<String>,
+    pub entry_point: Option<Address>,
+    /// Smart account the executor key owns.
+    pub account: Option<Address>,
+}
+
+/// Safe that redeems in the executor's place once an address is set.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct SafeLayer {
+    pub address: Option<Address>,
+    /// Safe Transaction Service that proposals go to when the threshold is above 1; the
+    /// network's by default.
+    pub service_url: Option<String>,
+}
+
+impl Layer {
+    /// Returns `self` with every field set in `other` taking precedence.
+    pub fn merge(self, other: Layer) -> Layer {
+        Layer {
+            network: other.network.or(self.network),
+            chain_id: other.chain_id.or(self.chain_id),
+            api_url: other.api_url.or(self.api_url),
+            api_token: other.api_token.or(self.api_token),
+            api_headers: other.api_headers.or(self.api_headers),
+            api_page_size: other.api_page_size.or(self.api_page_size),
+            api_health_url: other.api_health_url.or(self.api_health_url),
+            api_min_interval: other.api_min_interval.or(self.api_min_interval),
+            api_replicas: other.api_replicas.or(self.api_replicas),
+            api_quorum: other.api_quorum.or(self.api_quorum),
+            rpc_url: other.rpc_url.or(self.rpc_url),
+            rpc_fallbacks: other.rpc_fallbacks.or(self.rpc_fallbacks),
+            rpc_cooldown: other.rpc_cooldown.or(self.rpc_cooldown),
+            send_rpc_url: other.send_rpc_url.or(self.send_rpc_url),
+            ws_url: other.ws_url.or(self.ws_url),
+            circles_rpc_url: other.circles_rpc_url.or(self.circles_rpc_url),
+            explorer_url: other.explorer_url.or(self.explorer_url),
+            pathfinder: other.pathfinder.or(self.pathfinder),
+            pathfinder_api: other.pathfinder_api.or(self.pathfinder_api),
+            rpc_timeout: other.rpc_timeout.or(self.rpc_timeout),
+            circles_rpc_timeout: other.circles_rpc_timeout.or(self.circles_rpc_timeout),
+            circles_rpc_rate_limit: other.circles_rpc_rate_limit.or(self.circles_rpc_rate_limit),
+            pathfinder_timeout: other.pathfinder_timeout.or(self.pathfinder_timeout),
+            pathfinder_cassette: other.pathfinder_cassette.or(self.pathfinder_cassette),
+            pathfinder_max_failures: other
+                .pathfinder_max_failures
+                .or(self.pathfinder_max_failures),
+            pathfinder_cooldown: other.pathfinder_cooldown.or(self.pathfinder_cooldown),
+            max_path_transfers: other.max_path_transfers.or(self.max_path_transfers),
+            max_path_token_owners: other.max_path_token_owners.or(self.max_path_token_owners),
+            exclude_from_tokens: other.exclude_from_tokens.or(self.exclude_from_tokens),
+            exclude_to_tokens: other.exclude_to_tokens.or(self.exclude_to_tokens),
+            module_address: other.module_address.or(self.module_address),
+            modules: other.modules.or(self.modules),
+            interval: other.interval.or(self.interval),
+            max_runtime: other.max_runtime.or(self.max_runtime),
+            concurrency: other.concurrency.or(self.concurrency),
+            batch_size: other.batch_size.or(self.batch_size),
+            confirmations: other.confirmations.or(self.confirmations),
+            paused: other.paused.or(self.paused),
+            min_balance: other.min_balance.or(self.min_balance),
+            pause_on_low_balance: other.pause_on_low_balance.or(self.pause_on_low_balance),
+            pause_file: other.pause_file.or(self.pause_file),
+            state_file: other.state_file.or(self.state_file),
+            state_backend: other.state_backend.or(self.state_backend),
+            metrics_file: other.metrics_file.or(self.metrics_file),
+            dedupe_window: other.dedupe_window.or(self.dedupe_window),
+            source_file: other.source_file.or(self.source_file),
+            cache_ttl: other.cache_ttl.or(self.cache_ttl),
+            path_cache_ttl: other.path_cache_ttl.or(self.path_cache_ttl),
+            max_path_age: other.max_path_age.or(self.max_path_age),
+            stream_url: other.stream_url.or(self.stream_url),
+            verify: other.verify.or(self.verify),
+            untrusted_fallback: other.untrusted_fallback.or(self.untrusted_fallback),
+            trust_preflight: other.trust_preflight.or(self.trust_preflight),
+            partial_redemption: other.partial_redemption.or(self.partial_redemption),
+            partial_min_fraction: other.partial_min_fraction.or(self.partial_min_fraction),
+            reduce_target: other.reduce_target.or(self.reduce_target),
+            subgraph_url: other.subgraph_url.or(self.subgraph_url),
+            logs_from_block: other.logs_from_block.or(self.logs_from_block),
+            logs_block_range: other.logs_block_range.or(self.logs_block_range),
+            subgraph_query: other.subgraph_query.or(self.subgraph_query),
+            allow: other.allow.or(self.allow),
+            deny: other.deny.or(self.deny),
+            path_rules: other.path_rules.or(self.path_rules),
+            retry: RetryLayer {
+                attempts: other.retry.attempts.or(self.retry.attempts),
+                backoff: other.retry.backoff.or(self.retry.backoff),
+                jitter: other.retry.jitter.or(self.retry.jitter),
+            },
+            redeem_retry: RedeemRetryLayer {
+                pathfinding: other
+                    .redeem_retry
+                    .pathfinding
+                    .or(self.redeem_retry.pathfinding),
+                stale_path: other
+                    .redeem_retry
+                    .stale_path
+                    .or(self.redeem_retry.stale_path),
+                simulation: other
+                    .redeem_retry
+                    .simulation
+                    .or(self.redeem_retry.simulation),
+                submission: other
+                    .redeem_retry
+                    .submission
+                    .or(self.redeem_retry.submission),
+                timeout: other.redeem_retry.timeout.or(self.redeem_retry.timeout),
+                reverted: other.redeem_retry.reverted.or(self.redeem_retry.reverted),
+                confirmation: other
+                    .redeem_retry
+                    .confirmation
+                    .or(self.redeem_retry.confirmation),
+                backoff: other.redeem_retry.backoff.or(self.redeem_retry.backoff),
+            },
+            http: HttpLayer {
+                connect_timeout: other.http.connect_timeout.or(self.http.connect_timeout),
+                timeout: other.http.timeout.or(self.http.timeout),
+                read_timeout: other.http.read_timeout.or(self.http.read_timeout),
+                max_response_size: other.http.max_response_size.or(self.http.max_response_size),
+                max_redirects: other.http.max_redirects.or(self.http.max_redirects),
+                proxy: other.http.proxy.or(self.http.proxy),
+            },
+            tx: TxLayer {
+                timeout: other.tx.timeout.or(self.tx.timeout),
+                rebroadcasts: other.tx.rebroadcasts.or(self.tx.rebroadcasts),
+                max_bumps: other.tx.max_bumps.or(self.tx.max_bumps),
+                fee_ceiling: other.tx.fee_ceiling.or(self.tx.fee_ceiling),
+                gas_multiplier: other.tx.gas_multiplier.or(self.tx.gas_multiplier),
+                gas_cap: other.tx.gas_cap.or(self.tx.gas_cap),
+            },
+            signer: SignerLayer {
+                private_key: other.signer.private_key.or(self.signer.private_key),
+                mnemonic: other.signer.mnemonic.or(self.signer.mnemonic),
+                hd_path: other.signer.hd_path.or(self.signer.hd_path),
+                account_index: other.signer.account_index.or(self.signer.account_index),
+                keystore: other.signer.keystore.or(self.signer.keystore),
+                password_file: other.signer.password_file.or(self.signer.password_file),
+                keystore_password: other
+                    .signer
+                    .keystore_password
+                    .or(self.signer.keystore_password),
+                remote_url: other.signer.remote_url.or(self.signer.remote_url),
+                remote_address: other.signer.remote_address.or(self.signer.remote_address),
+            },
+            gelato: GelatoLayer {
+                url: other.gelato.url.or(self.gelato.url),
+                api_key: other.gelato.api_key.or(self.gelato.api_key),
+            },
+            bundler: BundlerLayer {
+                url: other.bundler.url.or(self.bundler.url),
+                entry_point: other.bundler.entry_point.or(self.bundler.entry_point),
+                account: other.bundler.account.or(self.bundler.account),
+            },
+            safe: SafeLayer {
+                address: other.safe.address.or(self.safe.address),
+                service_url: other.safe.service_url.or(self.safe.service_url),
+            },
+        }
+    }
+
+    /// Built-in defaults for the layer's network (Gnosis unless one was selected).
+    pub fn defaults(network: Option<Network>) -> Layer {
+        Layer {
+            api_url: Some(DEFAULT_API_URL.to_string()),
+            rpc_cooldown: Some(Duration::from_secs(30)),
+            pathfinder_max_failures: Some(5),
+            pathfinder_cooldown: Some(Duration::from_secs(60)),
+            retry: RetryLayer {
+                attempts: Some(3),
+                backoff: Some(Duration::from_secs(1)),
+                jitter: Some(true),
+            },
+            http: HttpLayer {
+                connect_timeout: Some(Duration::from_secs(10)),
+                timeout: Some(Duration::from_secs(60)),
+                read_timeout: None,
+                max_response_size: Some(64 * 1024 * 1024),
+                max_redirects: Some(5),
+                proxy: None,
+            },
+            tx: TxLayer {
+                timeout: Some(Duration::from_secs(120)),
+                rebroadcasts: Some(3),
+                max_bumps: Some(3),
+                fee_ceiling: None,
+                gas_multiplier: Some(1.2),
+                gas_cap: None,
+            },
+            gelato: GelatoLayer {
+                url: Some(DEFAULT_GELATO_URL.to_string()),
+                api_key: None,
+            },
+            bundler: BundlerLayer {
+                entry_point: Some(ENTRY_POINT_V07),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+        .merge(network.unwrap_or_default().layer())
+    }
+
+    /// Settings only read from the environment, never from flags.
+    fn from_env() -> Layer {
+        Layer {
+            api_token: env::var("API_TOKEN").ok(),
+            signer: SignerLayer {
+                private_key: env::var("PK").ok(),
+                mnemonic: env::var("MNEMONIC").ok(),
+                keystore_password: env::var("KEYSTORE_PASSWORD").ok(),
+                ..Default::default()
+            },
+            gelato: GelatoLayer {
+                url: None,
+                api_key: env::var("GELATO_API_KEY").ok(),
+            },
+            ..Default::default()
+        }
+    }
+}
+
+/// A config file: base settings plus named `[profile.<name>]` overrides.
+#[derive(Debug, Default, Deserialize)]
+pub struct FileConfig {
+    #[serde(flatten)]
+    pub base: Layer,
+    #[serde(default)]
+    pub profile: HashMap<String, Layer>,
+}
+
+impl FileConfig {
+    pub fn parse(contents: &str) -> Result<Self> {
+        Ok(toml::from_str(contents)?)
+    }
+
+    fn load(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file {}", path.display()))?;
+        Self::parse(&contents)
+            .with_context(|| format!("Failed to parse config file {}", path.display()))
+    }
+
+    /// The base layer with the named profile applied on top.
+    pub fn layer(mut self, profile: Option<&str>) -> Result<Layer> {
+        match profile {
+            None => Ok(self.base),
+            Some(name) => {
+                let overrides = self
+                    .profile
+                    .remove(name)
+                    .ok_or_else(|| anyhow!("Unknown profile {name:?}"))?;
+                Ok(self.base.merge(overrides))
+            }
+        }
+    }
+}
+
+pub struct Config {
+    pub network: Network,
+    pub chain_id: u64,
+    pub api_url: Url,
+    /// Headers sent with every indexer request, including the bearer token if configured.
+    pub api_headers: HeaderMap,
+    /// When set, the indexer is queried page by page with `limit`/`offset`.
+    pub api_page_size: Option<usize>,
+    /// Checked before every run; the run is aborted if the indexer is down or incompatible.
+    pub api_health_url: Option<Url>,
+    /// Validators and bodies of previous indexer responses, for conditional requests.
+    pub api_conditional: ConditionalCache,
+    /// Spaces out indexer requests by `api_min_interval`.
+    pub api_rate_limit: RateLimiter,
+    /// Further indexer replicas queried concurrently with `api_url`.
+    pub api_replicas: Vec<Url>,
+    /// How many of the indexers must list a subscription; 1 takes the union.
+    pub api_quorum: usize,
+    pub rpc_url: Url,
+    /// Further execution RPCs failed over to when `rpc_url` errors or times out.
+    pub rpc_fallbacks: Vec<Url>,
+    /// Spreads execution RPC requests over `rpc_url` and `rpc_fallbacks`, if any are set.
+    pub rpc_failover: Option<Failover>,
+    /// Endpoint that signed transactions are sent to, such as a frontrunning-protected RPC;
+    /// everything else, including watching for receipts, still goes through `rpc_url`.
+    pub send_rpc_url: Option<Url>,
+    /// WebSocket connection that receipts are awaited on, if a `ws_url` is set.
+    pub ws: Option<Ws>,
+    /// Block explorer that transaction links point to.
+    pub explorer_url: Url,
+    pub circles_rpc_url: Url,
+    /// Path searches and balance and trust queries on `circles_rpc_url`, shared by every
+    /// redemption.
+    pub circles: CirclesClient,
+    /// Time allowed for a path search, independent of the execution RPC's `rpc_timeout`.
+    pub circles_rpc_timeout: Option<Duration>,
+    /// Searches transfer paths for trusted redemptions.
+    pub pathfinder: Box<dyn Pathfinder>,
+    /// Time allowed for one search (or batch of searches) by `pathfinder`, after which it is
+    /// cancelled.
+    pub pathfinder_timeout: Option<Duration>,
+    /// Suspends path searches while the pathfinder is down.
+    pub pathfinder_breaker: CircuitBreaker,
+    /// Paths with more transfers than this are rejected, as their redemption would take too
+    /// much gas.
+    pub max_path_transfers: Option<usize>,
+    /// Paths moving the tokens of more distinct owners than this are rejected.
+    pub max_path_token_owners: Option<usize>,
+    /// Tokens (by owner) excluded from the sender's side of every path, e.g. dust or group
+    /// tokens.
+    pub exclude_from_tokens: Vec<Address>,
+    /// Tokens (by owner) excluded from the receiver's side of every path.
+    pub exclude_to_tokens: Vec<Address>,
+    /// When non-empty, only subscriptions on these SubscriptionModules are redeemed. Each
+    /// redemption is sent to the module named in its indexer entry.
+    pub modules: Vec<Address>,
+    /// Poll interval when running as a daemon.
+    pub interval: Option<Duration>,
+    /// Wall-clock budget for a single pass; subscriptions left when it runs out are skipped.
+    pub max_runtime: Option<Duration>,
+    /// How many redemptions are processed at the same time; nonces are handed out in order.
+    pub concurrency: usize,
+    /// Up to this many redemptions are sent together in one Multicall3 transaction; 1 sends
+    /// each on its own.
+    pub batch_size: usize,
+    /// Blocks a sent redemption waits for before it is reported; 0 returns once it is sent.
+    pub confirmations: u64,
+    /// Kill switch: halts all redemptions while set.
+    pub paused: bool,
+    /// Kill switch: halts all redemptions while this file exists.
+    pub pause_file: Option<PathBuf>,
+    /// Balance in wei below which whoever pays for gas is reported as running low.
+    pub min_balance: Option<U256>,
+    /// Halts all redemptions while the balance is below `min_balance`.
+    pub pause_on_low_balance: bool,
+    /// Whether the last balance check found the balance below `min_balance`.
+    pub low_balance: AtomicBool,
+    /// Where runs record the last fetch time and unredeemed subscriptions, enabling delta
+    /// fetches.
+    pub state_file: Option<PathBuf>,
+    /// Whether `state_file` is JSON or a SQLite database with a ledger of attempts.
+    pub state_backend: StateBackend,
+    /// Where the fetch metrics are written after every fetch, in the Prometheus text format.
+    pub metrics_file: Option<PathBuf>,
+    /// Latency, result and error counts of indexer fetches.
+    pub fetch_metrics: FetchMetrics,
+    /// Latency of pathfinder searches by outcome, written to `metrics_file` with the fetch
+    /// metrics.
+    pub pathfinder_metrics: PathfinderMetrics,
+    /// How long a redeemed or rejected subscription is ignored by later runs.
+    pub dedupe_window: Duration,
+    /// Read subscriptions from this JSON or CSV file instead of the indexer.
+    pub source_file: Option<PathBuf>,
+    /// How long fetched subscriptions are reused before the source is queried again.
+    pub cache_ttl: Duration,
+    /// Paths found within `path_cache_ttl` (and `max_path_age`), reused for identical
+    /// searches.
+    pub path_cache: PathCache,
+    /// Paths searched longer ago than this are searched again before they are submitted.
+    pub max_path_age: Option<Duration>,
+    /// Indexer server-sent events endpoint announcing newly redeemable subscriptions.
+    pub stream_url: Option<Url>,
+    /// Check each subscription against the chain before redeeming it.
+    pub verify: bool,
+    /// When pathfinding fails for a trusted subscription, try redeeming it without a path,
+    /// as an untrusted one is, if the module accepts that.
+    pub untrusted_fallback: bool,
+    /// Before searching a path, check that the recipient directly accepts at least one of the
+    /// tokens the subscriber holds.
+    pub trust_preflight: bool,
+    /// When the pathfinder finds less flow than a trusted subscription is due, redeem the
+    /// whole periods it covers instead, if the module accepts that.
+    pub partial_redemption: bool,
+    /// Share of the due periods, between 0 and 1, below which a partial redemption is not made.
+    pub partial_min_fraction: f64,
+    /// When the pathfinder finds less flow than the target and no partial redemption applies,
+    /// search again for this share, between 0 and 1, of the flow it found.
+    pub reduce_target: Option<f64>,
+    /// Query this subgraph with GraphQL instead of the indexer.
+    pub subgraph_url: Option<Url>,
+    /// GraphQL query whose first top-level field lists the subscriptions.
+    pub subgraph_query: String,
+    /// Discover subscriptions from the modules' event logs from this block on instead of the
+    /// indexer.
+    pub logs_from_block: Option<u64>,
+    /// Most blocks covered by one `eth_getLogs` request.
+    pub logs_block_range: u64,
+    /// When non-empty, only these subscribers/recipients are redeemed.
+    pub allow: AddressList,
+    /// Subscribers/recipients that are never redeemed.
+    pub deny: AddressList,
+    /// Pathfinder settings by subscriber and recipient; see [`Config::path_rule`].
+    pub path_rules: Vec<PathRule>,
+    /// How often and how patiently indexer requests are retried.
+    pub retry: Retry,
+    /// How often a failed redemption is tried again, by class of failure.
+    pub redeem_retry: RedeemRetry,
+    /// Pooled HTTP client shared by every indexer, subgraph, stream and RPC request.
+    pub http: Client,
+    /// Client for JSON-RPC requests, as `http` but with `rpc_timeout` as a total timeout.
+    pub rpc_http: Client,
+    /// Per-request limits applied on top of the client's connect and read timeouts.
+    pub http_limits: HttpLimits,
+    /// How long sent redemptions may stay pending, and what is done about it.
+    pub tx: Transactions,
+    /// The executor's nonces, counted locally across a batch of redemptions.
+    pub nonces: Nonces,
+    /// Relay that sends redemptions in the executor's place, if a Gelato key is set.
+    pub gelato: Option<Gelato>,
+    /// Bundler that sends redemptions as user operations of a smart account, if configured.
+    pub bundler: Option<Bundler>,
+    /// Safe that executes or is proposed redemptions, if configured.
+    pub safe: Option<Safe>,
+    /// The config file this configuration was loaded from, if any.
+    pub path: Option<PathBuf>,
+    signer: Option<PrivateKeySigner>,
+    keystore: Option<Keystore>,
+    mnemonic: Option<Mnemonic>,
+    remote: Option<RemoteSigner>,
+}
+
+/// An encrypted executor key, decrypted the first time it is needed so that commands which
+/// never sign do not ask for its password.
+struct Keystore {
+    path: PathBuf,
+    password: Option<String>,
+    password_file: Option<PathBuf>,
+    signer: Mutex<Option<PrivateKeySigner>>,
+}
+
+impl Keystore {
+    fn signer(&self) -> Result<PrivateKeySigner> {
+        let mut signer = self
+            .signer
+            .lock()
+            .map_err(|_| anyhow!("Keystore lock poisoned"))?;
+        if let Some(signer) = &*signer {
+            return Ok(signer.clone());
+        }
+        let decrypted = decrypt_keystore(
+            &self.path,
+            self.password.clone(),
+            self.password_file.as_deref(),
+        )?;
+        *signer = Some(decrypted.clone());
+        Ok(decrypted)
+    }
+}
+
+impl Config {
+    /// Resolves configuration from defaults, the config file (and profile), the environment,
+    /// and command-line flags, in increasing order of precedence.
+    pub fn load(args: &ConfigArgs) -> Result<Self> {
+        let path = match &args.config {
+            Some(path) => Some(path.clone()),
+            None if Path::new(DEFAULT_CONFIG_FILE).exists() => Some(DEFAULT_CONFIG_FILE.into()),
+            None if args.profile.is_some() => {
+                return Err(anyhow!("--profile requires a config file"));
+            }
+            None => None,
+        };
+        let file = match &path {
+            Some(path) => FileConfig::load(path)?,
+            None => FileConfig::default(),
+        };
+        let layer = file
+            .layer(args.profile.as_deref())?
+            .merge(Layer::from_env())
+            .merge(args.layer());
+        let mut config = Self::from_layer(Layer::defaults(layer.network).merge(layer))?;
+        config.path = path;
+        Ok(config)
+    }
+
+    pub fn from_layer(layer: Layer) -> Result<Self> {
+        fn url(name: &str, value: Option<String>) -> Result<Url> {
+            let value = value.ok_or_else(|| anyhow!("{name} is not set"))?;
+            value
+                .parse()
+                .with_context(|| format!("Invalid {name}: {value}"))
+        }
+        let api_replicas = layer
+            .api_replicas
+            .unwrap_or_default()
+            .into_iter()
+            .map(|value| url("api_replicas", Some(value)))
+            .collect::<Result<Vec<_>>>()?;
+        let rpc_url = url("rpc_url", layer.rpc_url)?;
+        let rpc_fallbacks = layer
+            .rpc_fallbacks
+            .unwrap_or_default()
+            .into_iter()
+            .map(|value| url("rpc_fallbacks", Some(value)))
+            .collect::<Result<Vec<_>>>()?;
+        let api_quorum = layer.api_quorum.unwrap_or(1);
+        if api_quorum == 0 || api_quorum > api_replicas.len() + 1 {
+            return Err(anyhow!(
+                "api_quorum must be between 1 and the number of indexers ({})",
+                api_replicas.len() + 1
+            ));
+        }
+        let path_rules = layer.path_rules.unwrap_or_default();
+        if path_rules
+            .iter()
+            .any(|rule| rule.subscriber.is_none() && rule.recipient.is_none())
+        {
+            return Err(anyhow!(
+                "every path_rules entry needs a subscriber or recipient"
+            ));
+        }
+        let mnemonic = layer
+            .signer
+            .mnemonic
+            .map(|phrase| {
+                Mnemonic::new(
+                    &phrase,
+                    layer.signer.hd_path.as_deref().unwrap_or(DEFAULT_HD_PATH),
+                )
+            })
+            .transpose()?;
+        let http = http_client(&layer.http)?;
+        let circles_rpc_url = url("circles_rpc_url", layer.circles_rpc_url)?;
+        let circles_rpc_interval = match layer.circles_rpc_rate_limit {
+            None => Duration::ZERO,
+            Some(rate) if rate > 0.0 && rate.is_finite() => Duration::from_secs_f64(1.0 / rate),
+            Some(_) => return Err(anyhow!("circles_rpc_rate_limit must be above 0")),
+        };
+        let rpc_http = match layer.rpc_timeout {
+            Some(timeout) => http_client_builder(&layer.http)?
+                .timeout(timeout)
+                .build()
+                .context("Failed to build HTTP client")?,
+            None => http.clone(),
+        };
+        let rpc_failover = (!rpc_fallbacks.is_empty()).then(|| {
+            Failover::new(
+                &rpc_http,
+                [rpc_url.clone()].into_iter().chain(rpc_fallbacks.clone()),
+                layer.rpc_cooldown.unwrap_or_default(),
+            )
+        });
+        let remote = match layer.signer.remote_url {
+            _ if layer.signer.private_key.is_some()
+                || mnemonic.is_some()
+                || layer.signer.keystore.is_some() =>
+            {
+                if layer.signer.remote_url.is_some() {
+                    return Err(anyhow!(
+                        "signer.remote_url cannot be used with PK, MNEMONIC or KEYSTORE"
+                    ));
+                }
+                None
+            }
+            Some(value) => Some(RemoteSigner {
+                address: layer
+                    .signer
+                    .remote_address
+                    .ok_or_else(|| anyhow!("signer.remote_address is not set"))?,
+                client: RpcClient::new_http_with_client(
+                    http.clone(),
+                    url("signer.remote_url", Some(value))?,
+                ),
+            }),
+            None => None,
+        };
+        if remote.is_some() && (layer.safe.address.is_some() || layer.bundler.url.is_some()) {
+            return Err(anyhow!(
+                "Safe transactions and user operations cannot be signed remotely"
+            ));
+        }
+        let senders = [
+            layer.gelato.api_key.is_some(),
+            layer.bundler.url.is_some(),
+            layer.safe.address.is_some(),
+        ];
+        if senders.into_iter().filter(|&set| set).count() > 1 {
+            return Err(anyhow!("only one of gelato, bundler and safe can be used"));
+        }
+        Ok(Self {
+            network: layer.network.unwrap_or_default(),
+            chain_id: layer
+                .chain_id
+                .ok_or_else(|| anyhow!("chain_id is not set"))?,
+            api_url: url("api_url", layer.api_url)?,
+            api_headers: api_headers(layer.api_headers.unwrap_or_default(), layer.api_token)?,
+            api_page_size: match layer.api_page_size {
+                Some(0) => return Err(anyhow!("api_page_size must be positive")),
+                size => size,
+            },
+            api_health_url: layer
+                .api_health_url
+                .map(|value| url("api_health_url", Some(value)))
+                .transpose()?,
+            api_conditional: ConditionalCache::default(),
+            api_rate_limit: RateLimiter::new("indexer", layer.api_min_interval.unwrap_or_default()),
+            api_replicas,
+            api_quorum,
+            rpc_url,
+            rpc_fallbacks,
+            rpc_failover,
+            send_rpc_url: layer
+                .send_rpc_url
+                .map(|value| url("send_rpc_url", Some(value)))
+                .transpose()?,
+            ws: layer
+                .ws_url
+                .map(|value| url("ws_url", Some(value)).map(Ws::new))
+                .transpose()?,
+            circles: CirclesClient::new(
+                http.clone(),
+                circles_rpc_url.clone(),
+                layer.pathfinder_api.unwrap_or_default(),
+                circles_rpc_interval,
+            ),
+            circles_rpc_url,
+            explorer_url: url("explorer_url", layer.explorer_url)?,
+            circles_rpc_timeout: layer.circles_rpc_timeout.or(layer.http.timeout),
+            pathfinder: layer
+                .pathfinder
+                .unwrap_or_default()
+                .pathfinder(layer.pathfinder_cassette.as_deref())?,
+            pathfinder_timeout: layer
+                .pathfinder_timeout
+                .or(layer.circles_rpc_timeout)
+                .or(layer.http.timeout),
+            pathfinder_breaker: CircuitBreaker::new(
+                layer.pathfinder_max_failures.unwrap_or(0),
+                layer.pathfinder_cooldown.unwrap_or_default(),
+            ),
+            max_path_transfers: layer.max_path_transfers,
+            max_path_token_owners: layer.max_path_token_owners,
+            exclude_from_tokens: layer.exclude_from_tokens.unwrap_or_default(),
+            exclude_to_tokens: layer.exclude_to_tokens.unwrap_or_default(),
+            modules: modules(layer.module_address, layer.modules.unwrap_or_default()),
+            interval: match layer.interval {
+                Some(Duration::ZERO) => return Err(anyhow!("interval must be positive")),
+                interval => interval,
+            },
+            max_runtime: layer.max_runtime,
+            concurrency: match layer.concurrency {
+                Some(0) => return Err(anyhow!("concurrency must be positive")),
+                concurrency => concurrency.unwrap_or(1),
+            },
+            batch_size: match layer.batch_size {
+                Some(0) => return Err(anyhow!("batch_size must be positive")),
+                batch_size => batch_size.unwrap_or(1),
+            },
+            confirmations: layer.confirmations.unwrap_or(1),
+            paused: layer.paused.unwrap_or(false),
+            pause_file: layer.pause_file,
+            min_balance: layer
+                .min_balance
+                .map(|xdai| parse_ether(&xdai))
+                .transpose()
+                .context("Invalid min_balance")?,
+            pause_on_low_balance: layer.pause_on_low_balance.unwrap_or(false),
+            low_balance: AtomicBool::new(false),
+            state_file: layer.state_file,
+            state_backend: layer.state_backend.unwrap_or_default(),
+            metrics_file: layer.metrics_file,
+            fetch_metrics: FetchMetrics::default(),
+            pathfinder_metrics: PathfinderMetrics::default(),
+            dedupe_window: layer.dedupe_window.unwrap_or(DEFAULT_DEDUPE_WINDOW),
+            source_file: layer.source_file,
+            cache_ttl: layer.cache_ttl.unwrap_or_default(),
+            // A cached path must still be fresh enough to submit.
+            path_cache: PathCache::new(
+                layer
+                    .path_cache_ttl
+                    .unwrap_or_default()
+                    .min(layer.max_path_age.unwrap_or(Duration::MAX)),
+            ),
+            max_path_age: layer.max_path_age,
+            stream_url: layer
+                .stream_url
+                .map(|value| url("stream_url", Some(value)))
+                .transpose()?,
+            verify: layer.verify.unwrap_or(true),
+            untrusted_fallback: layer.untrusted_fallback.unwrap_or(false),
+            trust_preflight: layer.trust_preflight.unwrap_or(false),
+            partial_redemption: layer.partial_redemption.unwrap_or(false),
+            partial_min_fraction: match layer.partial_min_fraction.unwrap_or(0.5) {
+                fraction if (0.0..=1.0).contains(&fraction) => fraction,
+                _ => return Err(anyhow!("partial_min_fraction must be between 0 and 1")),
+            },
+            reduce_target: match layer.reduce_target {
+                None => None,
+                Some(fraction) if fraction > 0.0 && fraction <= 1.0 => Some(fraction),
+                Some(_) => return Err(anyhow!("reduce_target must be above 0 and at most 1")),
+            },
+            subgraph_url: layer
+                .subgraph_url
+                .map(|value| url("subgraph_url", Some(value)))
+                .transpose()?,
+            subgraph_query: layer
+                .subgraph_query
+                .unwrap_or_else(|| DEFAULT_SUBGRAPH_QUERY.to_string()),
+            logs_from_block: layer.logs_from_block,
+            logs_block_range: match layer.logs_block_range {
+                Some(0) => return Err(anyhow!("logs_block_range must be at least 1")),
+                range => range.unwrap_or(DEFAULT_LOGS_BLOCK_RANGE),
+            },
+            allow: layer.allow.unwrap_or_default(),
+            deny: layer.deny.unwrap_or_default(),
+            path_rules,
+            retry: Retry {
+                attempts: layer.retry.attempts.unwrap_or(1).max(1),
+                backoff: layer.retry.backoff.unwrap_or_default(),
+                jitter: layer.retry.jitter.unwrap_or(false),
+            },
+            redeem_retry: RedeemRetry {
+                pathfinding: layer.redeem_retry.pathfinding.unwrap_or_default(),
+                stale_path: layer.redeem_retry.stale_path.unwrap_or_default(),
+                simulation: layer.redeem_retry.simulation.unwrap_or_default(),
+                submission: layer.redeem_retry.submission.unwrap_or_default(),
+                timeout: layer.redeem_retry.timeout.unwrap_or_default(),
+                reverted: layer.redeem_retry.reverted.unwrap_or_default(),
+                confirmation: layer.redeem_retry.confirmation.unwrap_or_default(),
+                backoff: layer.redeem_retry.backoff.unwrap_or_default(),
+            },
+            http: http.clone(),
+            rpc_http,
+            http_limits: HttpLimits {
+                timeout: layer.http.timeout,
+                max_response_size: layer.http.max_response_size.unwrap_or(usize::MAX),
+            },
+            tx: Transactions {
+                timeout: layer.tx.timeout,
+                rebroadcasts: layer.tx.rebroadcasts.unwrap_or(0),
+                max_bumps: layer.tx.max_bumps.unwrap_or(0),
+                fee_ceiling: layer
+                    .tx
+                    .fee_ceiling
+                    .map(|gwei| u128::from(gwei) * 1_000_000_000),
+                gas_multiplier: match layer.tx.gas_multiplier {
+                    Some(multiplier) if !(1.0..=10.0).contains(&multiplier) => {
+                        return Err(anyhow!("tx.gas_multiplier must be between 1 and 10"));
+                    }
+                    multiplier => multiplier.unwrap_or(1.0),
+                },
+                gas_cap: layer.tx.gas_cap,
+            },
+            nonces: Nonces::default(),
+            gelato: match layer.gelato.api_key {
+                Some(api_key) => Some(Gelato {
+                    url: url("gelato.url", layer.gelato.url)?,
+                    api_key,
+                }),
+                None => None,
+            },
+            bundler: match layer.bundler.url {
+                Some(value) => Some(Bundler {
+                    url: url("bundler.url", Some(value))?,
+                    entry_point: layer.bundler.entry_point.unwrap_or(ENTRY_POINT_V07),
+                    account: layer
+                        .bundler
+                        .account
+                        .ok_or_else(|| anyhow!("bundler.account is not set"))?,
+                }),
+                None => None,
+            },
+            safe: match layer.safe.address {
+                Some(address) => Some(Safe {
+                    address,
+                    service_url: url("safe.service_url", layer.safe.service_url)?,
+                    lock: Default::default(),
+                }),
+                None => None,
+            },
+            path: None,
+            signer: match (layer.signer.private_key, &mnemonic, &layer.signer.keystore) {
+                (Some(pk), None, None) => Some(pk.parse().context("Invalid private key")?),
+                (None, Some(mnemonic), None) => {
+                    Some(mnemonic.signer(layer.signer.account_index.unwrap_or(0))?)
+                }
+                (None, None, _) => None,
+                _ => {
+                    return Err(anyhow!("only one of PK, MNEMONIC and KEYSTORE can be set"));
+                }
+            },
+            keystore: layer.signer.keystore.map(|path| Keystore {
+                path,
+                password: layer.signer.keystore_password,
+                password_file: layer.signer.password_file,
+                signer: Mutex::new(None),
+            }),
+            mnemonic,
+            remote,
+        })
+    }
+
+    /// Why redemptions are halted, if a kill switch is engaged. Checked before every
+    /// redemption so operators can stop spending without restarting the process.
+    pub fn halted(&self) -> Option<String> {
+        if self.paused {
+            return Some("paused by configuration".to_string());
+        }
+        if self.pause_on_low_balance && self.low_balance.load(Ordering::Relaxed) {
+            return Some("executor balance is below min_balance".to_string());
+        }
+        match &self.pause_file {
+            Some(path) if path.exists() => Some(format!("pause file {} exists", path.display())),
+            _ => None,
+        }
+    }
+
+    /// Why the allow/deny lists exclude `subscription`, if they do.
+    pub fn screen(&self, subscription: &RedeemableSubscription) -> Option<String> {
+        if self.deny.subscribers.contains(&subscription.subscriber) {
+            return Some(format!("subscriber {} is denied", subscription.subscriber));
+        }
+        if self.deny.recipients.contains(&subscription.recipient) {
+            return Some(format!("recipient {} is denied", subscription.recipient));
+        }
+        if !self.allow.subscribers.is_empty()
+            && !self.allow.subscribers.contains(&subscription.subscriber)
+        {
+            return Some(format!(
+                "subscriber {} is not allowed",
+                subscription.subscriber
+            ));
+        }
+        if !self.allow.recipients.is_empty()
+            && !self.allow.recipients.contains(&subscription.recipient)
+        {
+            return Some(format!(
+                "recipient {} is not allowed",
+                subscription.recipient
+            ));
+        }
+        None
+    }
+
+    /// The pathfinder settings for a path from `subscriber` to `recipient`: each field from
+    /// the first matching rule in `path_rules` that sets it.
+    pub fn path_rule(&self, subscriber: Address, recipient: Address) -> PathRule {
+        self.path_rules
+            .iter()
+            .filter(|rule| rule.matches(subscriber, recipient))
+            .fold(PathRule::default(), PathRule::or)
+    }
+
+    /// A JSON-RPC client for the execution RPC, failing over to `rpc_fallbacks` if any are set.
+    pub fn rpc(&self) -> RpcClient {
+        match &self.rpc_failover {
+            Some(failover) => RpcClient::new(failover.clone(), false),
+            None => self.rpc_client(&self.rpc_url),
+        }
+    }
+
+    /// A JSON-RPC client for `url` that goes through the shared HTTP client's proxy and is
+    /// limited by `rpc_timeout`.
+    pub fn rpc_client(&self, url: &Url) -> RpcClient {
+        RpcClient::new_http_with_client(self.rpc_http.clone(), url.clone())
+    }
+
+    /// The address the module sees calling `redeem`: the smart account when sending through a
+    /// bundler, the Safe when redeeming through one, otherwise the executor.
+    pub fn caller(&self) -> Result<Address> {
+        match (&self.bundler, &self.safe) {
+            (Some(bundler), _) => Ok(bundler.account),
+            (_, Some(safe)) => Ok(safe.address),
+            _ => self.executor(),
+        }
+    }
+
+    /// The mnemonic the executor was derived from, if any.
+    pub fn mnemonic(&self) -> Option<&Mnemonic> {
+        self.mnemonic.as_ref()
+    }
+
+    /// The redeeming wallet's key; only commands that send transactions require one, and
+    /// signing Safe transactions and user operations needs it locally. A keystore is decrypted
+    /// on the first call.
+    pub fn signer(&self) -> Result<PrivateKeySigner> {
+        match (&self.signer, &self.keystore) {
+            (Some(signer), _) => Ok(signer.clone()),
+            (None, Some(keystore)) => keystore.signer(),
+            (None, None) => Err(anyhow!("PK, MNEMONIC or KEYSTORE is not set")),
+        }
+    }
+
+    /// Takes over the key `previous` already decrypted from the same keystore, so that a
+    /// reload does not decrypt it (or prompt for its password) again.
+    pub fn keep_signer(&mut self, previous: &Config) {
+        if let (Some(keystore), Some(old)) = (&mut self.keystore, &previous.keystore)
+            && keystore.path == old.path
+            && let Ok(signer) = old.signer.lock()
+        {
+            *keystore.signer.get_mut().unwrap_or_else(|e| e.into_inner()) = signer.clone();
+        }
+    }
+
+    /// The address of the redeeming wallet, whether its key is local or remote.
+    pub fn executor(&self) -> Result<Address> {
+        match &self.remote {
+            Some(remote) => Ok(remote.address),
+            None => Ok(self.signer()?.address()),
+        }
+    }
+
+    /// Signs the executor's transactions, locally or through the remote signer.
+    pub fn wallet(&self) -> Result<EthereumWallet> {
+        match &self.remote {
+            Some(remote) => Ok(EthereumWallet::new(remote.clone())),
+            None => Ok(self.signer()?.into()),
+        }
+    }
+}
+
+/// Exponential backoff policy for transient indexer failures.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Retry {
+    /// Total number of attempts, including the first.
+    pub attempts: u32,
+    /// Delay before the first retry; doubled for every further retry.
+    pub backoff: Duration,
+    /// Randomly shorten each delay by up to half to spread out retries.
+    pub jitter: bool,
+}
+
+impl Retry {
+    const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+    /// How long to wait after the given failed attempt (starting at 1).
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let delay = self
+            .backoff
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(Self::MAX_BACKOFF);
+        if !self.jitter {
+            return delay;
+        }
+        // Sub-second clock noise is random enough to de-synchronise retries.
+        let noise = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.subsec_nanos())
+            .unwrap_or_default();
+        delay.mul_f64(1.0 - f64::from(noise % 500) / 1000.0)
+    }
+}
+
+/// Retries of failed redemptions, counted separately for each class of failure. None are
+/// retried unless configured.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct RedeemRetry {
+    pub pathfinding: u32,
+    /// Retries, with a fresh path, of a trusted redemption whose path no longer simulates.
+    pub stale_path: u32,
+    /// Retries of any other redemption that would revert; the outcome rarely changes.
+    pub simulation: u32,
+    pub submission: u32,
+    /// Retries of a transaction stuck after every rebroadcast and fee bump. Its nonce stays
+    /// taken, so a retry only succeeds once the stuck transaction is mined or dropped.
+    pub timeout: u32,
+    pub reverted: u32,
+    pub confirmation: u32,
+    /// Delay before each retry.
+    pub backoff: Duration,
+}
+
+impl RedeemRetry {
+    /// The name under which `error` is counted and how often it may be retried. A trusted
+    /// redemption that would revert counts as a stale path only if the retry finds a new path.
+    pub fn limit(&self, error: &RedeemError, repath: bool) -> (&'static str, u32) {
+        match error {
+            RedeemError::Simulation(_) if repath => ("stale_path", self.stale_path),
+            RedeemError::Pathfinding(_) => ("pathfinding", self.pathfinding),
+            RedeemError::Simulation(_) => ("simulation", self.simulation),
+            RedeemError::Submission(_) => ("submission", self.submission),
+            RedeemError::Timeout { .. } => ("timeout", self.timeout),
+            RedeemError::Reverted { .. } => ("reverted", self.reverted),
+            RedeemError::Confirmation { .. } => ("confirmation", self.confirmation),
+        }
+    }
+}
+
+/// Handling of sent transactions that are not mined.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Transactions {
+    /// How long to wait for a receipt before treating the transaction as stuck.
+    pub timeout: Option<Duration>,
+    /// How often a stuck transaction is rebroadcast before its redemption is failed.
+    pub rebroadcasts: u32,
+    /// How often a transaction priced out by a rising base fee is replaced with higher fees.
+    pub max_bumps: u32,
+    /// Highest max fee per gas a replacement may offer, in wei.
+    pub fee_ceiling: Option<u128>,
+    /// Factor applied to every gas estimate.
+    pub gas_multiplier: f64,
+    /// Redemptions whose buffered gas estimate exceeds this are refused.
+    pub gas_cap: Option<u64>,
+}
+
+/// Limits that apply per request rather than per client.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HttpLimits {
+    /// Total time allowed for a request, including reading the body. Not applied to the
+    /// long-lived event stream.
+    pub timeout: Option<Duration>,
+    /// Largest response body accepted, in bytes.
+    pub max_response_size: usize,
+}
+
+/// The shared HTTP client. Idle connections are kept alive between polls so that repeated
+/// indexer requests skip the TCP and TLS handshakes.
+fn http_client(layer: &HttpLayer) -> Result<Client> {
+    http_client_builder(layer)?
+        .build()
+        .context("Failed to build HTTP client")
+}
+
+fn http_client_builder(layer: &HttpLayer) -> Result<reqwest::ClientBuilder> {
+    let mut builder = Client::builder()
+        .pool_idle_timeout(Duration::from_secs(90))
+        .pool_max_idle_per_host(8)
+        .tcp_keepalive(Duration::from_secs(60))
+        .redirect(match layer.max_redirects {
+            Some(0) => reqwest::redirect::Policy::none(),
+            Some(max) => reqwest::redirect::Policy::limited(max),
+            None => reqwest::redirect::Policy::default(),
+        });
+    if let Some(timeout) = layer.connect_timeout {
+        builder = builder.connect_timeout(timeout);
+    }
+    if let Some(timeout) = layer.read_timeout {
+        builder = builder.read_timeout(timeout);
+    }
+    if let Some(proxy) = &layer.proxy {
+        builder = builder
+            .proxy(reqwest::Proxy::all(proxy).with_context(|| format!("Invalid proxy {proxy:?}"))?);
+    }
+    Ok(builder)
+}
+
+/// `module_address` followed by the further `modules`, without duplicates.
+fn modules(module_address: Option<Address>, modules: Vec<Address>) -> Vec<Address> {
+    let mut all: Vec<Address> = module_address.into_iter().collect();
+    for module in modules {
+        if !all.contains(&module) {
+            all.push(module);
+        }
+    }
+    all
+}
+
+/// Builds the indexer request headers, adding `Authorization: Bearer <token>` if a token is set.
+fn api_headers(headers: HashMap<String, String>, token: Option<String>) -> Result<HeaderMap> {
+    let mut map = HeaderMap::new();
+    for (name, value) in headers {
+        map.insert(
+            HeaderName::try_from(name.as_str())
+                .with_context(|| format!("Invalid api_headers name {name:?}"))?,
+            HeaderValue::try_from(value.as_str())
+                .with_context(|| format!("Invalid api_headers value for {name:?}"))?,
+        );
+    }
+    if let Some(token) = token {
+        let mut value =
+            HeaderValue::try_from(format!("Bearer {token}")).context("Invalid api_token")?;
+        value.set_sensitive(true);
+        map.insert(AUTHORIZATION, value);
+    }
+    Ok(map)
+}
+
+/// The executor key from the keystore at `path`, decrypted with `password`, else the first
+/// line of `password_file`, else a password typed at a prompt.
+fn decrypt_keystore(
+    path: &Path,
+    password: Option<String>,
+    password_file: Option<&Path>,
+) -> Result<PrivateKeySigner> {
+    let password = match (password, password_file) {
+        (Some(password), _) => password,
+        (None, Some(file)) => std::fs::read_to_string(file)
+            .with_context(|| format!("Failed to read password file {}", file.display()))?
+            .lines()
+            .next()
+            .unwrap_or_default()
+            .to_string(),
+        (None, None) => rpassword::prompt_password(format!("Password for {}: ", path.display()))
+            .context("Failed to read the keystore password")?,
+    };
+    PrivateKeySigner::decrypt_keystore(path, password)
+        .with_context(|| format!("Failed to decrypt keystore {}", path.display()))
+}
+
+/// Detects edits to a config file by its modification time.
+pub struct Watcher {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+}
+
+impl Watcher {
+    pub fn new(path: PathBuf) -> Self {
+        let modified = Self::modified(&path);
+        Self { path, modified }
+    }
+
+    fn modified(path: &Path) -> Option<SystemTime> {
+        std::fs::metadata(path).and_then(|m| m.modified()).ok()
+    }
+
+    /// Whether the file changed since the last call (or since the watcher was created).
+    pub fn changed(&mut self) -> bool {
+        let modified = Self::modified(&self.path);
+        let changed = modified != self.modified;
+        self.modified = modified;
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FILE: &str = r#"
+        api_url = "http://indexer.local/redeemable"
+        module_address = "0xcebe4b6d50ce877a9689ce4516fe96911e099a78"
+
+        [profile.staging]
+        api_url = "http://staging.local/redeemable"
+        rpc_url = "http://staging-rpc.local/"
+    "#;
+
+    #[test]
+    fn test_profile_overrides_base() {
+        let layer = FileConfig::parse(FILE)
+            .unwrap()
+            .layer(Some("staging"))
+            .unwrap();
+        let config = Config::from_layer(Layer::defaults(None).merge(layer)).unwrap();
+        assert_eq!(config.api_url.as_str(), "http://staging.local/redeemable");
+        assert_eq!(config.rpc_url.as_str(), "http://staging-rpc.local/");
+        assert_eq!(
+            config.circles_rpc_url.as_str(),
+            Network::Gnosis.circles_rpc_url()
+        );
+        assert_eq!(config.modules.len(), 1);
+        assert!(config.verify);
+    }
+
+    #[test]
+    fn test_modules() {
+        let layer = FileConfig::parse(
+            r#"
+            modules = [
+                "0xcebe4b6d50ce877a9689ce4516fe96911e099a78",
+                "0x0000000000000000000000000000000000000001",
+            ]
+            "#,
+        )
+        .unwrap()
+        .layer(None)
+        .unwrap();
+        let config = Config::from_layer(Layer::defaults(None).merge(layer)).unwrap();
+        assert_eq!(
+            config.modules,
+            vec![
+                Network::Gnosis.module_address().unwrap(),
+                Address::with_last_byte(1)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tx_table() {
+        let layer = FileConfig::parse("[tx]\nrebroadcasts = 0")
+            .unwrap()
+            .layer(None)
+            .unwrap();
+        let config = Config::from_layer(Layer::defaults(None).merge(layer)).unwrap();
+        assert_eq!(
+            config.tx,
+            Transactions {
+                timeout: Some(Duration::from_secs(120)),
+                rebroadcasts: 0,
+                max_bumps: 3,
+                fee_ceiling: None,
+                gas_multiplier: 1.2,
+                gas_cap: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_redeem_retry_table() {
+        let layer = FileConfig::parse("[redeem_retry]\nsubmission = 3\nstale_path = 1")
+            .unwrap()
+            .layer(None)
+            .unwrap();
+        let config = Config::from_layer(Layer::defaults(None).merge(layer)).unwrap();
+        let retry = &config.redeem_retry;
+        let submission = RedeemError::Submission("timed out".into());
+        assert_eq!(retry.limit(&submission, true), ("submission", 3));
+        let revert = RedeemError::Simulation("revert: NotRedeemable".to_string());
+        assert_eq!(retry.limit(&revert, true), ("stale_path", 1));
+        assert_eq!(retry.limit(&revert, false), ("simulation", 0));
+    }
+
+    #[test]
+    fn test_mnemonic_signer() {
+        let signer = |private_key: Option<&str>| {
+            Config::from_layer(Layer::defaults(None).merge(Layer {
+                signer: SignerLayer {
+                    private_key: private_key.map(Into::into),
+                    mnemonic: Some(
+                        "test test test test test test test test test test test junk".into(),
+                    ),
+                    account_index: Some(1),
+                    ..Default::default()
+                },
+                ..Default::default()
+            }))
+        };
+        assert_eq!(
+            signer(None).unwrap().signer().unwrap().address(),
+            "0x70997970C51812dc3A010C7d01b50e0d17dc79C8"
+                .parse::<Address>()
+                .unwrap()
+        );
+        assert!(signer(Some("0x01")).is_err());
+    }
+
+    #[test]
+    fn test_keystore_signer() {
+        let keystore = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/keystore.json");
+        let signer = |password: &str| {
+            Config::from_layer(Layer::defaults(None).merge(Layer {
+                signer: SignerLayer {
+                    keystore: Some(keystore.clone()),
+                    keystore_password: Some(password.into()),
+                    ..Default::default()
+                },
+                ..Default::default()
+            }))
+        };
+        let config = signer("testpassword").unwrap();
+        assert_eq!(
+            config.signer().unwrap().address(),
+            "0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266"
+                .parse::<Address>()
+                .unwrap()
+        );
+        // The key is only decrypted when needed, and a reload keeps it.
+        let mut reloaded = signer("wrongpassword").unwrap();
+        assert!(reloaded.signer().is_err());
+        reloaded.keep_signer(&config);
+        assert_eq!(
+            reloaded.signer().unwrap().address(),
+            config.signer().unwrap().address()
+        );
+
+        let password_file = env::temp_dir().join(format!("keystore-{}.txt", std::process::id()));
+        std::fs::write(&password_file, "testpassword\n").unwrap();
+        let config = Config::from_layer(Layer::defaults(None).merge(Layer {
+            signer: SignerLayer {
+                keystore: Some(keystore.clone()),
+                password_file: Some(password_file.clone()),
+                ..Default::default()
+            },
+            ..Default::default()
+        }));
+        std::fs::remove_file(&password_file).unwrap();
+        assert_eq!(
+            config.unwrap().signer().unwrap().address(),
+            "0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266"
+                .parse::<Address>()
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_bundler_table() {
+        let parse = |contents| {
+            let layer = FileConfig::parse(contents).unwrap().layer(None).unwrap();
+            Config::from_layer(Layer::defaults(None).merge(layer))
+        };
+        assert!(parse("[bundler]\nurl = \"http://bundler.local\"").is_err());
+        let config = parse(
+            "[bundler]\nurl = \"http://bundler.local\"\n\
+             account = \"0x0000000000000000000000000000000000000001\"",
+        )
+        .unwrap();
+        let bundler = config.bundler.unwrap();
+        assert_eq!(bundler.entry_point, ENTRY_POINT_V07);
+        assert_eq!(bundler.account, Address::with_last_byte(1));
+    }
+
+    #[test]
+    fn test_unknown_profile() {
+        let file = FileConfig::parse(FILE).unwrap();
+        assert!(file.layer(Some("production")).is_err());
+    }
+
+    #[test]
+    fn test_later_layers_take_precedence() {
+        let file = FileConfig::parse(FILE).unwrap().layer(None).unwrap();
+        let flags = Layer {
+            api_url: Some("http://flag.local/redeemable".to_string()),
+            ..Default::default()
+        };
+        let config = Config::from_layer(Layer::defaults(None).merge(file).merge(flags)).unwrap();
+        assert_eq!(config.api_url.as_str(), "http://flag.local/redeemable");
+    }
+
+    #[test]
+    fn test_network_defaults() {
+        let layer = FileConfig::parse(r#"network = "chiado""#)
+            .unwrap()
+            .layer(None)
+            .unwrap();
+        let config = Config::from_layer(Layer::defaults(layer.network).merge(layer)).unwrap();
+        assert_eq!(config.network, Network::Chiado);
+        assert_eq!(config.chain_id, 10200);
+        assert_eq!(config.rpc_url.as_str(), Network::Chiado.rpc_url());
+        assert_eq!(config.explorer_url.as_str(), Network::Chiado.explorer_url());
+        assert!(config.modules.is_empty());
+    }
+
+    #[test]
+    fn test_interval_from_file() {
+        let layer = FileConfig::parse(r#"interval = "5m""#)
+            .unwrap()
+            .layer(None)
+            .unwrap();
+        assert_eq!(layer.interval, Some(Duration::from_secs(300)));
+
+        let layer = FileConfig::parse(r#"interval = "0s""#)
+            .unwrap()
+            .layer(None)
+            .unwrap();
+        assert!(Config::from_layer(Layer::defaults(None).merge(layer)).is_err());
+    }
+
+    #[test]
+    fn test_api_headers_and_token() {
+        let mut layer = FileConfig::parse(
+            r#"
+            api_token = "secret"
+
+            [api_headers]
+            x-api-key = "key"
+            "#,
+        )
+        .unwrap()
+        .layer(None)
+        .unwrap();
+        let config = Config::from_layer(Layer::defaults(None).merge(layer.clone())).unwrap();
+        assert_eq!(config.api_headers["x-api-key"], "key");
+        assert_eq!(config.api_headers[AUTHORIZATION], "Bearer secret");
+        assert!(config.api_headers[AUTHORIZATION].is_sensitive());
+
+        layer.api_headers = Some(HashMap::from([("bad header".into(), "x".into())]));
+        assert!(Config::from_layer(Layer::defaults(None).merge(layer)).is_err());
+    }
+
+    #[test]
+    fn test_retry_delay() {
+        let config = Config::from_layer(Layer::defaults(None)).unwrap();
+        assert_eq!(config.retry.attempts, 3);
+        let retry = Retry {
+            jitter: false,
+            ..config.retry
+        };
+        assert_eq!(retry.delay(1), Duration::from_secs(1));
+        assert_eq!(retry.delay(3), Duration::from_secs(4));
+        assert_eq!(retry.delay(20), Duration::from_secs(60));
+        let jittered = Retry {
+            jitter: true,
+            ..retry
+        }
+        .delay(2);
+        assert!(jittered > Duration::from_secs(1) && jittered <= Duration::from_secs(2));
+    }
+
+    #[test]
+    fn test_api_quorum_bounds() {
+        let layer = |quorum| Layer {
+            api_replicas: Some(vec!["http://replica.local/redeemable".to_string()]),
+            api_quorum: Some(quorum),
+            ..Layer::defaults(None)
+        };
+        assert_eq!(Config::from_layer(layer(2)).unwrap().api_replicas.len(), 1);
+        assert!(Config::from_layer(layer(0)).is_err());
+        assert!(Config::from_layer(layer(3)).is_err());
+    }
+
+    #[test]
+    fn test_http_limits() {
+        let layer = FileConfig::parse(
+            r#"
+            [http]
+            timeout = "5s"
+            max_response_size = 1024
+            "#,
+        )
+        .unwrap()
+        .layer(None)
+        .unwrap();
+        let config = Config::from_layer(Layer::defaults(None).merge(layer)).unwrap();
+        assert_eq!(
+            config.http_limits,
+            HttpLimits {
+                timeout: Some(Duration::from_secs(5)),
+                max_response_size: 1024,
+            }
+        );
+    }
+
+    #[test]
+    fn test_circles_rpc_rate_limit() {
+        let with_rate = |rate| {
+            Config::from_layer(Layer::defaults(None).merge(Layer {
+                circles_rpc_rate_limit: Some(rate),
+                ..Default::default()
+            }))
+        };
+        assert!(with_rate(2.5).is_ok());
+        assert!(with_rate(0.0).is_err());
+        assert!(with_rate(f64::NAN).is_err());
+    }
+
+    #[test]
+    fn test_circles_rpc_timeout() {
+        let layer = FileConfig::parse(
+            r#"
+            rpc_timeout = "10s"
+            [http]
+            timeout = "5s"
+            "#,
+        )
+        .unwrap()
+        .layer(None)
+        .unwrap();
+        let config = Config::from_layer(Layer::defaults(None).merge(layer.clone())).unwrap();
+        assert_eq!(config.circles_rpc_timeout, Some(Duration::from_secs(5)));
+
+        let config = Config::from_layer(Layer::defaults(None).merge(layer).merge(Layer {
+            circles_rpc_timeout: Some(Duration::from_secs(60)),
+            ..Default::default()
+        }))
+        .unwrap();
+        assert_eq!(config.circles_rpc_timeout, Some(Duration::from_secs(60)));
+        assert_eq!(config.pathfinder_timeout, Some(Duration::from_secs(60)));
+
+        let config = Config::from_layer(Layer {
+            pathfinder_timeout: Some(Duration::from_secs(20)),
+            ..Layer::defaults(None)
+        })
+        .unwrap();
+        assert_eq!(config.pathfinder_timeout, Some(Duration::from_secs(20)));
+    }
+
+    #[test]
+    fn test_invalid_proxy() {
+        let mut layer = Layer::defaults(None);
+        layer.http.proxy = Some("socks5h://127.0.0.1:9050".to_string());
+        assert!(Config::from_layer(layer.clone()).is_ok());
+        layer.http.proxy = Some("not a url".to_string());
+        assert!(Config::from_layer(layer).is_err());
+    }
+
+    #[test]
+    fn test_watcher_detects_changes() {
+        let path = env::temp_dir().join(format!("redeem-rs-watch-{}.toml", std::process::id()));
+        std::fs::write(&path, "").unwrap();
+        let mut watcher = Watcher::new(path.clone());
+        assert!(!watcher.changed());
+        std::fs::remove_file(&path).unwrap();
+        assert!(watcher.changed());
+        assert!(!watcher.changed());
+    }
+
+    #[test]
+    fn test_pause_file_halts() {
+        let path = env::temp_dir().join(format!("redeem-rs-pause-{}", std::process::id()));
+        let mut config = Config::from_layer(Layer::defaults(None)).unwrap();
+        assert_eq!(config.halted(), None);
+        config.pause_file = Some(path.clone());
+        assert_eq!(config.halted(), None);
+        std::fs::write(&path, "").unwrap();
+        assert!(config.halted().is_some());
+        std::fs::remove_file(&path).unwrap();
+        config.paused = true;
+        assert!(config.halted().is_some());
+    }
+
+    #[test]
+    fn test_low_balance_halts_only_when_configured() {
+        let layer = FileConfig::parse("min_balance = \"0.5\"")
+            .unwrap()
+            .layer(None)
+            .unwrap();
+        let mut config = Config::from_layer(Layer::defaults(None).merge(layer)).unwrap();
+        assert_eq!(
+            config.min_balance,
+            Some(U256::from(500_000_000_000_000_000u64))
+        );
+        config.low_balance.store(true, Ordering::Relaxed);
+        assert_eq!(config.halted(), None);
+        config.pause_on_low_balance = true;
+        assert!(config.halted().is_some());
+    }
+
+    #[test]
+    fn test_path_rules() {
+        let layer = FileConfig::parse(
+            r#"
+            [[path_rules]]
+            recipient = "0x6b69683c8897e3d18e74b1ba117b49f80423da5d"
+            subscriber = "0xcf6dc192dc292d5f2789da2db02d6dd4f41f4214"
+            to_tokens = ["0x0000000000000000000000000000000000000002"]
+
+            [[path_rules]]
+            recipient = "0x6b69683c8897e3d18e74b1ba117b49f80423da5d"
+            use_wrapped_balances = true
+            to_tokens = ["0x0000000000000000000000000000000000000001"]
+            "#,
+        )
+        .unwrap()
+        .layer(None)
+        .unwrap();
+        let config = Config::from_layer(Layer::defaults(None).merge(layer.clone())).unwrap();
+        let recipient = "0x6b69683c8897e3d18e74b1ba117b49f80423da5d"
+            .parse()
+            .unwrap();
+        let subscriber = "0xcf6dc192dc292d5f2789da2db02d6dd4f41f4214"
+            .parse()
+            .unwrap();
+
+        let rule = config.path_rule(subscriber, recipient);
+        assert_eq!(rule.use_wrapped_balances, Some(true));
+        assert_eq!(rule.to_tokens, Some(vec![Address::with_last_byte(2)]));
+        let rule = config.path_rule(Address::ZERO, recipient);
+        assert_eq!(rule.to_tokens, Some(vec![Address::with_last_byte(1)]));
+        assert_eq!(
+            config.path_rule(subscriber, Address::ZERO),
+            PathRule::default()
+        );
+
+        let unkeyed = Layer {
+            path_rules: Some(vec![PathRule::default()]),
+            ..Default::default()
+        };
+        assert!(Config::from_layer(Layer::defaults(None).merge(layer).merge(unkeyed)).is_err());
+    }
+
+    #[test]
+    fn test_screen_allow_and_deny() {
+        let layer = FileConfig::parse(
+            r#"
+            [allow]
+            recipients = ["0x6b69683c8897e3d18e74b1ba117b49f80423da5d"]
+
+            [deny]
+            subscribers = ["0xcf6dc192dc292d5f2789da2db02d6dd4f41f4214"]
+            "#,
+        )
+        .unwrap()
+        .layer(None)
+        .unwrap();
+        let config = Config::from_layer(Layer::defaults(None).merge(layer)).unwrap();
+        let mut subscription = RedeemableSubscription {
+            contract_address: Address::ZERO,
+            id: Default::default(),
+            recipient: "0x6b69683c8897e3d18e74b1ba117b49f80423da5d"
+                .parse()
+                .unwrap(),
+            subscriber: Address::ZERO,
+            amount: U256::from(1),
+            periods: 1,
+            category: crate::redeem::Category::Trusted,
+        };
+        assert_eq!(config.screen(&subscription), None);
+        subscription.subscriber = "0xcf6dc192dc292d5f2789da2db02d6dd4f41f4214"
+            .parse()
+            .unwrap();
+        assert!(config.screen(&subscription).unwrap().contains("denied"));
+        subscription.subscriber = Address::ZERO;
+        subscription.recipient = Address::ZERO;
+        assert!(
+            config
+                .screen(&subscription)
+                .unwrap()
+                .contains("not allowed")
+        );
+    }
+}
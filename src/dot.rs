@@ -0,0 +1,88 @@
+use crate::pathfinder;
+use alloy::primitives::{Address, U256, utils::format_ether};
+use circles_pathfinder::PathData;
+use std::fmt::Write;
+
+/// A Graphviz DOT digraph of the transfers along `path_data` from `from` to `to`: one node
+/// per flow vertex, and one edge per transfer labelled with its amount and token owner.
+pub fn render(path_data: &PathData, from: Address, to: Address) -> String {
+    graph(
+        &path_data.flow_vertices,
+        path_data
+            .flow_edges
+            .iter()
+            .map(|edge| U256::from(edge.amount)),
+        &path_data.packed_coordinates,
+        from,
+        to,
+    )
+}
+
+/// The digraph of transfers carrying `amounts` between `vertices`, whose (token owner, from,
+/// to) vertex indices are packed in `packed_coordinates`.
+fn graph(
+    vertices: &[Address],
+    amounts: impl Iterator<Item = U256>,
+    packed_coordinates: &[u8],
+    from: Address,
+    to: Address,
+) -> String {
+    let mut dot = String::from("digraph path {\n    rankdir=LR;\n");
+    for (i, vertex) in vertices.iter().enumerate() {
+        let shape = if *vertex == from || *vertex == to {
+            "doublecircle"
+        } else {
+            "circle"
+        };
+        writeln!(dot, "    v{i} [label=\"{vertex}\", shape={shape}];").unwrap();
+    }
+    for (amount, [token_owner, sender, receiver]) in
+        amounts.zip(pathfinder::coordinates(packed_coordinates))
+    {
+        let owner = vertices
+            .get(token_owner as usize)
+            .map_or_else(|| format!("#{token_owner}"), Address::to_string);
+        writeln!(
+            dot,
+            "    v{sender} -> v{receiver} [label=\"{} CRC\\ntoken {owner}\"];",
+            format_ether(amount)
+        )
+        .unwrap();
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_graph() {
+        let vertices = [1, 2, 3].map(Address::with_last_byte);
+        // 0x..01 sends its own token to 0x..03, which passes on its own to 0x..02.
+        let packed = [0, 0, 0, 0, 0, 2, 0, 2, 0, 2, 0, 1];
+        let amounts = [U256::from(10).pow(U256::from(18)); 2];
+        let dot = graph(
+            &vertices,
+            amounts.into_iter(),
+            &packed,
+            vertices[0],
+            vertices[1],
+        );
+        assert!(dot.starts_with("digraph path {"));
+        assert!(dot.contains(&format!(
+            "v0 [label=\"{}\", shape=doublecircle]",
+            vertices[0]
+        )));
+        assert!(dot.contains(&format!("v2 [label=\"{}\", shape=circle]", vertices[2])));
+        assert!(dot.contains(&format!(
+            "v0 -> v2 [label=\"1.000000000000000000 CRC\\ntoken {}\"]",
+            vertices[0]
+        )));
+        assert!(dot.contains(&format!(
+            "v2 -> v1 [label=\"1.000000000000000000 CRC\\ntoken {}\"]",
+            vertices[2]
+        )));
+    }
+}
@@ -0,0 +1,66 @@
+use std::process::ExitCode;
+
+/// Failures that map to a dedicated process exit code, so orchestration can tell them apart.
+///
+/// | Code | Meaning                               |
+/// |------|---------------------------------------|
+/// | 0    | Every subscription was processed      |
+/// | 1    | Unexpected error                      |
+/// | 2    | Configuration error (or invalid CLI)  |
+/// | 3    | Fetching subscriptions failed         |
+/// | 4    | Some redemptions failed               |
+#[derive(Debug)]
+pub enum Failure {
+    Config(anyhow::Error),
+    Fetch(anyhow::Error),
+    Partial { failed: usize, total: usize },
+}
+
+impl Failure {
+    pub fn exit_code(&self) -> ExitCode {
+        ExitCode::from(match self {
+            Failure::Config(_) => 2,
+            Failure::Fetch(_) => 3,
+            Failure::Partial { .. } => 4,
+        })
+    }
+}
+
+impl std::fmt::Display for Failure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Failure::Config(e) => write!(f, "Configuration error: {e:#}"),
+            Failure::Fetch(e) => write!(f, "Failed to fetch subscriptions: {e:#}"),
+            Failure::Partial { failed, total } => {
+                write!(f, "{failed} of {total} redemptions failed")
+            }
+        }
+    }
+}
+
+impl std::error::Error for Failure {}
+
+/// The exit code for an error returned from `main`.
+pub fn exit_code(error: &(dyn std::error::Error + 'static)) -> ExitCode {
+    match error.downcast_ref::<Failure>() {
+        Some(failure) => failure.exit_code(),
+        None => ExitCode::FAILURE,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exit_codes() {
+        let error: Box<dyn std::error::Error> = Failure::Partial {
+            failed: 1,
+            total: 3,
+        }
+        .into();
+        assert_eq!(exit_code(error.as_ref()), ExitCode::from(4));
+        let error: Box<dyn std::error::Error> = "boom".into();
+        assert_eq!(exit_code(error.as_ref()), ExitCode::FAILURE);
+    }
+}
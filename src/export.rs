@@ -0,0 +1,192 @@
+use alloy::primitives::{Address, Bytes};
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::{
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    config::Config,
+    redeem::{self, PreparedRedemption, RedeemableSubscription},
+    report::Outcome,
+};
+
+/// An unsigned redemption transaction as written for an offline signer.
+#[derive(Debug, Serialize)]
+pub struct UnsignedRedemption<'a> {
+    pub from: Address,
+    #[serde(flatten)]
+    pub transaction: &'a PreparedRedemption,
+    pub subscription: &'a RedeemableSubscription,
+}
+
+/// How prepared redemptions are written.
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum ExportFormat {
+    /// One `<subscription id>.json` unsigned transaction per redemption in the output directory.
+    Files,
+    /// A single Safe Transaction Builder batch file.
+    Safe,
+}
+
+/// A batch importable by the Safe{Wallet} Transaction Builder app.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SafeBatch {
+    pub version: &'static str,
+    pub chain_id: String,
+    pub created_at: u128,
+    pub meta: SafeBatchMeta,
+    pub transactions: Vec<SafeTransaction>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SafeBatchMeta {
+    pub name: String,
+    pub description: String,
+    pub created_from_safe_address: Address,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SafeTransaction {
+    pub to: Address,
+    pub value: String,
+    pub data: Bytes,
+    pub contract_method: Option<()>,
+    pub contract_inputs_values: Option<()>,
+}
+
+impl SafeBatch {
+    pub fn new(safe: Address, chain_id: u64, transactions: &[PreparedRedemption]) -> Self {
+        Self {
+            version: "1.0",
+            chain_id: chain_id.to_string(),
+            created_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis(),
+            meta: SafeBatchMeta {
+                name: format!("Redeem {} subscriptions", transactions.len()),
+                description: "Generated by redeem-rs".to_string(),
+                created_from_safe_address: safe,
+            },
+            transactions: transactions
+                .iter()
+                .map(|t| SafeTransaction {
+                    to: t.to,
+                    value: t.value.to_string(),
+                    data: t.data.clone(),
+                    contract_method: None,
+                    contract_inputs_values: None,
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Prepares every subscription for `from` and writes the unsigned transactions to `out` in
+/// the given format.
+pub async fn export_calldata(
+    config: &Config,
+    subscriptions: Vec<RedeemableSubscription>,
+    from: Address,
+    out: &Path,
+    format: ExportFormat,
+) -> Result<Vec<Outcome>> {
+    if format == ExportFormat::Files {
+        std::fs::create_dir_all(out)
+            .with_context(|| format!("Failed to create {}", out.display()))?;
+    }
+    let mut outcomes = Vec::with_capacity(subscriptions.len());
+    let mut batch = Vec::new();
+    for subscription in subscriptions {
+        let id = subscription.id;
+        let value = Some(subscription.value());
+        let outcome = match redeem::prepare_payment(config, &subscription, from).await {
+            Ok(transaction) => {
+                let outcome = Outcome::dry_run(id, transaction.gas);
+                if format == ExportFormat::Files {
+                    let path = out.join(format!("{id}.json"));
+                    let unsigned = UnsignedRedemption {
+                        from,
+                        transaction: &transaction,
+                        subscription: &subscription,
+                    };
+                    write(&path, &unsigned)?;
+                }
+                batch.push(transaction);
+                outcome
+            }
+            Err(e) => Outcome::failed_or_rejected(id, e),
+        };
+        outcomes.push(outcome.with_value(value));
+    }
+    if format == ExportFormat::Safe {
+        write(out, &SafeBatch::new(from, config.chain_id, &batch))?;
+    }
+    Ok(outcomes)
+}
+
+fn write(path: &Path, value: &impl Serialize) -> Result<()> {
+    std::fs::write(path, serde_json::to_string_pretty(value)?)
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    tracing::info!("Wrote {}", path.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::redeem::Category;
+    use alloy::primitives::{B256, U256};
+
+    #[test]
+    fn test_serialize_unsigned_redemption() {
+        let transaction = PreparedRedemption {
+            chain_id: 100,
+            to: Address::repeat_byte(1),
+            value: U256::ZERO,
+            data: Bytes::from_static(&[0xab]),
+            gas: 21_000,
+        };
+        let subscription = RedeemableSubscription {
+            contract_address: Address::repeat_byte(1),
+            id: B256::ZERO,
+            recipient: Address::ZERO,
+            subscriber: Address::ZERO,
+            amount: U256::from(1),
+            periods: 1,
+            category: Category::Untrusted,
+        };
+        let json = serde_json::to_value(UnsignedRedemption {
+            from: Address::ZERO,
+            transaction: &transaction,
+            subscription: &subscription,
+        })
+        .unwrap();
+        assert_eq!(json["chain_id"], 100);
+        assert_eq!(json["data"], "0xab");
+        assert_eq!(json["gas"], 21_000);
+        assert_eq!(json["subscription"]["category"], "untrusted");
+    }
+
+    #[test]
+    fn test_safe_batch() {
+        let transaction = PreparedRedemption {
+            chain_id: 100,
+            to: Address::repeat_byte(1),
+            value: U256::ZERO,
+            data: Bytes::from_static(&[0xab]),
+            gas: 21_000,
+        };
+        let json =
+            serde_json::to_value(SafeBatch::new(Address::ZERO, 100, &[transaction])).unwrap();
+        assert_eq!(json["chainId"], "100");
+        assert_eq!(json["transactions"][0]["value"], "0");
+        assert_eq!(json["transactions"][0]["data"], "0xab");
+        assert!(json["transactions"][0]["contractMethod"].is_null());
+    }
+}
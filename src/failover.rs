@@ -0,0 +1,202 @@
+use alloy::{
+    rpc::json_rpc::{RequestPacket, ResponsePacket},
+    transports::{TransportError, TransportErrorKind, TransportFut, http::Http},
+};
+use reqwest::{Client, Url};
+use std::{
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+use tokio::time::{Duration, Instant};
+use tower::Service;
+
+/// Weight of the latest outcome in an endpoint's health score.
+const SCORE_WEIGHT: f64 = 0.2;
+
+/// Cap on the cooldown of an endpoint that keeps failing, as a multiple of the configured one.
+const MAX_BACKOFF: u32 = 16;
+
+/// A transport over several execution RPCs. Each request goes to the healthiest endpoint that
+/// is not cooling down and moves on to the next one if it fails or times out. A failed
+/// endpoint cools down for `cooldown`, doubled with every further consecutive failure; once
+/// every endpoint is cooling down, the one due back first is tried first.
+#[derive(Debug, Clone)]
+pub struct Failover {
+    endpoints: Arc<Vec<Endpoint>>,
+    cooldown: Duration,
+}
+
+#[derive(Debug)]
+struct Endpoint {
+    url: Url,
+    transport: Http<Client>,
+    health: Mutex<Health>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Health {
+    /// Moving average of recent outcomes, from 0 (all failed) to 1 (all succeeded).
+    score: f64,
+    failures: u32,
+    cooling_until: Option<Instant>,
+}
+
+impl Failover {
+    /// Endpoints are preferred in the order given while they are equally healthy.
+    pub fn new(client: &Client, urls: impl IntoIterator<Item = Url>, cooldown: Duration) -> Self {
+        let endpoints = urls
+            .into_iter()
+            .map(|url| Endpoint {
+                transport: Http::with_client(client.clone(), url.clone()),
+                url,
+                health: Mutex::new(Health {
+                    score: 1.0,
+                    failures: 0,
+                    cooling_until: None,
+                }),
+            })
+            .collect();
+        Self {
+            endpoints: Arc::new(endpoints),
+            cooldown,
+        }
+    }
+
+    /// Indices of the endpoints in the order they are tried.
+    fn order(&self) -> Vec<usize> {
+        let now = Instant::now();
+        let mut order = self
+            .endpoints
+            .iter()
+            .map(|endpoint| {
+                let health = *endpoint.health.lock().unwrap();
+                (
+                    health.cooling_until.filter(|&until| until > now),
+                    health.score,
+                )
+            })
+            .enumerate()
+            .collect::<Vec<_>>();
+        // Stable, so equally healthy endpoints keep their configured order.
+        order.sort_by(|(_, (a_until, a_score)), (_, (b_until, b_score))| {
+            a_until.cmp(b_until).then(b_score.total_cmp(a_score))
+        });
+        order.into_iter().map(|(index, _)| index).collect()
+    }
+
+    async fn request(self, request: RequestPacket) -> Result<ResponsePacket, TransportError> {
+        let mut last_error = None;
+        for index in self.order() {
+            let endpoint = &self.endpoints[index];
+            match endpoint.transport.clone().call(request.clone()).await {
+                Ok(response) => {
+                    endpoint.succeeded();
+                    return Ok(response);
+                }
+                Err(e) => {
+                    let cooldown = endpoint.failed(self.cooldown);
+                    tracing::warn!(
+                        "RPC {} failed, cooling down for {cooldown:?}: {e}",
+                        endpoint.url
+                    );
+                    last_error = Some(e);
+                }
+            }
+        }
+        Err(last_error.unwrap_or_else(|| TransportErrorKind::custom_str("no RPC configured")))
+    }
+}
+
+impl Endpoint {
+    fn succeeded(&self) {
+        let mut health = self.health.lock().unwrap();
+        if health.failures > 0 {
+            tracing::info!("RPC {} recovered", self.url);
+        }
+        health.score += SCORE_WEIGHT * (1.0 - health.score);
+        health.failures = 0;
+        health.cooling_until = None;
+    }
+
+    /// Records a failure and returns how long the endpoint now cools down.
+    fn failed(&self, cooldown: Duration) -> Duration {
+        let mut health = self.health.lock().unwrap();
+        health.score -= SCORE_WEIGHT * health.score;
+        health.failures = health.failures.saturating_add(1);
+        let backoff = 1u32
+            .checked_shl(health.failures - 1)
+            .unwrap_or(MAX_BACKOFF)
+            .min(MAX_BACKOFF);
+        let cooldown = cooldown * backoff;
+        health.cooling_until = Some(Instant::now() + cooldown);
+        cooldown
+    }
+}
+
+impl Service<RequestPacket> for Failover {
+    type Response = ResponsePacket;
+    type Error = TransportError;
+    type Future = TransportFut<'static>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: RequestPacket) -> Self::Future {
+        Box::pin(self.clone().request(request))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_order() {
+        let urls =
+            ["http://a.local", "http://b.local", "http://c.local"].map(|url| url.parse().unwrap());
+        let failover = Failover::new(&Client::new(), urls, Duration::from_secs(30));
+        assert_eq!(failover.order(), [0, 1, 2]);
+
+        // A failed endpoint goes last, and cools down longer on every further failure.
+        assert_eq!(
+            failover.endpoints[0].failed(failover.cooldown),
+            Duration::from_secs(30)
+        );
+        assert_eq!(failover.order(), [1, 2, 0]);
+        assert_eq!(
+            failover.endpoints[1].failed(failover.cooldown),
+            Duration::from_secs(30)
+        );
+        assert_eq!(
+            failover.endpoints[1].failed(failover.cooldown),
+            Duration::from_secs(60)
+        );
+        assert_eq!(failover.order(), [2, 0, 1]);
+
+        // Back from its cooldown, it stays behind endpoints with a better record.
+        failover.endpoints[0].health.lock().unwrap().cooling_until = None;
+        assert_eq!(failover.order(), [2, 0, 1]);
+        failover.endpoints[0].succeeded();
+        assert_eq!(failover.endpoints[0].health.lock().unwrap().failures, 0);
+    }
+
+    #[test]
+    fn test_backoff_stays_capped() {
+        let url = "http://a.local".parse().unwrap();
+        let failover = Failover::new(&Client::new(), [url], Duration::from_secs(1));
+        let endpoint = &failover.endpoints[0];
+        for _ in 0..40 {
+            endpoint.failed(failover.cooldown);
+        }
+        assert_eq!(
+            endpoint.failed(failover.cooldown),
+            Duration::from_secs(MAX_BACKOFF.into())
+        );
+        endpoint.health.lock().unwrap().failures = u32::MAX;
+        assert_eq!(
+            endpoint.failed(failover.cooldown),
+            Duration::from_secs(MAX_BACKOFF.into())
+        );
+    }
+}
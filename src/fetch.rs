@@ -1,24 +1,863 @@
+use crate::cli::FilterArgs;
+use crate::config::Config;
 use crate::redeem::RedeemableSubscription;
-use anyhow::{Context, Result};
-use reqwest::{Client, Url};
+use alloy::primitives::B256;
+use anyhow::Result;
+use futures::future::join_all;
+use reqwest::{
+    Client, RequestBuilder, Response, StatusCode, Url,
+    header::{ETAG, HeaderMap, HeaderValue, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED},
+};
+use serde::de::DeserializeOwned;
+use std::collections::{HashMap, HashSet};
+use std::sync::{
+    Mutex,
+    atomic::{AtomicU64, Ordering},
+};
+use tokio::time::{Duration, Instant};
 
-pub async fn fetch_redeemable_subscriptions(api_url: Url) -> Result<Vec<RedeemableSubscription>> {
-    let client = Client::new();
+/// Fetches the redeemable subscriptions from the configured indexer, sending the configured
+/// headers and bearer token, and passing `filter` and the module address as query parameters.
+/// With `api_page_size` set, pages are requested with `limit`/`offset` until the indexer
+/// returns a short page, or one with no new subscriptions, for at most `MAX_PAGES` pages.
+/// Every page is buffered before any subscription is returned, and a subscription listed on
+/// several pages is returned once.
+pub async fn fetch_redeemable_subscriptions(
+    config: &Config,
+    filter: &FilterArgs,
+) -> Result<Vec<RedeemableSubscription>> {
+    fetch_redeemable_since(config, filter, None).await
+}
+
+/// Like [`fetch_redeemable_subscriptions`], but with `since` (a Unix timestamp) only asks for
+/// subscriptions that became redeemable after it.
+///
+/// With `api_replicas` configured, all replicas are queried concurrently and a subscription
+/// is kept if at least `api_quorum` of them list it (by default any one, i.e. the union).
+///
+/// Every call is recorded in `config.fetch_metrics` and, if configured, written to
+/// `metrics_file`.
+pub async fn fetch_redeemable_since(
+    config: &Config,
+    filter: &FilterArgs,
+    since: Option<u64>,
+) -> Result<Vec<RedeemableSubscription>> {
+    let started = Instant::now();
+    let result = fetch_all(config, filter, since).await;
+    config.fetch_metrics.record(
+        started.elapsed(),
+        match &result {
+            Ok(subscriptions) => Ok(subscriptions.len()),
+            Err(e) => Err(e
+                .chain()
+                .find_map(|cause| cause.downcast_ref::<FetchError>())
+                .map_or("other", FetchError::class)),
+        },
+    );
+    crate::metrics::write(config);
+    result
+}
+
+async fn fetch_all(
+    config: &Config,
+    filter: &FilterArgs,
+    since: Option<u64>,
+) -> Result<Vec<RedeemableSubscription>> {
+    if config.api_replicas.is_empty() {
+        return fetch_from(config, &config.api_url, filter, since).await;
+    }
+    let urls: Vec<&Url> = std::iter::once(&config.api_url)
+        .chain(&config.api_replicas)
+        .collect();
+    let results = join_all(
+        urls.iter()
+            .map(|url| fetch_from(config, url, filter, since)),
+    )
+    .await;
+    let mut lists = Vec::new();
+    let mut last_error = None;
+    for (url, result) in urls.iter().zip(results) {
+        match result {
+            Ok(list) => lists.push(list),
+            Err(e) => {
+                tracing::warn!("Indexer replica {url} failed: {e:#}");
+                last_error = Some(e);
+            }
+        }
+    }
+    if lists.len() < config.api_quorum {
+        let error = last_error.unwrap_or_else(|| anyhow::anyhow!("no indexer replica answered"));
+        return Err(error.context(format!(
+            "Only {} of {} indexer replicas answered, {} required",
+            lists.len(),
+            urls.len(),
+            config.api_quorum
+        )));
+    }
+    Ok(quorum(lists, config.api_quorum))
+}
+
+/// The subscriptions listed by at least `quorum` of `lists`, in first-seen order.
+fn quorum(lists: Vec<Vec<RedeemableSubscription>>, quorum: usize) -> Vec<RedeemableSubscription> {
+    let mut counts: HashMap<B256, usize> = HashMap::new();
+    let mut seen = Vec::new();
+    for list in lists {
+        let mut ids = HashSet::new();
+        for subscription in list {
+            if !ids.insert(subscription.id) {
+                continue;
+            }
+            let count = counts.entry(subscription.id).or_default();
+            if *count == 0 {
+                seen.push(subscription);
+            }
+            *count += 1;
+        }
+    }
+    let agreed: Vec<_> = seen
+        .into_iter()
+        .filter(|s| counts[&s.id] >= quorum)
+        .collect();
+    if agreed.len() != counts.len() {
+        tracing::warn!(
+            "{} subscriptions were listed by fewer than {quorum} indexer replicas",
+            counts.len() - agreed.len()
+        );
+    }
+    agreed
+}
+
+/// Most pages requested in one fetch, should an indexer never return a short page.
+const MAX_PAGES: usize = 1000;
+
+async fn fetch_from(
+    config: &Config,
+    api_url: &Url,
+    filter: &FilterArgs,
+    since: Option<u64>,
+) -> Result<Vec<RedeemableSubscription>> {
+    let client = &config.http;
+    let mut api_url = query_url(config, api_url, filter);
+    if let Some(since) = since {
+        api_url
+            .query_pairs_mut()
+            .append_pair("since", &since.to_string());
+    }
+    let Some(limit) = config.api_page_size else {
+        return Ok(dedupe(parse_entries(
+            fetch_page(client, config, api_url).await?,
+        )));
+    };
+
+    // Pages are counted in entries as the indexer sent them, malformed ones included, so
+    // that skipping one neither ends pagination early nor shifts the offset.
+    let mut entries = Vec::new();
+    let mut ids = HashSet::new();
+    for number in 1.. {
+        let page = fetch_page(client, config, page_url(&api_url, limit, entries.len())).await?;
+        let last = page.len() < limit;
+        // Entries without an id are malformed; they cannot tell whether the page is new.
+        let new = page
+            .iter()
+            .filter(|entry| match entry.get("id") {
+                Some(id) => ids.insert(id.to_string()),
+                None => true,
+            })
+            .count();
+        entries.extend(page);
+        if last {
+            break;
+        }
+        if new == 0 {
+            tracing::warn!("Indexer page {number} repeats earlier subscriptions; stopping there");
+            break;
+        }
+        if number == MAX_PAGES {
+            tracing::warn!("Stopped after {MAX_PAGES} pages of subscriptions");
+            break;
+        }
+    }
+    let subscriptions = dedupe(parse_entries(entries));
+    tracing::debug!("Fetched {} subscriptions in total", subscriptions.len());
 
-    let response = client
-        .get(api_url)
+    Ok(subscriptions)
+}
+
+/// `subscriptions` with only the first of those sharing an id.
+fn dedupe(mut subscriptions: Vec<RedeemableSubscription>) -> Vec<RedeemableSubscription> {
+    let mut seen = HashSet::new();
+    subscriptions.retain(|subscription| seen.insert(subscription.id));
+    subscriptions
+}
+
+/// Major version of the indexer API this client understands.
+const SUPPORTED_API_VERSION: u64 = 1;
+
+/// Queries the indexer's health endpoint, failing if it is unreachable, unhealthy, or reports
+/// an incompatible API `version` in its JSON body. Returns the reported version, if any.
+pub async fn check_health(config: &Config, health_url: &Url) -> Result<Option<String>, FetchError> {
+    config.api_rate_limit.acquire().await;
+    let response = request(config, config.http.get(health_url.clone()))
         .send()
         .await
-        .context("Failed to send HTTP request")?;
+        .map_err(|e| FetchError::network(health_url, e))?;
+    let status = response.status();
+    if !status.is_success() {
+        return Err(FetchError::Status {
+            url: health_url.clone(),
+            status,
+        });
+    }
+    let body: Option<serde_json::Value> = read_json(response, config.http_limits.max_response_size)
+        .await
+        .ok();
+    let version = body
+        .as_ref()
+        .and_then(|body| body.get("version"))
+        .and_then(|version| version.as_str())
+        .map(str::to_string);
+    if let Some(version) = &version {
+        check_api_version(version)?;
+    }
+    Ok(version)
+}
+
+fn check_api_version(version: &str) -> Result<(), FetchError> {
+    let major = version
+        .trim_start_matches('v')
+        .split('.')
+        .next()
+        .and_then(|major| major.parse::<u64>().ok());
+    match major {
+        Some(SUPPORTED_API_VERSION) => Ok(()),
+        _ => Err(FetchError::UnsupportedVersion(version.to_string())),
+    }
+}
+
+/// Spaces out requests to a service, such as the indexer or the Circles RPC, so that
+/// aggressive polling or many concurrent redemptions cannot hammer it.
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    /// What is requested, for the log.
+    service: &'static str,
+    min_interval: Duration,
+    next: Mutex<Option<Instant>>,
+    throttled: AtomicU64,
+}
+
+impl RateLimiter {
+    pub fn new(service: &'static str, min_interval: Duration) -> Self {
+        Self {
+            service,
+            min_interval,
+            ..Default::default()
+        }
+    }
+
+    /// How many requests have had to wait so far.
+    pub fn throttled(&self) -> u64 {
+        self.throttled.load(Ordering::Relaxed)
+    }
+
+    /// Waits until a request may be sent, at least `min_interval` after the previous one.
+    pub async fn acquire(&self) {
+        let now = Instant::now();
+        let wait = {
+            let Ok(mut next) = self.next.lock() else {
+                return;
+            };
+            let at = next.map_or(now, |next| next.max(now));
+            *next = Some(at + self.min_interval);
+            at - now
+        };
+        if !wait.is_zero() {
+            let throttled = self.throttled.fetch_add(1, Ordering::Relaxed) + 1;
+            tracing::info!(
+                throttled_total = throttled,
+                "Throttling {} request for {}",
+                self.service,
+                humantime::format_duration(wait)
+            );
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+/// Remembers each indexer URL's last response and its `ETag`/`Last-Modified` validators, so
+/// that unchanged responses come back as an empty `304 Not Modified` and are not downloaded
+/// again.
+#[derive(Debug, Default)]
+pub struct ConditionalCache(Mutex<HashMap<Url, Validated>>);
+
+#[derive(Debug)]
+struct Validated {
+    etag: Option<HeaderValue>,
+    last_modified: Option<HeaderValue>,
+    entries: Vec<serde_json::Value>,
+}
 
-    if !response.status().is_success() {
-        return Err(anyhow::anyhow!("HTTP error! status: {}", response.status()));
+impl ConditionalCache {
+    /// `If-None-Match`/`If-Modified-Since` headers for a request to `url`.
+    fn conditions(&self, url: &Url) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        if let Ok(cache) = self.0.lock()
+            && let Some(validated) = cache.get(url)
+        {
+            if let Some(etag) = &validated.etag {
+                headers.insert(IF_NONE_MATCH, etag.clone());
+            }
+            if let Some(last_modified) = &validated.last_modified {
+                headers.insert(IF_MODIFIED_SINCE, last_modified.clone());
+            }
+        }
+        headers
     }
 
-    let subscriptions = response
-        .json::<Vec<RedeemableSubscription>>()
+    /// The entries last returned for `url`.
+    fn get(&self, url: &Url) -> Option<Vec<serde_json::Value>> {
+        let cache = self.0.lock().ok()?;
+        cache.get(url).map(|validated| validated.entries.clone())
+    }
+
+    /// Remembers a response if it carries validators.
+    fn store(&self, url: &Url, headers: &HeaderMap, entries: &[serde_json::Value]) {
+        let etag = headers.get(ETAG).cloned();
+        let last_modified = headers.get(LAST_MODIFIED).cloned();
+        if let Ok(mut cache) = self.0.lock() {
+            if etag.is_none() && last_modified.is_none() {
+                cache.remove(url);
+                return;
+            }
+            cache.insert(
+                url.clone(),
+                Validated {
+                    etag,
+                    last_modified,
+                    entries: entries.to_vec(),
+                },
+            );
+        }
+    }
+}
+
+/// Why a request to the indexer failed, so callers can branch on the class of failure.
+#[derive(Debug, thiserror::Error)]
+pub enum FetchError {
+    /// The request could not be sent or its body not read: connection, timeout or TLS errors.
+    #[error("Failed to send HTTP request to {url}")]
+    Network {
+        url: Url,
+        #[source]
+        source: reqwest::Error,
+    },
+    /// The server answered with a non-success status.
+    #[error("HTTP error from {url}: status {status}")]
+    Status { url: Url, status: StatusCode },
+    /// The body was larger than `http.max_response_size`.
+    #[error("Response exceeds the {max_size} byte limit")]
+    TooLarge { max_size: usize },
+    /// The body was not the expected JSON.
+    #[error("Failed to deserialize JSON")]
+    Decode(#[from] serde_json::Error),
+    /// The health endpoint reports an API version this client does not understand.
+    #[error("Indexer reports API version {0}, but only {SUPPORTED_API_VERSION}.x is supported")]
+    UnsupportedVersion(String),
+}
+
+impl FetchError {
+    fn network(url: &Url, source: reqwest::Error) -> Self {
+        FetchError::Network {
+            url: url.clone(),
+            source,
+        }
+    }
+
+    /// A short label for the kind of failure, used as a metrics label.
+    pub fn class(&self) -> &'static str {
+        match self {
+            FetchError::Network { .. } => "network",
+            FetchError::Status { .. } => "status",
+            FetchError::TooLarge { .. } => "too_large",
+            FetchError::Decode(_) => "decode",
+            FetchError::UnsupportedVersion(_) => "unsupported_version",
+        }
+    }
+
+    /// Whether retrying the same request may succeed: connection errors, timeouts, 429 and 5xx.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            FetchError::Network { source, .. } => {
+                source.is_connect() || source.is_timeout() || source.is_request()
+            }
+            FetchError::Status { status, .. } => {
+                status.is_server_error() || *status == StatusCode::TOO_MANY_REQUESTS
+            }
+            FetchError::TooLarge { .. }
+            | FetchError::Decode(_)
+            | FetchError::UnsupportedVersion(_) => false,
+        }
+    }
+}
+
+/// Adds the indexer headers and the per-request timeout.
+pub fn request(config: &Config, builder: RequestBuilder) -> RequestBuilder {
+    timed(config, builder.headers(config.api_headers.clone()))
+}
+
+/// Applies the per-request timeout, but none of the indexer's headers; for other services.
+pub fn timed(config: &Config, builder: RequestBuilder) -> RequestBuilder {
+    match config.http_limits.timeout {
+        Some(timeout) => builder.timeout(timeout),
+        None => builder,
+    }
+}
+
+/// Reads and deserializes a JSON body, refusing bodies larger than `max_size` bytes.
+pub async fn read_json<T: DeserializeOwned>(
+    mut response: Response,
+    max_size: usize,
+) -> Result<T, FetchError> {
+    if response
+        .content_length()
+        .is_some_and(|length| length > max_size as u64)
+    {
+        return Err(FetchError::TooLarge { max_size });
+    }
+    let url = response.url().clone();
+    let mut body = Vec::new();
+    while let Some(chunk) = response
+        .chunk()
         .await
-        .context("Failed to deserialize JSON")?;
+        .map_err(|e| FetchError::network(&url, e))?
+    {
+        if body.len() + chunk.len() > max_size {
+            return Err(FetchError::TooLarge { max_size });
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(serde_json::from_slice(&body)?)
+}
 
-    Ok(subscriptions)
+/// Fetches one page, retrying [retryable](FetchError::is_retryable) failures with exponential
+/// backoff.
+async fn fetch_page(
+    client: &Client,
+    config: &Config,
+    url: Url,
+) -> Result<Vec<serde_json::Value>, FetchError> {
+    let mut attempt = 1;
+    loop {
+        match try_fetch_page(client, config, url.clone()).await {
+            Ok(entries) => return Ok(entries),
+            Err(e) if e.is_retryable() && attempt < config.retry.attempts => {
+                let delay = config.retry.delay(attempt);
+                tracing::warn!(
+                    "Indexer request failed (attempt {attempt}/{}), retrying in {}: {:#}",
+                    config.retry.attempts,
+                    humantime::format_duration(delay),
+                    anyhow::Error::new(e)
+                );
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// A single request, returning the entries of the page unparsed.
+async fn try_fetch_page(
+    client: &Client,
+    config: &Config,
+    url: Url,
+) -> Result<Vec<serde_json::Value>, FetchError> {
+    config.api_rate_limit.acquire().await;
+    tracing::debug!("Fetching redeemable subscriptions from {url}");
+
+    let response = request(config, client.get(url.clone()))
+        .headers(config.api_conditional.conditions(&url))
+        .send()
+        .await
+        .map_err(|e| FetchError::network(&url, e))?;
+
+    let status = response.status();
+    if status == StatusCode::NOT_MODIFIED
+        && let Some(entries) = config.api_conditional.get(&url)
+    {
+        tracing::debug!("Indexer response not modified");
+        return Ok(entries);
+    }
+    if !status.is_success() {
+        return Err(FetchError::Status { url, status });
+    }
+
+    let headers = response.headers().clone();
+    let entries: Vec<serde_json::Value> =
+        read_json(response, config.http_limits.max_response_size).await?;
+    tracing::debug!("Fetched {} entries", entries.len());
+    config.api_conditional.store(&url, &headers, &entries);
+
+    Ok(entries)
+}
+
+/// Fields of [`RedeemableSubscription`] and other fields the indexer is known to send.
+const KNOWN_FIELDS: &[&str] = &[
+    "contract_address",
+    "id",
+    "recipient",
+    "subscriber",
+    "amount",
+    "periods",
+    "category",
+    "next_redeem_at",
+];
+
+/// Parses each entry on its own, so one malformed entry is logged and skipped rather than
+/// failing the whole batch. Unknown fields are logged at debug level to spot indexer changes.
+pub fn parse_entries(entries: Vec<serde_json::Value>) -> Vec<RedeemableSubscription> {
+    entries
+        .into_iter()
+        .enumerate()
+        .filter_map(|(index, entry)| {
+            if let Some(object) = entry.as_object() {
+                for field in object
+                    .keys()
+                    .filter(|k| !KNOWN_FIELDS.contains(&k.as_str()))
+                {
+                    tracing::debug!("Unknown field {field:?} in indexer entry {index}");
+                }
+            }
+            match serde_json::from_value(entry.clone()) {
+                Ok(subscription) => Some(subscription),
+                Err(e) => {
+                    tracing::warn!("Skipping malformed indexer entry {index}: {e}: {entry}");
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+/// The indexer URL with the filter and, if there is only one, the module address as query
+/// parameters. Subscriptions on other modules are left out after fetching.
+fn query_url(config: &Config, api_url: &Url, filter: &FilterArgs) -> Url {
+    let mut url = api_url.clone();
+    let mut query = filter.query();
+    if let [module] = config.modules[..] {
+        query.push(("module_address", module.to_string()));
+    }
+    if !query.is_empty() {
+        url.query_pairs_mut().extend_pairs(query);
+    }
+    url
+}
+
+/// `api_url` with `limit` and `offset` query parameters added.
+fn page_url(api_url: &Url, limit: usize, offset: usize) -> Url {
+    let mut url = api_url.clone();
+    url.query_pairs_mut()
+        .append_pair("limit", &limit.to_string())
+        .append_pair("offset", &offset.to_string());
+    url
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Layer, RetryLayer};
+    use alloy::primitives::U256;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    const SUBSCRIPTION: &str = r#"{
+        "contract_address": "0xcebe4b6d50ce877a9689ce4516fe96911e099a78",
+        "id": "0x50ede65601819b8885dc3dbf4676204fcd318c26b8281d82af20f69d55b4ca75",
+        "subscriber": "0xcf6dc192dc292d5f2789da2db02d6dd4f41f4214",
+        "recipient": "0x6b69683c8897e3d18e74b1ba117b49f80423da5d",
+        "amount": "10000000000000000",
+        "periods": 5,
+        "category": "trusted"
+    }"#;
+
+    /// A mock indexer answering one request per reply (status, body, delay before answering),
+    /// in order. Returns its URL and the server, which must outlive the test.
+    async fn serve(replies: Vec<(u16, String, Duration)>) -> (Url, MockServer) {
+        let server = MockServer::start().await;
+        for (status, body, delay) in replies {
+            Mock::given(method("GET"))
+                .and(path("/redeemable"))
+                .respond_with(
+                    ResponseTemplate::new(status)
+                        .set_body_raw(body, "application/json")
+                        .set_delay(delay),
+                )
+                .up_to_n_times(1)
+                .mount(&server)
+                .await;
+        }
+        let url = format!("{}/redeemable", server.uri()).parse().unwrap();
+        (url, server)
+    }
+
+    /// The URLs of the requests `server` received, in order.
+    async fn requests(server: &MockServer) -> Vec<Url> {
+        let requests = server.received_requests().await.unwrap();
+        requests.into_iter().map(|request| request.url).collect()
+    }
+
+    /// Defaults pointed at `url`, with two quick attempts per request.
+    fn config(url: &Url, layer: Layer) -> Config {
+        let layer = Layer {
+            api_url: Some(url.to_string()),
+            retry: RetryLayer {
+                attempts: Some(2),
+                backoff: Some(Duration::from_millis(10)),
+                jitter: Some(false),
+            },
+            ..layer
+        };
+        Config::from_layer(Layer::defaults(None).merge(layer)).unwrap()
+    }
+
+    async fn fetch(config: &Config) -> Result<Vec<RedeemableSubscription>> {
+        fetch_redeemable_subscriptions(config, &FilterArgs::default()).await
+    }
+
+    fn fetch_error(error: &anyhow::Error) -> &FetchError {
+        error.downcast_ref().expect("a FetchError")
+    }
+
+    #[tokio::test]
+    async fn test_fetch_success() {
+        let (url, server) = serve(vec![(200, format!("[{SUBSCRIPTION}]"), Duration::ZERO)]).await;
+        let subscriptions = fetch(&config(&url, Layer::default())).await.unwrap();
+        assert_eq!(subscriptions.len(), 1);
+        assert_eq!(subscriptions[0].periods, 5);
+        assert_eq!(requests(&server).await[0].path(), "/redeemable");
+    }
+
+    #[tokio::test]
+    async fn test_fetch_pages() {
+        let (url, server) = serve(vec![
+            (200, format!("[{SUBSCRIPTION}]"), Duration::ZERO),
+            (200, "[]".to_string(), Duration::ZERO),
+        ])
+        .await;
+        let layer = Layer {
+            api_page_size: Some(1),
+            ..Default::default()
+        };
+        let subscriptions = fetch(&config(&url, layer)).await.unwrap();
+        assert_eq!(subscriptions.len(), 1);
+        let requests = requests(&server).await;
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].query(), Some("limit=1&offset=0"));
+        assert_eq!(requests[1].query(), Some("limit=1&offset=1"));
+    }
+
+    #[tokio::test]
+    async fn test_fetch_pages_with_malformed_entry() {
+        let (url, server) = serve(vec![
+            (
+                200,
+                format!(r#"[{SUBSCRIPTION}, {{"id": "not a subscription"}}]"#),
+                Duration::ZERO,
+            ),
+            (200, format!("[{SUBSCRIPTION}]"), Duration::ZERO),
+        ])
+        .await;
+        let layer = Layer {
+            api_page_size: Some(2),
+            ..Default::default()
+        };
+        let subscriptions = fetch(&config(&url, layer)).await.unwrap();
+        // The second page lists the first subscription again.
+        assert_eq!(subscriptions.len(), 1);
+        let requests = requests(&server).await;
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[1].query(), Some("limit=2&offset=2"));
+    }
+
+    #[tokio::test]
+    async fn test_fetch_pages_stops_on_repeated_page() {
+        // An indexer that ignores `offset` returns the same full page every time.
+        let page = (200, format!("[{SUBSCRIPTION}]"), Duration::ZERO);
+        let (url, server) = serve(vec![page.clone(), page.clone(), page]).await;
+        let layer = Layer {
+            api_page_size: Some(1),
+            ..Default::default()
+        };
+        let subscriptions = fetch(&config(&url, layer)).await.unwrap();
+        assert_eq!(subscriptions.len(), 1);
+        assert_eq!(requests(&server).await.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_malformed_json() {
+        let (url, _server) = serve(vec![(200, "not json".to_string(), Duration::ZERO)]).await;
+        let error = fetch(&config(&url, Layer::default())).await.unwrap_err();
+        assert!(matches!(fetch_error(&error), FetchError::Decode(_)));
+    }
+
+    #[tokio::test]
+    async fn test_fetch_retries_server_errors() {
+        let (url, server) = serve(vec![
+            (503, String::new(), Duration::ZERO),
+            (200, format!("[{SUBSCRIPTION}]"), Duration::ZERO),
+        ])
+        .await;
+        let subscriptions = fetch(&config(&url, Layer::default())).await.unwrap();
+        assert_eq!(subscriptions.len(), 1);
+        assert_eq!(requests(&server).await.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_gives_up_on_server_errors() {
+        let (url, server) = serve(vec![
+            (500, String::new(), Duration::ZERO),
+            (502, String::new(), Duration::ZERO),
+        ])
+        .await;
+        let error = fetch(&config(&url, Layer::default())).await.unwrap_err();
+        assert!(matches!(
+            fetch_error(&error),
+            FetchError::Status {
+                status: StatusCode::BAD_GATEWAY,
+                ..
+            }
+        ));
+        assert_eq!(requests(&server).await.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_does_not_retry_client_errors() {
+        let (url, server) = serve(vec![(404, String::new(), Duration::ZERO)]).await;
+        let error = fetch(&config(&url, Layer::default())).await.unwrap_err();
+        assert_eq!(fetch_error(&error).class(), "status");
+        assert_eq!(requests(&server).await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_timeout() {
+        let slow = Duration::from_millis(500);
+        let (url, _server) = serve(vec![
+            (200, "[]".to_string(), slow),
+            (200, "[]".to_string(), slow),
+        ])
+        .await;
+        let mut layer = Layer::default();
+        layer.http.timeout = Some(Duration::from_millis(50));
+        let error = fetch(&config(&url, layer)).await.unwrap_err();
+        match fetch_error(&error) {
+            FetchError::Network { source, .. } => assert!(source.is_timeout()),
+            other => panic!("expected a timeout, got {other}"),
+        }
+    }
+
+    #[test]
+    fn test_parse_entries_skips_malformed() {
+        let entries = serde_json::json!([
+            {
+                "contract_address": "0xcebe4b6d50ce877a9689ce4516fe96911e099a78",
+                "id": "0x50ede65601819b8885dc3dbf4676204fcd318c26b8281d82af20f69d55b4ca75",
+                "subscriber": "0xcf6dc192dc292d5f2789da2db02d6dd4f41f4214",
+                "recipient": "0x6b69683c8897e3d18e74b1ba117b49f80423da5d",
+                "amount": "10000000000000000",
+                "periods": 5,
+                "category": "trusted",
+                "new_field": true
+            },
+            { "id": "not a subscription" }
+        ]);
+        let entries = serde_json::from_value(entries).unwrap();
+        assert_eq!(parse_entries(entries).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_rate_limiter() {
+        let limiter = RateLimiter::new("indexer", Duration::from_millis(20));
+        let start = Instant::now();
+        limiter.acquire().await;
+        limiter.acquire().await;
+        limiter.acquire().await;
+        assert!(start.elapsed() >= Duration::from_millis(40));
+        assert_eq!(limiter.throttled(), 2);
+    }
+
+    #[test]
+    fn test_conditional_cache() {
+        let cache = ConditionalCache::default();
+        let url: Url = "http://indexer.local/redeemable".parse().unwrap();
+        assert!(cache.conditions(&url).is_empty());
+
+        let mut headers = HeaderMap::new();
+        headers.insert(ETAG, HeaderValue::from_static("\"v1\""));
+        cache.store(&url, &headers, &[]);
+        assert_eq!(cache.conditions(&url)[IF_NONE_MATCH], "\"v1\"");
+        assert_eq!(cache.get(&url).map(|s| s.len()), Some(0));
+
+        cache.store(&url, &HeaderMap::new(), &[]);
+        assert!(cache.get(&url).is_none());
+    }
+
+    #[test]
+    fn test_fetch_error_is_retryable() {
+        let status = |status| FetchError::Status {
+            url: "http://indexer.local/redeemable".parse().unwrap(),
+            status,
+        };
+        assert!(status(StatusCode::BAD_GATEWAY).is_retryable());
+        assert!(status(StatusCode::TOO_MANY_REQUESTS).is_retryable());
+        assert!(!status(StatusCode::NOT_FOUND).is_retryable());
+        assert!(!FetchError::TooLarge { max_size: 1 }.is_retryable());
+    }
+
+    #[test]
+    fn test_check_api_version() {
+        assert!(check_api_version("1.4.2").is_ok());
+        assert!(check_api_version("v1").is_ok());
+        assert!(check_api_version("2.0.0").is_err());
+        assert!(check_api_version("latest").is_err());
+    }
+
+    #[test]
+    fn test_quorum() {
+        let subscription = |id: u8| RedeemableSubscription {
+            contract_address: Default::default(),
+            id: B256::repeat_byte(id),
+            recipient: Default::default(),
+            subscriber: Default::default(),
+            amount: U256::from(1),
+            periods: 1,
+            category: crate::redeem::Category::Untrusted,
+        };
+        let lists = || {
+            vec![
+                vec![subscription(1), subscription(2)],
+                vec![subscription(2), subscription(3), subscription(3)],
+            ]
+        };
+        let ids =
+            |list: Vec<RedeemableSubscription>| -> Vec<_> { list.iter().map(|s| s.id).collect() };
+        assert_eq!(
+            ids(quorum(lists(), 1)),
+            vec![
+                B256::repeat_byte(1),
+                B256::repeat_byte(2),
+                B256::repeat_byte(3)
+            ]
+        );
+        assert_eq!(ids(quorum(lists(), 2)), vec![B256::repeat_byte(2)]);
+    }
+
+    #[test]
+    fn test_page_url_keeps_existing_query() {
+        let url: Url = "http://indexer.local/redeemable?module=0xabc"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            page_url(&url, 100, 200).as_str(),
+            "http://indexer.local/redeemable?module=0xabc&limit=100&offset=200"
+        );
+    }
 }
@@ -0,0 +1,96 @@
+use crate::{cli::FilterArgs, redeem::RedeemableSubscription};
+
+impl FilterArgs {
+    /// Whether `subscription` passes the recipient, subscriber and minimum amount filters.
+    pub fn matches(&self, subscription: &RedeemableSubscription) -> bool {
+        (self.recipient.is_empty() || self.recipient.contains(&subscription.recipient))
+            && (self.subscriber.is_empty() || self.subscriber.contains(&subscription.subscriber))
+            && self
+                .min_amount
+                .is_none_or(|min| subscription.value() >= min)
+    }
+
+    /// Query parameters asking the indexer to apply the same filters server-side. The
+    /// filters are still applied locally, so indexers that ignore them remain supported.
+    pub fn query(&self) -> Vec<(&'static str, String)> {
+        let recipients = self.recipient.iter().map(|r| ("recipient", r.to_string()));
+        let subscribers = self
+            .subscriber
+            .iter()
+            .map(|s| ("subscriber", s.to_string()));
+        let min_amount = self.min_amount.map(|min| ("min_amount", min.to_string()));
+        recipients.chain(subscribers).chain(min_amount).collect()
+    }
+
+    /// Keeps the matching subscriptions, up to `max_count` of them.
+    pub fn apply(&self, subscriptions: Vec<RedeemableSubscription>) -> Vec<RedeemableSubscription> {
+        let total = subscriptions.len();
+        let filtered: Vec<_> = subscriptions
+            .into_iter()
+            .filter(|s| self.matches(s))
+            .take(self.max_count.unwrap_or(usize::MAX))
+            .collect();
+        if filtered.len() != total {
+            tracing::info!("Filtered {total} subscriptions down to {}", filtered.len());
+        }
+        filtered
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::redeem::Category;
+    use alloy::primitives::{Address, B256, U256};
+
+    fn subscription(recipient: u8, amount: u64) -> RedeemableSubscription {
+        RedeemableSubscription {
+            contract_address: Address::ZERO,
+            id: B256::repeat_byte(recipient),
+            recipient: Address::repeat_byte(recipient),
+            subscriber: Address::ZERO,
+            amount: U256::from(amount),
+            periods: 2,
+            category: Category::Trusted,
+        }
+    }
+
+    #[test]
+    fn test_apply_filters() {
+        let subscriptions = vec![
+            subscription(1, 10),
+            subscription(2, 50),
+            subscription(2, 100),
+        ];
+        let filter = FilterArgs {
+            recipient: vec![Address::repeat_byte(2)],
+            min_amount: Some(U256::from(100)),
+            ..Default::default()
+        };
+        let filtered = filter.apply(subscriptions.clone());
+        assert_eq!(filtered.len(), 2);
+
+        let filter = FilterArgs {
+            max_count: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(filter.apply(subscriptions).len(), 1);
+    }
+
+    #[test]
+    fn test_query() {
+        let filter = FilterArgs {
+            recipient: vec![Address::repeat_byte(2)],
+            min_amount: Some(U256::from(100)),
+            ..Default::default()
+        };
+        assert_eq!(
+            filter.query(),
+            vec![
+                ("recipient", Address::repeat_byte(2).to_string()),
+                ("min_amount", "100".to_string())
+            ]
+        );
+        assert!(FilterArgs::default().query().is_empty());
+    }
+}
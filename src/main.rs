@@ -1,43 +1,1059 @@
+mod bundler;
+mod cassette;
+mod check;
+mod circles;
+mod cli;
+mod config;
+mod dot;
+mod exit;
+mod export;
+mod failover;
 mod fetch;
+mod filter;
+mod metrics;
+mod mnemonic;
+mod network;
+mod nonce;
+mod pathfinder;
+mod progress;
 mod redeem;
+mod relay;
+mod remote;
+mod report;
+mod safe;
+mod solver;
+mod source;
+mod state;
+mod stream;
+mod tui;
+mod ws;
 
-use alloy::signers::local::PrivateKeySigner;
-use reqwest::Url;
-use std::env;
-use tracing_subscriber::FmtSubscriber;
+use alloy::primitives::{Address, U256, hex, utils::format_ether};
+use anyhow::anyhow;
+use clap::Parser;
+use cli::{
+    Cli, Command, ConfigArgs, ConfigCommand, ExportArgs, FilterArgs, OutputFormat, RedeemArgs,
+    RunArgs,
+};
+use config::Config;
+use exit::Failure;
+use futures::StreamExt;
+use progress::Progress;
+use redeem::{BatchError, PreparedRedemption, RedeemableSubscription, Sent};
+use report::{Outcome, Status, Summary};
+use source::{Cached, Source, SubscriptionSource};
+use state::StateFile;
+use std::collections::HashMap;
+use std::process::ExitCode;
+use std::sync::{
+    Arc,
+    atomic::{AtomicBool, Ordering},
+};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::time::{Instant, MissedTickBehavior, timeout_at};
+use tracing::Instrument;
+use tracing_subscriber::{EnvFilter, FmtSubscriber};
+use tui::Dashboard;
 
-struct Config {
-    signer: PrivateKeySigner,
-    api_url: Url,
+/// Settings that apply to every command processing subscriptions.
+#[derive(Clone)]
+struct Options {
+    dry_run: bool,
+    interactive: bool,
+    output: OutputFormat,
+    dashboard: Option<Dashboard>,
+    filter: FilterArgs,
+    /// Block explorer that redeemed outcomes link to.
+    explorer_url: reqwest::Url,
+    /// Set on shutdown, after which no further redemption is started.
+    stopping: Arc<AtomicBool>,
 }
 
-impl Config {
-    fn from_env() -> Result<Self, Box<dyn std::error::Error>> {
-        Ok(Self {
-            signer: env::var("PK")?.parse()?,
-            api_url: env::var("API_URL")
-                .unwrap_or_else(|_| "http://localhost:3030/redeemable".to_string())
-                .parse()?,
-        })
+impl Options {
+    fn emit(&self, outcome: &Outcome) {
+        if let Some(dashboard) = &self.dashboard {
+            dashboard.finish(outcome);
+        }
+        report::emit(outcome, self.output, &self.explorer_url);
+    }
+
+    fn stopping(&self) -> bool {
+        self.stopping.load(Ordering::Relaxed)
     }
 }
 
 #[tokio::main(flavor = "current_thread")]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
+async fn main() -> ExitCode {
+    match try_main().await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            exit::exit_code(e.as_ref())
+        }
+    }
+}
+
+async fn try_main() -> Result<(), Box<dyn std::error::Error>> {
     dotenv::dotenv().ok();
+    let cli = Cli::parse();
+    let tui = matches!(cli.command(), Command::Run(RunArgs { tui: true, .. }));
+    // RUST_LOG takes precedence for fine-grained, per-module filtering.
+    let filter =
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(cli.log_filter()));
     let subscriber = FmtSubscriber::builder()
-        // TODO: Change to DEBUG! https://github.com/deluXtreme/redeem-rs/issues/6
-        .with_max_level(tracing::Level::INFO)
+        .with_env_filter(filter)
+        // Logs would garble the dashboard, so they are dropped while it is shown.
+        .with_writer(move || -> Box<dyn std::io::Write> {
+            if tui {
+                Box::new(std::io::sink())
+            } else {
+                Box::new(progress::LogWriter)
+            }
+        })
         .finish();
     tracing::subscriber::set_global_default(subscriber).expect("Failed to set tracing subscriber");
 
-    let config = Config::from_env()?;
-    let subscriptions = fetch::fetch_redeemable_subscriptions(config.api_url).await?;
+    if let Command::Config(ConfigCommand::Check) = cli.command() {
+        return config_check(&cli.config).await;
+    }
+    let mut config = Config::load(&cli.config).map_err(Failure::Config)?;
+    tracing::debug!("Using {} (chain {})", config.network, config.chain_id);
+    if matches!(
+        cli.command(),
+        Command::Run(_) | Command::Simulate(_) | Command::Export(_) | Command::Redeem(_)
+    ) {
+        for &module in &config.modules {
+            let found = redeem::check_module(&config, module)
+                .await
+                .map_err(|e| Failure::Config(e.context("Invalid SubscriptionModule")))?;
+            tracing::debug!("Using SubscriptionModule {found}");
+        }
+    }
+    let source = Cached::new(Source::new(&config), config.cache_ttl);
+    let options = Options {
+        dry_run: cli.dry_run,
+        interactive: cli.interactive,
+        output: cli.output,
+        dashboard: tui.then(Dashboard::default),
+        filter: match cli.command() {
+            Command::Run(args) => args.filter,
+            _ => FilterArgs::default(),
+        },
+        explorer_url: config.explorer_url.clone(),
+        stopping: Default::default(),
+    };
+    match cli.command() {
+        Command::Run(args) => match args.interval.or(config.interval) {
+            Some(interval) => {
+                config.interval = Some(interval);
+                daemon(&cli.config, config, args.interval, &options).await
+            }
+            None => run(&config, &source, &options).await,
+        },
+        Command::List(filter) => list(&config, &source, &filter).await,
+        Command::Simulate(filter) => simulate(&config, &source, &filter, &options).await,
+        Command::Export(args) => export(&config, &source, args, &options).await,
+        Command::Redeem(args) => redeem_one(&config, &source, args, &options).await,
+        Command::Path {
+            from,
+            to,
+            amount,
+            dot,
+        } => path(&config, from, to, amount, dot.as_deref()).await,
+        Command::Accounts { count } => accounts(&config, count).await,
+        Command::Config(ConfigCommand::Check) => unreachable!("handled before loading config"),
+    }
+}
+
+async fn run(
+    config: &Config,
+    source: &impl SubscriptionSource,
+    options: &Options,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let deadline = config.max_runtime.map(|budget| Instant::now() + budget);
+    config.executor().map_err(Failure::Config)?;
+    if let Some(dashboard) = &options.dashboard {
+        match redeem::executor_balance(config).await {
+            Ok((executor, balance)) => dashboard.set_balance(executor, balance),
+            Err(e) => tracing::warn!("Failed to fetch executor balance: {e}"),
+        }
+    }
+    if let Some(health_url) = &config.api_health_url {
+        fetch::check_health(config, health_url)
+            .await
+            .map_err(|e| Failure::Fetch(e.into()))?;
+    }
+    let mut state = open_state(config, options)?;
+    let started = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let fetch = source.fetch(
+        config,
+        &options.filter,
+        state.as_ref().and_then(|file| file.state().since),
+    );
+    let subscriptions = match deadline {
+        Some(deadline) => timeout_at(deadline, fetch)
+            .await
+            .unwrap_or_else(|_| Err(anyhow!("run deadline exceeded"))),
+        None => fetch.await,
+    }
+    .map_err(Failure::Fetch)?;
     tracing::info!("Found {} subscriptions", subscriptions.len());
+    let fetched = match &mut state {
+        Some(file) => {
+            let current = file.state();
+            let fetched = current.unprocessed(current.merge(subscriptions), config.dedupe_window);
+            if let Err(e) = file.start(started, &fetched, config.dedupe_window) {
+                tracing::error!("{e:#}");
+            }
+            fetched
+        }
+        None => subscriptions,
+    };
+    let subscriptions = options.filter.apply(fetched);
+    let summary = redeem_all(config, subscriptions, deadline, options, state.as_mut()).await;
+    if options.output == OutputFormat::Text && options.dashboard.is_none() {
+        print!("{}", summary.table());
+    }
+    match summary.count(Status::Failed) {
+        0 => Ok(()),
+        failed => Err(Failure::Partial {
+            failed,
+            total: summary.outcomes.len(),
+        }
+        .into()),
+    }
+}
+
+/// Opens `config.state_file`, if one is set.
+fn open_state(config: &Config, options: &Options) -> Result<Option<StateFile>, Failure> {
+    config
+        .state_file
+        .as_deref()
+        .map(|path| StateFile::open(path, config.state_backend, options.dry_run))
+        .transpose()
+        .map_err(Failure::Config)
+}
+
+/// Processes `subscriptions` in order until `deadline`, after which the rest are skipped.
+/// Each outcome is recorded in `state` as soon as it completes.
+async fn redeem_all(
+    config: &Config,
+    subscriptions: Vec<RedeemableSubscription>,
+    deadline: Option<Instant>,
+    options: &Options,
+    mut state: Option<&mut StateFile>,
+) -> Summary {
+    if let Some(dashboard) = &options.dashboard {
+        dashboard.set_pending(&subscriptions);
+    }
+    // Each batch starts from the chain's pending nonce and counts up locally from there.
+    config.nonces.reset().await;
+    if !options.dry_run {
+        check_balance(config).await;
+    }
+    // A failed redemption is recorded and the batch carries on with the rest.
+    let mut summary = Summary::default();
+    let progress = (options.output == OutputFormat::Text
+        && options.dashboard.is_none()
+        && !options.interactive)
+        .then(|| Progress::new(subscriptions.len()));
+    // Prompts must not interleave, so interactive runs stay sequential and unbatched.
+    let (concurrency, batch_size) = if options.interactive {
+        (1, 1)
+    } else if options.dry_run {
+        (config.concurrency, 1)
+    } else {
+        (config.concurrency, config.batch_size)
+    };
+    let inputs: HashMap<_, _> = subscriptions
+        .iter()
+        .map(|subscription| (subscription.id, subscription.clone()))
+        .collect();
+    let chunks: Vec<_> = subscriptions
+        .chunks(batch_size)
+        .map(<[_]>::to_vec)
+        .collect();
+    let mut outcomes = futures::stream::iter(chunks)
+        .map(|chunk| redeem_chunk(config, chunk, deadline, options))
+        .buffer_unordered(concurrency);
+    while let Some(chunk) = outcomes.next().await {
+        for outcome in chunk {
+            options.emit(&outcome);
+            if let Some(file) = state.as_deref_mut()
+                && let Err(e) = file.record(inputs.get(&outcome.id), &outcome)
+            {
+                tracing::error!("{e:#}");
+            }
+            summary.push(outcome);
+        }
+        if let Some(progress) = &progress {
+            progress.update(&summary);
+        }
+        // Re-checked as the batch spends, so it pauses before running dry halfway through.
+        if !options.dry_run {
+            check_balance(config).await;
+        }
+    }
+    drop(progress);
+    summary
+}
+
+/// Runs [`redeem::check_balance`], logging rather than failing if the balance is unavailable.
+async fn check_balance(config: &Config) {
+    if let Err(e) = redeem::check_balance(config).await {
+        tracing::warn!("Failed to check the executor balance: {e}");
+    }
+}
+
+/// Redeems the subscriptions in `chunk` together in one Multicall3 transaction if there are
+/// several and none would revert; otherwise, and for subscriptions excluded by configuration,
+/// one by one.
+async fn redeem_chunk(
+    config: &Config,
+    chunk: Vec<RedeemableSubscription>,
+    deadline: Option<Instant>,
+    options: &Options,
+) -> Vec<Outcome> {
+    let (eligible, excluded): (Vec<_>, Vec<_>) = chunk
+        .into_iter()
+        .partition(|subscription| exclusion(config, subscription).is_none());
+    let mut outcomes = Vec::new();
+    let individually = if eligible.len() > 1
+        && deadline.is_none_or(|deadline| Instant::now() < deadline)
+        && !options.stopping()
+        && let Some(batched) = redeem_batch(config, &eligible, deadline, options).await
+    {
+        outcomes.extend(batched);
+        excluded
+    } else {
+        eligible.into_iter().chain(excluded).collect()
+    };
+    for subscription in individually {
+        outcomes.push(redeem_within(config, subscription, deadline, options).await);
+    }
+    outcomes
+}
+
+/// Sends `subscriptions` as one Multicall3 transaction. Returns `None`, so that they are
+/// redeemed one by one instead, if any of them cannot be prepared or would revert.
+async fn redeem_batch(
+    config: &Config,
+    subscriptions: &[RedeemableSubscription],
+    deadline: Option<Instant>,
+    options: &Options,
+) -> Option<Vec<Outcome>> {
+    let span = tracing::info_span!("batch", size = subscriptions.len());
+    let batch = async {
+        for subscription in subscriptions {
+            if let Some(dashboard) = &options.dashboard {
+                dashboard.start(subscription.id);
+            }
+            match redeem::check_due(config, subscription).await {
+                Ok(None) => {}
+                Ok(Some(reason)) => {
+                    tracing::info!("Not batching, {}: {reason}", subscription.id);
+                    return None;
+                }
+                Err(e) => {
+                    tracing::info!("Not batching, {} cannot be checked: {e}", subscription.id);
+                    return None;
+                }
+            }
+        }
+        let mut calls = Vec::with_capacity(subscriptions.len());
+        let batch: Vec<_> = subscriptions.iter().collect();
+        let found = std::time::Instant::now();
+        for (subscription, data) in subscriptions
+            .iter()
+            .zip(redeem::redeem_data_batch(config, &batch).await)
+        {
+            match data {
+                Ok(data) => calls.push((subscription, data)),
+                Err(e) => {
+                    tracing::info!("Not batching, {} cannot be prepared: {e}", subscription.id);
+                    return None;
+                }
+            }
+        }
+        if calls.iter().any(|(_, data)| !data.is_empty()) && redeem::path_expired(config, found) {
+            // Redeemed one by one, each gets a fresh path.
+            tracing::info!("Not batching, the paths found are too old to send");
+            return None;
+        }
+        match redeem::send_batch(config, &calls).await {
+            Ok(Some(sent)) => {
+                // One receipt covers the batch; each redemption is credited an equal share.
+                let share = Sent {
+                    gas_used: sent.gas_used.map(|gas| gas / subscriptions.len() as u64),
+                    ..sent
+                };
+                Some(
+                    subscriptions
+                        .iter()
+                        .map(|s| Outcome::redeemed(s.id, share).with_value(Some(s.value())))
+                        .collect(),
+                )
+            }
+            Ok(None) => None,
+            Err(BatchError::Unsent(e)) => {
+                tracing::warn!("Batch failed, redeeming one by one: {e}");
+                None
+            }
+            Err(BatchError::Sent(e)) => {
+                // The Multicall may still be mined; redeeming one by one could pay twice.
+                tracing::error!("Batch of {} failed after sending: {e}", subscriptions.len());
+                Some(
+                    subscriptions
+                        .iter()
+                        .map(|s| {
+                            Outcome {
+                                error_kind: Some(e.kind()),
+                                ..Outcome::failed(s.id, &e)
+                            }
+                            .with_tx_hash(e.tx_hash())
+                            .with_value(Some(s.value()))
+                        })
+                        .collect(),
+                )
+            }
+        }
+    }
+    .instrument(span);
+    match deadline {
+        Some(deadline) => timeout_at(deadline, batch).await.unwrap_or_else(|_| {
+            Some(
+                subscriptions
+                    .iter()
+                    .map(|s| {
+                        Outcome::failed(
+                            s.id,
+                            "run deadline exceeded mid-redemption; the transaction may have \
+                             been sent",
+                        )
+                        .with_value(Some(s.value()))
+                    })
+                    .collect(),
+            )
+        }),
+        None => batch.await,
+    }
+}
+
+/// Why configuration excludes `subscription` from being redeemed right now, if it does.
+fn exclusion(config: &Config, subscription: &RedeemableSubscription) -> Option<String> {
+    if let Some(reason) = config.halted() {
+        Some(reason)
+    } else if let Some(reason) = config.screen(subscription) {
+        Some(reason)
+    } else if !config.modules.is_empty() && !config.modules.contains(&subscription.contract_address)
+    {
+        Some("not on a configured SubscriptionModule".to_string())
+    } else if redeem::needs_path(config, subscription) {
+        config.pathfinder_breaker.open()
+    } else {
+        None
+    }
+}
+
+/// Processes one subscription unless the run's deadline has passed or the daemon is shutting
+/// down, failing it if the deadline passes mid-redemption.
+async fn redeem_within(
+    config: &Config,
+    subscription: RedeemableSubscription,
+    deadline: Option<Instant>,
+    options: &Options,
+) -> Outcome {
+    let id = subscription.id;
+    let value = Some(subscription.value());
+    if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+        return Outcome::skipped(id, "run deadline exceeded").with_value(value);
+    }
+    if options.stopping() {
+        return Outcome::skipped(id, "shutting down").with_value(value);
+    }
+    if let Some(dashboard) = &options.dashboard {
+        dashboard.start(id);
+    }
+    match deadline {
+        Some(deadline) => timeout_at(deadline, process(config, subscription, options))
+            .await
+            .unwrap_or_else(|_| {
+                Outcome::failed(
+                    id,
+                    "run deadline exceeded mid-redemption; the transaction may have been sent",
+                )
+                .with_value(value)
+            }),
+        None => process(config, subscription, options).await,
+    }
+}
+
+/// Redeems a single subscription, or only prepares it for a dry run.
+async fn process(
+    config: &Config,
+    subscription: RedeemableSubscription,
+    options: &Options,
+) -> Outcome {
+    let span = tracing::info_span!("subscription", id = %subscription.id);
+    process_inner(config, subscription, options)
+        .instrument(span)
+        .await
+}
+
+async fn process_inner(
+    config: &Config,
+    subscription: RedeemableSubscription,
+    options: &Options,
+) -> Outcome {
+    let id = subscription.id;
+    let value = Some(subscription.value());
+    let outcome = if let Some(reason) = exclusion(config, &subscription) {
+        Outcome::skipped(id, reason)
+    } else if let Some(outcome) = not_due(config, &subscription).await {
+        outcome
+    } else if options.dry_run {
+        tracing::info!("Preparing {:?} redemption", subscription.category);
+        tracing::debug!("{:#?}", subscription);
+        match redeem::dry_run_payment(config, subscription).await {
+            Ok(prepared) => {
+                tracing::info!("Would call {}", prepared.to);
+                tracing::debug!("Calldata: {}", prepared.data);
+                Outcome::dry_run(id, prepared.gas)
+            }
+            Err(e) => Outcome::failed_or_rejected(id, e),
+        }
+    } else if options.interactive {
+        match redeem::dry_run_payment(config, subscription.clone()).await {
+            Ok(prepared) if confirm(&subscription, &prepared).await => {
+                match redeem::send_prepared(config, &prepared).await {
+                    Ok(sent) => Outcome::redeemed(id, sent),
+                    Err(e) => Outcome::failed(id, e),
+                }
+            }
+            Ok(_) => Outcome::skipped(id, "declined by operator"),
+            Err(e) => Outcome::failed_or_rejected(id, e),
+        }
+    } else {
+        tracing::info!("Redeeming {:?} subscription", subscription.category);
+        tracing::debug!("{:#?}", subscription);
+        if config.verify {
+            redeem_verified(config, &subscription).await
+        } else {
+            let repath = redeem::needs_path(config, &subscription);
+            match redeem::retrying(config, repath, || {
+                redeem::redeem_payment(config, subscription.clone())
+            })
+            .await
+            {
+                Ok(sent) => Outcome::redeemed(id, sent),
+                Err(e) => Outcome::redemption_failed(id, e),
+            }
+        }
+    };
+    outcome.with_value(value)
+}
+
+/// Skipped if the module says `subscription` is not due yet (it is tried again next run), or
+/// failed if the module could not be asked.
+async fn not_due(config: &Config, subscription: &RedeemableSubscription) -> Option<Outcome> {
+    match redeem::check_due(config, subscription).await {
+        Ok(None) => None,
+        Ok(Some(reason)) => Some(Outcome::skipped(subscription.id, reason)),
+        Err(e) => Some(Outcome::failed(subscription.id, e)),
+    }
+}
+
+/// Redeems only if the chain agrees with the indexer; a stale or bogus entry is skipped.
+async fn redeem_verified(config: &Config, subscription: &RedeemableSubscription) -> Outcome {
+    let id = subscription.id;
+    let found = std::time::Instant::now();
+    let (data, fallback) = match redeem::redeem_data_or_fallback(config, subscription).await {
+        Ok(data) => data,
+        Err(e) => return Outcome::redemption_failed(id, redeem::RedeemError::Pathfinding(e)),
+    };
+    let from = match config.caller() {
+        Ok(from) => from,
+        Err(e) => return Outcome::failed(id, e),
+    };
+    match redeem::check_onchain(config, subscription, &data, from).await {
+        Ok(Some(reason)) => match fallback {
+            // Refused without a path; that says nothing about the subscription itself.
+            Some(e) => Outcome::redemption_failed(id, redeem::RedeemError::Pathfinding(e)),
+            None => Outcome::rejected(id, reason),
+        },
+        Ok(None) => match redeem::retrying(config, false, || {
+            redeem::send_redeem(config, subscription, data.clone(), found)
+        })
+        .await
+        {
+            Ok(sent) => Outcome::redeemed(id, sent),
+            Err(e) => Outcome::redemption_failed(id, e),
+        },
+        Err(e) => Outcome::failed(id, e),
+    }
+}
+
+/// Runs a redemption pass every interval until interrupted.
+///
+/// Errors from a single pass are logged rather than propagated, so a flaky indexer or RPC
+/// does not take the daemon down. Edits to the config file are picked up before the next
+/// pass; an `--interval` flag keeps precedence over the file. With a `stream_url`,
+/// subscriptions pushed by the indexer are redeemed as soon as they arrive, deduplicated
+/// against the state file like polled ones; the stream is reopened when a reload changes
+/// `stream_url` or the indexer headers. Ctrl-C or SIGTERM stops the daemon once the
+/// redemptions in flight have finished and the state has been saved.
+async fn daemon(
+    args: &ConfigArgs,
+    mut config: Config,
+    interval_flag: Option<Duration>,
+    options: &Options,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut interval = config.interval.expect("daemon requires an interval");
+    tracing::info!("Polling every {}", humantime::format_duration(interval));
+    let mut ticker = poll_interval(interval);
+    let mut watcher = config.path.clone().map(config::Watcher::new);
+    let (quit_tx, mut quit_rx) = tokio::sync::mpsc::channel(1);
+    if let Some(dashboard) = &options.dashboard {
+        dashboard.spawn(quit_tx);
+    }
+    let mut source = Cached::new(Source::new(&config), config.cache_ttl);
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+    let (pushed_tx, mut pushed_rx) = tokio::sync::mpsc::channel(16);
+    let mut listener = spawn_stream(&config, &pushed_tx);
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {
+                if watcher.as_mut().is_some_and(|w| w.changed()) {
+                    match Config::load(args) {
+                        Ok(mut reloaded) => {
+                            tracing::info!("Reloaded configuration");
+                            reloaded.keep_signer(&config);
+                            if reloaded.stream_url != config.stream_url
+                                || reloaded.api_headers != config.api_headers
+                            {
+                                if let Some(listener) = listener.take() {
+                                    listener.abort();
+                                }
+                                listener = spawn_stream(&reloaded, &pushed_tx);
+                            }
+                            config = reloaded;
+                            source = Cached::new(Source::new(&config), config.cache_ttl);
+                        }
+                        Err(e) => tracing::error!("Keeping previous configuration: {e:#}"),
+                    }
+                    let reloaded = interval_flag.or(config.interval).unwrap_or(interval);
+                    if reloaded != interval {
+                        interval = reloaded;
+                        tracing::info!("Polling every {}", humantime::format_duration(interval));
+                        ticker = poll_interval(interval);
+                        ticker.tick().await;
+                    }
+                }
+                let (result, stopped) = until_shutdown(
+                    run(&config, &source, options),
+                    shutdown.as_mut(),
+                    options,
+                )
+                .await;
+                if let Err(e) = result {
+                    tracing::error!("Redemption pass failed: {e}");
+                }
+                if stopped {
+                    return Ok(());
+                }
+            }
+            Some(pushed) = pushed_rx.recv() => {
+                let (result, stopped) = until_shutdown(
+                    redeem_pushed(&config, pushed, options),
+                    shutdown.as_mut(),
+                    options,
+                )
+                .await;
+                match result {
+                    Ok(summary) => match summary.count(Status::Failed) {
+                        0 => {}
+                        failed => tracing::error!("{failed} pushed redemptions failed"),
+                    },
+                    Err(e) => tracing::error!("Pushed redemptions failed: {e}"),
+                }
+                if stopped {
+                    return Ok(());
+                }
+            }
+            () = &mut shutdown => {
+                tracing::info!("Shutting down");
+                return Ok(());
+            }
+            Some(()) = quit_rx.recv() => return Ok(()),
+        }
+    }
+}
+
+/// Listens to `config.stream_url`, if set, forwarding the subscriptions it pushes to `tx`.
+fn spawn_stream(
+    config: &Config,
+    tx: &tokio::sync::mpsc::Sender<Vec<RedeemableSubscription>>,
+) -> Option<tokio::task::JoinHandle<()>> {
+    let url = config.stream_url.clone()?;
+    Some(stream::spawn(
+        config.http.clone(),
+        url,
+        config.api_headers.clone(),
+        tx.clone(),
+    ))
+}
+
+/// Redeems subscriptions pushed by the indexer like a poll pass does: those settled within
+/// the dedupe window are ignored, and the rest and their outcomes are recorded in the state
+/// file.
+async fn redeem_pushed(
+    config: &Config,
+    pushed: Vec<RedeemableSubscription>,
+    options: &Options,
+) -> Result<Summary, Failure> {
+    let mut state = open_state(config, options)?;
+    let pushed = match &mut state {
+        Some(file) => {
+            let pushed = file.state().unprocessed(pushed, config.dedupe_window);
+            if let Err(e) = file.push(&pushed) {
+                tracing::error!("{e:#}");
+            }
+            pushed
+        }
+        None => pushed,
+    };
+    let subscriptions = options.filter.apply(pushed);
+    tracing::info!("Indexer pushed {} subscriptions", subscriptions.len());
+    Ok(redeem_all(config, subscriptions, None, options, state.as_mut()).await)
+}
+
+/// Drives `pass` to completion. If `shutdown` fires meanwhile, no further redemption is started:
+/// those in flight finish, the rest are skipped and the pass saves its state as usual. Returns
+/// whether the daemon should then exit. A second Ctrl-C exits without waiting.
+async fn until_shutdown<T>(
+    pass: impl Future<Output = T>,
+    shutdown: std::pin::Pin<&mut impl Future<Output = ()>>,
+    options: &Options,
+) -> (T, bool) {
+    tokio::pin!(pass);
+    tokio::select! {
+        result = &mut pass => return (result, false),
+        () = shutdown => {}
+    }
+    tracing::info!("Shutting down once the redemptions in flight have finished");
+    options.stopping.store(true, Ordering::Relaxed);
+    tokio::select! {
+        result = pass => (result, true),
+        _ = tokio::signal::ctrl_c() => {
+            tracing::warn!("Interrupted again; exiting without waiting");
+            std::process::exit(130);
+        }
+    }
+}
+
+/// Resolves on Ctrl-C or, on Unix, SIGTERM.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = terminate.recv() => {}
+                }
+                return;
+            }
+            Err(e) => tracing::warn!("Failed to listen for SIGTERM: {e}"),
+        }
+    }
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+fn poll_interval(interval: Duration) -> tokio::time::Interval {
+    let mut ticker = tokio::time::interval(interval);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    ticker
+}
+
+/// Shows a prepared redemption and asks the operator whether to send it.
+async fn confirm(subscription: &RedeemableSubscription, prepared: &PreparedRedemption) -> bool {
+    let prompt = format!(
+        "\nSubscription {}\n  subscriber: {}\n  recipient:  {}\n  amount:     {} x {} periods ({:?})\n  gas:        {}\nSend? [y/N] ",
+        subscription.id,
+        subscription.subscriber,
+        subscription.recipient,
+        subscription.amount,
+        subscription.periods,
+        subscription.category,
+        prepared.gas
+    );
+    tokio::task::spawn_blocking(move || {
+        eprint!("{prompt}");
+        let mut answer = String::new();
+        std::io::stdin().read_line(&mut answer).is_ok()
+            && matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
+    })
+    .await
+    .unwrap_or(false)
+}
+
+async fn config_check(args: &ConfigArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let checks = check::check_config(args).await;
+    let mut failed = 0;
+    for check in &checks {
+        match &check.result {
+            Ok(detail) => println!("ok    {:<12} {detail}", check.name),
+            Err(e) => {
+                failed += 1;
+                println!("FAIL  {:<12} {e:#}", check.name);
+            }
+        }
+    }
+    if failed > 0 {
+        return Err(Failure::Config(anyhow!("{failed} configuration check(s) failed")).into());
+    }
+    Ok(())
+}
+
+async fn simulate(
+    config: &Config,
+    source: &impl SubscriptionSource,
+    filter: &FilterArgs,
+    options: &Options,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let from = config.caller().map_err(Failure::Config)?;
+    let subscriptions = filter.apply(
+        source
+            .fetch(config, filter, None)
+            .await
+            .map_err(Failure::Fetch)?,
+    );
+    let mut summary = Summary::default();
     for subscription in subscriptions {
-        tracing::info!("Redeeming {:#?}", subscription);
-        let tx_hash = redeem::redeem_payment(config.signer.clone(), subscription).await?;
-        tracing::info!("Redeemed at: https://gnosisscan.io/tx/{}", tx_hash);
+        let id = subscription.id;
+        let outcome = match redeem::simulate_payment(config, &subscription, from)
+            .instrument(tracing::info_span!("subscription", id = %id))
+            .await
+        {
+            Ok(()) => Outcome::simulated(id),
+            Err(e) => Outcome::failed(id, e),
+        }
+        .with_value(Some(subscription.value()));
+        options.emit(&outcome);
+        summary.push(outcome);
+    }
+    if options.output == OutputFormat::Text {
+        print!("{}", summary.table());
+    }
+    match summary.count(Status::Failed) {
+        0 => Ok(()),
+        failed => Err(Failure::Partial {
+            failed,
+            total: summary.outcomes.len(),
+        }
+        .into()),
+    }
+}
+
+async fn export(
+    config: &Config,
+    source: &impl SubscriptionSource,
+    args: ExportArgs,
+    options: &Options,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let from = match args.from {
+        Some(from) => from,
+        None => config.caller().map_err(Failure::Config)?,
+    };
+    let subscriptions = args.filter.apply(
+        source
+            .fetch(config, &args.filter, None)
+            .await
+            .map_err(Failure::Fetch)?,
+    );
+    let mut summary = Summary::default();
+    for outcome in
+        export::export_calldata(config, subscriptions, from, &args.out, args.format).await?
+    {
+        options.emit(&outcome);
+        summary.push(outcome);
+    }
+    if options.output == OutputFormat::Text {
+        print!("{}", summary.table());
+    }
+    match summary.count(Status::Failed) {
+        0 => Ok(()),
+        failed => Err(Failure::Partial {
+            failed,
+            total: summary.outcomes.len(),
+        }
+        .into()),
+    }
+}
+
+async fn list(
+    config: &Config,
+    source: &impl SubscriptionSource,
+    filter: &FilterArgs,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let subscriptions = filter.apply(
+        source
+            .fetch(config, filter, None)
+            .await
+            .map_err(Failure::Fetch)?,
+    );
+    for subscription in &subscriptions {
+        println!(
+            "{} {:?} {} -> {} amount={} periods={}",
+            subscription.id,
+            subscription.category,
+            subscription.subscriber,
+            subscription.recipient,
+            subscription.amount,
+            subscription.periods
+        );
+    }
+    tracing::info!("Found {} subscriptions", subscriptions.len());
+    Ok(())
+}
+
+async fn redeem_one(
+    config: &Config,
+    source: &impl SubscriptionSource,
+    args: RedeemArgs,
+    options: &Options,
+) -> Result<(), Box<dyn std::error::Error>> {
+    config.executor().map_err(Failure::Config)?;
+    let subscription = match (args.json, args.id) {
+        (Some(subscription), _) => subscription,
+        (None, Some(id)) => source
+            .fetch_one(config, id)
+            .await
+            .map_err(Failure::Fetch)?
+            .ok_or_else(|| format!("Subscription {id} is not redeemable"))?,
+        (None, None) => unreachable!("clap requires --id or --json"),
+    };
+    let outcome = process(config, subscription, options).await;
+    options.emit(&outcome);
+    match outcome.status {
+        Status::Failed => Err(Failure::Partial {
+            failed: 1,
+            total: 1,
+        }
+        .into()),
+        _ => Ok(()),
+    }
+}
+
+async fn path(
+    config: &Config,
+    from: Address,
+    to: Address,
+    amount: U256,
+    dot_file: Option<&std::path::Path>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let found = match redeem::find_path(config, from, to, amount).await {
+        Err(e) => match redeem::reduced_target(config, e.as_ref()) {
+            Some(target) => {
+                println!("Reduced target: {} CRC", format_ether(target));
+                redeem::find_path(config, from, to, target).await
+            }
+            None => Err(e),
+        },
+        found => found,
+    };
+    let path_data = match found {
+        Ok(path_data) => path_data,
+        Err(e) => {
+            explain_no_path(config, from, to).await;
+            return Err(e);
+        }
+    };
+    if let Some(file) = dot_file {
+        std::fs::write(file, dot::render(&path_data, from, to))
+            .map_err(|e| format!("Failed to write {}: {e}", file.display()))?;
+    }
+    let vertices = &path_data.flow_vertices;
+    println!("Transfers:");
+    for (edge, [token_owner, from, to]) in path_data
+        .flow_edges
+        .iter()
+        .zip(pathfinder::coordinates(&path_data.packed_coordinates))
+    {
+        println!(
+            "  {} -> {} amount={} token_owner={}",
+            vertices[from as usize],
+            vertices[to as usize],
+            edge.amount,
+            vertices[token_owner as usize]
+        );
+    }
+    println!("Flow matrix:");
+    println!("  Vertices:");
+    for (i, vertex) in vertices.iter().enumerate() {
+        println!("    {i}: {vertex}");
+    }
+    println!("  Edges:");
+    for (i, edge) in path_data.flow_edges.iter().enumerate() {
+        println!("    {i}: sink={} amount={}", edge.streamSinkId, edge.amount);
+    }
+    println!("  Streams:");
+    for stream in &path_data.streams {
+        println!(
+            "    source={} edges={:?}",
+            stream.sourceCoordinate, stream.flowEdgeIds
+        );
+    }
+    println!(
+        "Packed coordinates: 0x{}",
+        hex::encode(&path_data.packed_coordinates)
+    );
+    println!("Source coordinate: {}", path_data.source_coordinate);
+    println!("Stats: {}", pathfinder::PathStats::of(&path_data));
+    println!(
+        "Calldata size: {} bytes",
+        redeem::redeem_calldata_size(path_data)
+    );
+    Ok(())
+}
+
+/// Prints what the Circles RPC knows about the sender's balance and the receiver's trust, as
+/// hints to why no path was found. Tokens the receiver accepts directly are not the only way
+/// to reach it, since paths can pass through other accounts.
+async fn explain_no_path(config: &Config, from: Address, to: Address) {
+    let circles = &config.circles;
+    match circles.total_balance(from).await {
+        Ok(balance) => println!("{from} holds {} CRC", format_ether(balance)),
+        Err(e) => tracing::warn!("Failed to fetch the balance of {from}: {e:#}"),
+    }
+    match circles.accepted_tokens(from, to).await {
+        Ok((accepted, held)) => {
+            println!("{to} directly accepts {accepted} of the {held} tokens {from} holds")
+        }
+        Err(e) => tracing::warn!("Failed to fetch trust relations: {e:#}"),
+    }
+}
+
+/// Prints the first `count` accounts derived from the mnemonic, marking the executor.
+async fn accounts(config: &Config, count: u32) -> Result<(), Box<dyn std::error::Error>> {
+    let mnemonic = config
+        .mnemonic()
+        .ok_or_else(|| Failure::Config(anyhow!("MNEMONIC is not set")))?;
+    let executor = config.executor().map_err(Failure::Config)?;
+    for index in 0..count {
+        let address = mnemonic.signer(index)?.address();
+        let balance = redeem::balance(config, address).await?;
+        let marker = if address == executor {
+            " (executor)"
+        } else {
+            ""
+        };
+        println!("{index} {address} {} xDAI{marker}", format_ether(balance));
     }
     Ok(())
 }
@@ -46,16 +1062,93 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 mod tests {
     use super::*;
 
+    #[tokio::test]
+    async fn test_redeem_all_concurrently() {
+        let layer = config::Layer {
+            paused: Some(true),
+            concurrency: Some(4),
+            batch_size: Some(3),
+            ..Default::default()
+        };
+        let config = Config::from_layer(config::Layer::defaults(None).merge(layer)).unwrap();
+        let options = Options {
+            dry_run: false,
+            interactive: false,
+            output: OutputFormat::Json,
+            dashboard: None,
+            filter: FilterArgs::default(),
+            explorer_url: config.explorer_url.clone(),
+            stopping: Default::default(),
+        };
+        let subscriptions: Vec<_> = (0..10)
+            .map(|id| RedeemableSubscription {
+                contract_address: config.modules[0],
+                id: alloy::primitives::B256::with_last_byte(id),
+                recipient: Address::ZERO,
+                subscriber: Address::ZERO,
+                amount: U256::from(1),
+                periods: 1,
+                category: redeem::Category::Untrusted,
+            })
+            .collect();
+        let summary = redeem_all(&config, subscriptions, None, &options, None).await;
+        assert_eq!(summary.outcomes.len(), 10);
+        assert!(
+            summary
+                .outcomes
+                .iter()
+                .all(|outcome| outcome.status == Status::Skipped)
+        );
+    }
+
+    #[test]
+    fn test_dry_run_leaves_state_alone() {
+        let path =
+            std::env::temp_dir().join(format!("redeem-rs-run-state-{}.json", std::process::id()));
+        let config = Config::from_layer(config::Layer::defaults(None).merge(config::Layer {
+            state_file: Some(path.clone()),
+            ..Default::default()
+        }))
+        .unwrap();
+        let mut options = Options {
+            dry_run: true,
+            interactive: false,
+            output: OutputFormat::Json,
+            dashboard: None,
+            filter: FilterArgs::default(),
+            explorer_url: config.explorer_url.clone(),
+            stopping: Default::default(),
+        };
+        let id = alloy::primitives::B256::with_last_byte(1);
+        let mut state = open_state(&config, &options).unwrap().unwrap();
+        state.start(1, &[], config.dedupe_window).unwrap();
+        state.record(None, &Outcome::dry_run(id, 21_000)).unwrap();
+        assert!(!path.exists());
+
+        options.dry_run = false;
+        let mut state = open_state(&config, &options).unwrap().unwrap();
+        state.start(1, &[], config.dedupe_window).unwrap();
+        state
+            .record(None, &Outcome::redeemed(id, alloy::primitives::B256::ZERO))
+            .unwrap();
+        let state = state::State::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(state.since.is_some());
+        assert!(state.processed.contains_key(&id));
+    }
+
     #[tokio::test]
     #[ignore]
     async fn test_redeem_one() {
         dotenv::dotenv().ok();
-        let config = Config::from_env().expect("Failed to load config");
-        let subscriptions = fetch::fetch_redeemable_subscriptions(config.api_url)
+        let cli = Cli::parse_from(["redeem-rs"]);
+        let config = Config::load(&cli.config).expect("Failed to load config");
+        let subscriptions = Source::new(&config)
+            .fetch(&config, &FilterArgs::default(), None)
             .await
             .expect("Failed to fetch redeemable subscriptions");
         if let Some(subscription) = subscriptions.first().cloned() {
-            let result = redeem::redeem_payment(config.signer, subscription).await;
+            let result = redeem::redeem_payment(&config, subscription).await;
             assert!(result.is_ok(), "redeem_payment failed: {:?}", result.err());
         }
     }
@@ -0,0 +1,237 @@
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::config::Config;
+
+/// Upper bounds, in seconds, of the latency histogram buckets.
+const LATENCY_BUCKETS: &[f64] = &[0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0];
+
+/// Counters and a latency histogram for subscription fetches, rendered in the Prometheus text
+/// format so operators can spot indexer degradation.
+#[derive(Debug, Default)]
+pub struct FetchMetrics(Mutex<Inner>);
+
+#[derive(Debug, Default)]
+struct Inner {
+    errors: BTreeMap<&'static str, u64>,
+    latency: Histogram,
+    subscriptions: usize,
+}
+
+/// Latency observations, bucketed by [`LATENCY_BUCKETS`].
+#[derive(Debug, Default)]
+struct Histogram {
+    /// Observations per bucket (not cumulative); the last slot counts those above every bound.
+    buckets: [u64; LATENCY_BUCKETS.len() + 1],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, latency: Duration) {
+        let seconds = latency.as_secs_f64();
+        self.count += 1;
+        self.sum += seconds;
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|&bound| seconds <= bound)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        self.buckets[bucket] += 1;
+    }
+
+    /// The series of histogram `name`, each with `labels` (such as `outcome="ok"`).
+    fn render(&self, out: &mut String, name: &str, labels: &str) {
+        let (bucket_labels, labels) = if labels.is_empty() {
+            (String::new(), String::new())
+        } else {
+            (format!("{labels},"), format!("{{{labels}}}"))
+        };
+        let mut cumulative = 0;
+        for (bound, count) in LATENCY_BUCKETS.iter().zip(&self.buckets) {
+            cumulative += count;
+            let _ = writeln!(
+                out,
+                "{name}_bucket{{{bucket_labels}le=\"{bound}\"}} {cumulative}"
+            );
+        }
+        let _ = writeln!(
+            out,
+            "{name}_bucket{{{bucket_labels}le=\"+Inf\"}} {}\n\
+             {name}_sum{labels} {}\n\
+             {name}_count{labels} {}",
+            self.count, self.sum, self.count
+        );
+    }
+}
+
+impl FetchMetrics {
+    /// Records one fetch: how long it took, and either how many subscriptions it returned or
+    /// the class of error it failed with.
+    pub fn record(&self, latency: Duration, result: Result<usize, &'static str>) {
+        let Ok(mut inner) = self.0.lock() else {
+            return;
+        };
+        inner.latency.observe(latency);
+        match result {
+            Ok(count) => inner.subscriptions = count,
+            Err(class) => *inner.errors.entry(class).or_default() += 1,
+        }
+    }
+
+    /// The metrics in the Prometheus text exposition format, along with how many indexer
+    /// requests the rate limit has held back.
+    pub fn render(&self, throttled: u64) -> String {
+        let Ok(inner) = self.0.lock() else {
+            return String::new();
+        };
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "# HELP redeem_fetch_total Subscription fetches, successful or not.\n\
+             # TYPE redeem_fetch_total counter\n\
+             redeem_fetch_total {}",
+            inner.latency.count
+        );
+        let _ = writeln!(
+            out,
+            "# HELP redeem_fetch_errors_total Failed subscription fetches by error class.\n\
+             # TYPE redeem_fetch_errors_total counter"
+        );
+        for (class, count) in &inner.errors {
+            let _ = writeln!(
+                out,
+                "redeem_fetch_errors_total{{class=\"{class}\"}} {count}"
+            );
+        }
+        let _ = writeln!(
+            out,
+            "# HELP redeem_fetch_duration_seconds Time taken by each subscription fetch.\n\
+             # TYPE redeem_fetch_duration_seconds histogram"
+        );
+        inner
+            .latency
+            .render(&mut out, "redeem_fetch_duration_seconds", "");
+        let _ = writeln!(
+            out,
+            "# HELP redeem_fetch_subscriptions Subscriptions returned by the last successful fetch.\n\
+             # TYPE redeem_fetch_subscriptions gauge\n\
+             redeem_fetch_subscriptions {}",
+            inner.subscriptions
+        );
+        let _ = writeln!(
+            out,
+            "# HELP redeem_fetch_throttled_total Indexer requests delayed by the rate limit.\n\
+             # TYPE redeem_fetch_throttled_total counter\n\
+             redeem_fetch_throttled_total {throttled}"
+        );
+        out
+    }
+}
+
+/// A latency histogram of pathfinder searches per outcome, so operators can spot pathfinder
+/// degradation that slows whole runs.
+#[derive(Debug, Default)]
+pub struct PathfinderMetrics(Mutex<BTreeMap<&'static str, Histogram>>);
+
+impl PathfinderMetrics {
+    /// Records one search (or batch of searches): how long it took and how it ended.
+    pub fn record(&self, latency: Duration, outcome: &'static str) {
+        if let Ok(mut outcomes) = self.0.lock() {
+            outcomes.entry(outcome).or_default().observe(latency);
+        }
+    }
+
+    /// The metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let Ok(outcomes) = self.0.lock() else {
+            return String::new();
+        };
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "# HELP redeem_pathfinder_duration_seconds Time taken by each pathfinder search, by outcome.\n\
+             # TYPE redeem_pathfinder_duration_seconds histogram"
+        );
+        for (outcome, latency) in outcomes.iter() {
+            latency.render(
+                &mut out,
+                "redeem_pathfinder_duration_seconds",
+                &format!("outcome=\"{outcome}\""),
+            );
+        }
+        out
+    }
+}
+
+/// Writes the fetch and pathfinder metrics to `metrics_file`, if one is set, replacing it
+/// atomically so a scraper (such as the node_exporter textfile collector) never reads a
+/// partial file. Failures are only logged.
+pub fn write(config: &Config) {
+    let Some(path) = &config.metrics_file else {
+        return;
+    };
+    if let Err(e) = write_file(
+        path,
+        config
+            .fetch_metrics
+            .render(config.api_rate_limit.throttled())
+            + &config.pathfinder_metrics.render(),
+    ) {
+        tracing::warn!("{e:#}");
+    }
+}
+
+fn write_file(path: &Path, text: String) -> Result<()> {
+    let temp = path.with_extension("prom.tmp");
+    std::fs::write(&temp, text)
+        .and_then(|()| std::fs::rename(&temp, path))
+        .with_context(|| format!("Failed to write metrics file {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let metrics = FetchMetrics::default();
+        metrics.record(Duration::from_millis(80), Ok(12));
+        metrics.record(Duration::from_secs(90), Err("status"));
+        let text = metrics.render(3);
+        assert!(text.contains("redeem_fetch_total 2\n"));
+        assert!(text.contains("redeem_fetch_errors_total{class=\"status\"} 1\n"));
+        assert!(text.contains("redeem_fetch_duration_seconds_bucket{le=\"0.05\"} 0\n"));
+        assert!(text.contains("redeem_fetch_duration_seconds_bucket{le=\"0.1\"} 1\n"));
+        assert!(text.contains("redeem_fetch_duration_seconds_bucket{le=\"60\"} 1\n"));
+        assert!(text.contains("redeem_fetch_duration_seconds_bucket{le=\"+Inf\"} 2\n"));
+        assert!(text.contains("redeem_fetch_subscriptions 12\n"));
+        assert!(text.contains("redeem_fetch_throttled_total 3\n"));
+    }
+
+    #[test]
+    fn test_render_pathfinder() {
+        let metrics = PathfinderMetrics::default();
+        metrics.record(Duration::from_millis(300), "ok");
+        metrics.record(Duration::from_secs(2), "ok");
+        metrics.record(Duration::from_secs(90), "timeout");
+        let text = metrics.render();
+        assert!(
+            text.contains(
+                "redeem_pathfinder_duration_seconds_bucket{outcome=\"ok\",le=\"0.25\"} 0\n"
+            )
+        );
+        assert!(
+            text.contains(
+                "redeem_pathfinder_duration_seconds_bucket{outcome=\"ok\",le=\"2.5\"} 2\n"
+            )
+        );
+        assert!(text.contains(
+            "redeem_pathfinder_duration_seconds_bucket{outcome=\"timeout\",le=\"+Inf\"} 1\n"
+        ));
+        assert!(text.contains("redeem_pathfinder_duration_seconds_count{outcome=\"ok\"} 2\n"));
+    }
+}
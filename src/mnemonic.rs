@@ -0,0 +1,81 @@
+use alloy::signers::local::{MnemonicBuilder, PrivateKeySigner, coins_bip39::English};
+use anyhow::{Context, Result, anyhow};
+
+/// Derivation path of Ethereum accounts, to which the account index is appended.
+pub const DEFAULT_HD_PATH: &str = "m/44'/60'/0'/0";
+
+/// A BIP-39 mnemonic and BIP-32 path from which executor accounts are derived, one per index.
+/// The phrase must be valid English BIP-39, checksum included, so a mistyped word is rejected
+/// rather than deriving other accounts.
+pub struct Mnemonic {
+    phrase: String,
+    path: String,
+}
+
+impl Mnemonic {
+    pub fn new(phrase: &str, path: &str) -> Result<Self> {
+        let mnemonic = Self {
+            phrase: phrase.split_whitespace().collect::<Vec<_>>().join(" "),
+            path: path.trim_end_matches('/').to_string(),
+        };
+        if mnemonic.path != "m" && !mnemonic.path.starts_with("m/") {
+            return Err(anyhow!("Invalid HD path: {path}: must start with m"));
+        }
+        mnemonic
+            .builder(0)
+            .with_context(|| format!("Invalid HD path: {path}"))?
+            .build()
+            .context("Invalid mnemonic")?;
+        Ok(mnemonic)
+    }
+
+    /// The signer for account `index` below the path.
+    pub fn signer(&self, index: u32) -> Result<PrivateKeySigner> {
+        Ok(self.builder(index)?.build()?)
+    }
+
+    fn builder(&self, index: u32) -> Result<MnemonicBuilder<English>> {
+        Ok(MnemonicBuilder::<English>::default()
+            .phrase(self.phrase.as_str())
+            .derivation_path(format!("{}/{index}", self.path))?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy::primitives::address;
+
+    #[test]
+    fn test_mnemonic_accounts() {
+        let mnemonic = Mnemonic::new(
+            "test test test test test test test test test test test junk",
+            DEFAULT_HD_PATH,
+        )
+        .unwrap();
+        assert_eq!(
+            mnemonic.signer(0).unwrap().address(),
+            address!("0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266")
+        );
+        assert_eq!(
+            mnemonic.signer(1).unwrap().address(),
+            address!("0x70997970C51812dc3A010C7d01b50e0d17dc79C8")
+        );
+        assert!(Mnemonic::new("test test", DEFAULT_HD_PATH).is_err());
+        // Twelve valid words whose checksum does not match.
+        assert!(
+            Mnemonic::new(
+                "test test test test test test test test test test test test",
+                DEFAULT_HD_PATH
+            )
+            .is_err()
+        );
+        assert!(
+            Mnemonic::new(
+                "test test test test test test test test test test test junk",
+                "44'/60'"
+            )
+            .is_err()
+        );
+    }
+}
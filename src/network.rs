@@ -0,0 +1,87 @@
+use alloy::primitives::{Address, address};
+use clap::ValueEnum;
+use serde::Deserialize;
+
+use crate::config::{Layer, SafeLayer};
+
+/// A chain the SubscriptionModule is deployed on.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Network {
+    /// Gnosis Chain mainnet.
+    #[default]
+    Gnosis,
+    /// Chiado testnet.
+    Chiado,
+}
+
+impl Network {
+    pub fn chain_id(&self) -> u64 {
+        match self {
+            Network::Gnosis => 100,
+            Network::Chiado => 10200,
+        }
+    }
+
+    pub fn rpc_url(&self) -> &'static str {
+        match self {
+            Network::Gnosis => "https://rpc.gnosischain.com/",
+            Network::Chiado => "https://rpc.chiadochain.net/",
+        }
+    }
+
+    pub fn circles_rpc_url(&self) -> &'static str {
+        match self {
+            Network::Gnosis => "https://rpc.aboutcircles.com/",
+            Network::Chiado => "https://chiado-rpc.aboutcircles.com/",
+        }
+    }
+
+    /// Block explorer that transaction links point to.
+    pub fn explorer_url(&self) -> &'static str {
+        match self {
+            Network::Gnosis => "https://gnosisscan.io/",
+            Network::Chiado => "https://gnosis-chiado.blockscout.com/",
+        }
+    }
+
+    pub fn module_address(&self) -> Option<Address> {
+        match self {
+            Network::Gnosis => Some(address!("0xcebe4b6d50ce877a9689ce4516fe96911e099a78")),
+            Network::Chiado => None,
+        }
+    }
+
+    pub fn safe_service_url(&self) -> &'static str {
+        match self {
+            Network::Gnosis => "https://safe-transaction-gnosis-chain.safe.global/",
+            Network::Chiado => "https://safe-transaction-chiado.safe.global/",
+        }
+    }
+
+    /// The network's endpoints and contracts as a configuration layer.
+    pub fn layer(&self) -> Layer {
+        Layer {
+            network: Some(*self),
+            chain_id: Some(self.chain_id()),
+            rpc_url: Some(self.rpc_url().to_string()),
+            circles_rpc_url: Some(self.circles_rpc_url().to_string()),
+            explorer_url: Some(self.explorer_url().to_string()),
+            module_address: self.module_address(),
+            safe: SafeLayer {
+                address: None,
+                service_url: Some(self.safe_service_url().to_string()),
+            },
+            ..Default::default()
+        }
+    }
+}
+
+impl std::fmt::Display for Network {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Network::Gnosis => "gnosis",
+            Network::Chiado => "chiado",
+        })
+    }
+}
@@ -0,0 +1,68 @@
+use alloy::{
+    network::Network,
+    primitives::Address,
+    providers::{Provider, fillers::NonceManager},
+    transports::TransportResult,
+};
+use futures::lock::Mutex;
+use std::{collections::HashMap, sync::Arc};
+
+/// Hands out consecutive nonces from a locally kept counter, so that a batch of redemptions
+/// fetches the account nonce once instead of racing on `eth_getTransactionCount` and failing
+/// with "nonce too low" or "replacement underpriced".
+///
+/// After a failed send the counter may be ahead of the chain; [`Nonces::reset`] makes the next
+/// transaction fetch the pending nonce again.
+#[derive(Debug, Clone, Default)]
+pub struct Nonces(Arc<Mutex<HashMap<Address, u64>>>);
+
+impl Nonces {
+    /// Forgets every cached nonce.
+    pub async fn reset(&self) {
+        self.0.lock().await.clear();
+    }
+}
+
+#[async_trait::async_trait]
+impl NonceManager for Nonces {
+    async fn get_next_nonce<P, N>(&self, provider: &P, address: Address) -> TransportResult<u64>
+    where
+        P: Provider<N>,
+        N: Network,
+    {
+        // Held across the fetch so that concurrent senders cannot both fetch the same nonce.
+        let mut nonces = self.0.lock().await;
+        let nonce = match nonces.get(&address) {
+            Some(last) => last + 1,
+            None => {
+                let nonce = provider.get_transaction_count(address).pending().await?;
+                tracing::debug!("Fetched nonce {nonce} for {address}");
+                nonce
+            }
+        };
+        nonces.insert(address, nonce);
+        Ok(nonce)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy::{providers::ProviderBuilder, transports::mock::Asserter};
+
+    #[tokio::test]
+    async fn test_nonces_fetch_once_until_reset() {
+        let asserter = Asserter::new();
+        let provider = ProviderBuilder::new().connect_mocked_client(asserter.clone());
+        let nonces = Nonces::default();
+        let executor = Address::repeat_byte(1);
+
+        asserter.push_success(&"0x7");
+        assert_eq!(nonces.get_next_nonce(&provider, executor).await.unwrap(), 7);
+        assert_eq!(nonces.get_next_nonce(&provider, executor).await.unwrap(), 8);
+
+        nonces.reset().await;
+        asserter.push_success(&"0x7");
+        assert_eq!(nonces.get_next_nonce(&provider, executor).await.unwrap(), 7);
+    }
+}
@@ -0,0 +1,672 @@
+use alloy::primitives::{Address, U256};
+use circles_pathfinder::{FindPathParams, PathData, PathfinderError, TransferStep};
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::{cassette::Cassette, circles, config::Config, solver::Graph};
+
+/// The engine that searches transfer paths.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Backend {
+    /// The `pathfinder_api` method on `circles_rpc_url`.
+    #[default]
+    CirclesRpc,
+    /// A max-flow search in-process, over balances and trust relations read from
+    /// `circles_rpc_url`.
+    Local,
+    /// `circles-rpc`, recording every response to `pathfinder_cassette`.
+    Record,
+    /// Only the responses recorded in `pathfinder_cassette`, without the network.
+    Replay,
+}
+
+impl Backend {
+    /// The pathfinder of the backend; `record` and `replay` need a `cassette`.
+    pub fn pathfinder(self, cassette: Option<&Path>) -> anyhow::Result<Box<dyn Pathfinder>> {
+        let cassette = || cassette.ok_or_else(|| anyhow::anyhow!("pathfinder_cassette is not set"));
+        Ok(match self {
+            Backend::CirclesRpc => Box::new(CirclesRpc),
+            Backend::Local => Box::new(Local),
+            Backend::Record => Box::new(Record(Cassette::open(cassette()?)?)),
+            Backend::Replay => Box::new(Replay(Cassette::load(cassette()?)?)),
+        })
+    }
+}
+
+/// The pathfinder found less flow than the target, so the path cannot pay it in full.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("Pathfinder found a flow of {max_flow}, short of the target {target}")]
+pub struct InsufficientFlow {
+    /// The most that can be routed, in atto-circles.
+    pub max_flow: U256,
+    pub target: U256,
+}
+
+impl InsufficientFlow {
+    /// The shortfall `e` reports, if it is one: an [`InsufficientFlow`], or the `Imbalanced`
+    /// error of a flow matrix that does not reach its target.
+    pub fn find(e: &(dyn std::error::Error + 'static)) -> Option<InsufficientFlow> {
+        if let Some(shortfall) = e.downcast_ref::<InsufficientFlow>() {
+            return Some(shortfall.clone());
+        }
+        match e.downcast_ref() {
+            Some(PathfinderError::Imbalanced {
+                terminal_sum,
+                expected,
+            }) => Some(InsufficientFlow {
+                max_flow: U256::from(*terminal_sum),
+                target: U256::from(*expected),
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// The pathfinder did not answer in the time allowed.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("Pathfinder did not answer within {0:?}")]
+pub struct TimedOut(pub Duration);
+
+/// The pathfinder's transfers do not form a path from the sender to the receiver, so their
+/// flow matrix would only revert on chain.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("Pathfinder returned an invalid path: {0}")]
+pub struct InvalidPath(pub String);
+
+/// Checks that `transfers` form a path for `params`: every transfer carries something between
+/// two different avatars, every avatar in between passes on exactly what it receives, and
+/// what leaves the sender ends at the receiver. No transfers at all is left for the flow
+/// matrix to report as [`InsufficientFlow`].
+pub fn validate(params: &FindPathParams, transfers: &[TransferStep]) -> Result<(), InvalidPath> {
+    // What each avatar receives and sends.
+    let mut flows: HashMap<Address, (U256, U256)> = HashMap::new();
+    for (index, transfer) in transfers.iter().enumerate() {
+        if transfer.value.is_zero() {
+            return Err(InvalidPath(format!("transfer {index} carries nothing")));
+        }
+        if transfer.from_address == transfer.to_address {
+            return Err(InvalidPath(format!(
+                "transfer {index} goes from {} to itself",
+                transfer.from_address
+            )));
+        }
+        let value = U256::from(transfer.value);
+        let sent = &mut flows.entry(transfer.from_address).or_default().1;
+        *sent = sent.saturating_add(value);
+        let received = &mut flows.entry(transfer.to_address).or_default().0;
+        *received = received.saturating_add(value);
+    }
+    if transfers.is_empty() {
+        return Ok(());
+    }
+    for (avatar, (received, sent)) in &flows {
+        if *avatar != params.from && *avatar != params.to && received != sent {
+            return Err(InvalidPath(format!(
+                "{avatar} receives {received} but sends {sent}"
+            )));
+        }
+    }
+    let (_, out_of_source) = flows.get(&params.from).copied().unwrap_or_default();
+    let (into_sink, _) = flows.get(&params.to).copied().unwrap_or_default();
+    if into_sink.is_zero() || into_sink != out_of_source {
+        return Err(InvalidPath(format!(
+            "{out_of_source} leaves {} but {into_sink} reaches {}",
+            params.from, params.to
+        )));
+    }
+    Ok(())
+}
+
+/// `e`, as an [`InsufficientFlow`] if it reports one.
+pub fn typed(e: PathfinderError) -> Box<dyn std::error::Error> {
+    match InsufficientFlow::find(&e) {
+        Some(shortfall) => shortfall.into(),
+        None => e.into(),
+    }
+}
+
+/// Searches transfer paths and prepares their flow matrices for the contract.
+#[async_trait::async_trait(?Send)]
+pub trait Pathfinder {
+    /// A path for `params.target_flow` from `params.from` to `params.to`. Finding less flow
+    /// than the target is an [`InsufficientFlow`] error.
+    async fn find_path(
+        &self,
+        config: &Config,
+        params: &FindPathParams,
+    ) -> Result<PathData, Box<dyn std::error::Error>>;
+
+    /// A path for each of `searches`, in order. Fails as a whole only if none can be searched;
+    /// by default they are searched one by one.
+    async fn find_paths(
+        &self,
+        config: &Config,
+        searches: &[&FindPathParams],
+    ) -> Result<Vec<Result<PathData, Box<dyn std::error::Error>>>, Box<dyn std::error::Error>> {
+        let mut found = Vec::with_capacity(searches.len());
+        for params in searches {
+            found.push(self.find_path(config, params).await);
+        }
+        Ok(found)
+    }
+}
+
+/// The Circles RPC at `circles_rpc_url`, with every search bounded by `circles_rpc_timeout`.
+pub struct CirclesRpc;
+
+#[async_trait::async_trait(?Send)]
+impl Pathfinder for CirclesRpc {
+    async fn find_path(
+        &self,
+        config: &Config,
+        params: &FindPathParams,
+    ) -> Result<PathData, Box<dyn std::error::Error>> {
+        within(config.circles_rpc_timeout, config.circles.find_path(params)).await
+    }
+
+    /// All searches go out in a single JSON-RPC batch request.
+    async fn find_paths(
+        &self,
+        config: &Config,
+        searches: &[&FindPathParams],
+    ) -> Result<Vec<Result<PathData, Box<dyn std::error::Error>>>, Box<dyn std::error::Error>> {
+        within(
+            config.circles_rpc_timeout,
+            config.circles.find_paths(searches),
+        )
+        .await
+    }
+}
+
+/// Searches paths itself, so only balance and trust queries go to the Circles RPC. It explores
+/// a few hops around the sender, so it can miss long paths the RPC's pathfinder would find.
+pub struct Local;
+
+#[async_trait::async_trait(?Send)]
+impl Pathfinder for Local {
+    async fn find_path(
+        &self,
+        config: &Config,
+        params: &FindPathParams,
+    ) -> Result<PathData, Box<dyn std::error::Error>> {
+        let graph = Graph::load(&config.circles, params).await?;
+        circles::flow_matrix(params, &graph.max_flow(params))
+    }
+}
+
+/// How long and how spread out a path is, which drives the gas its redemption takes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathStats {
+    /// Transfers (flow edges) along the path.
+    pub transfers: usize,
+    /// Distinct avatars whose tokens are transferred.
+    pub token_owners: usize,
+    /// The most a single transfer carries, in atto-circles.
+    pub largest_transfer: U256,
+}
+
+impl PathStats {
+    pub fn of(path_data: &PathData) -> Self {
+        Self::from_parts(
+            path_data
+                .flow_edges
+                .iter()
+                .map(|edge| U256::from(edge.amount)),
+            &path_data.packed_coordinates,
+        )
+    }
+
+    /// The stats of transfers carrying `amounts`, whose (token owner, from, to) vertex indices
+    /// are packed in `packed_coordinates`, two bytes each.
+    fn from_parts(amounts: impl Iterator<Item = U256>, packed_coordinates: &[u8]) -> Self {
+        let token_owners: HashSet<_> = coordinates(packed_coordinates)
+            .map(|[token_owner, ..]| token_owner)
+            .collect();
+        let (transfers, largest_transfer) = amounts
+            .fold((0, U256::ZERO), |(count, largest), amount| {
+                (count + 1, largest.max(amount))
+            });
+        PathStats {
+            transfers,
+            token_owners: token_owners.len(),
+            largest_transfer,
+        }
+    }
+}
+
+/// The (token owner, from, to) vertex indices of each transfer, as packed big-endian, two
+/// bytes each, in `packed_coordinates`.
+pub fn coordinates(packed_coordinates: &[u8]) -> impl Iterator<Item = [u16; 3]> + '_ {
+    packed_coordinates
+        .chunks_exact(6)
+        .map(|chunk| [0, 2, 4].map(|i| u16::from_be_bytes([chunk[i], chunk[i + 1]])))
+}
+
+impl std::fmt::Display for PathStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} transfers of {} token owners, the largest {}",
+            self.transfers, self.token_owners, self.largest_transfer
+        )
+    }
+}
+
+/// Logs the stats of the path found for `params`, and rejects it if it has more transfers or
+/// token owners than `max_path_transfers` or `max_path_token_owners` allow, since its
+/// redemption would take too much gas.
+pub fn check_path(
+    config: &Config,
+    params: &FindPathParams,
+    path_data: &PathData,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let stats = PathStats::of(path_data);
+    tracing::debug!("Path {} -> {} has {stats}", params.from, params.to);
+    if let Some(max) = config.max_path_transfers
+        && stats.transfers > max
+    {
+        return Err(format!(
+            "path has {} transfers, more than the {max} allowed",
+            stats.transfers
+        )
+        .into());
+    }
+    if let Some(max) = config.max_path_token_owners
+        && stats.token_owners > max
+    {
+        return Err(format!(
+            "path moves tokens of {} owners, more than the {max} allowed",
+            stats.token_owners
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// Suspends path searches for `cooldown` once `threshold` of them in a row have failed
+/// without an answer, rather than sending every subscription of a run to a pathfinder that is
+/// down. After the cooldown one search is let through; the circuit closes again once a search
+/// gets an answer, and opens for another cooldown if it fails too.
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    /// Zero never opens the circuit.
+    threshold: u32,
+    cooldown: Duration,
+    state: Mutex<Breaker>,
+}
+
+#[derive(Debug, Default)]
+struct Breaker {
+    failures: u32,
+    open_until: Option<Instant>,
+}
+
+impl CircuitBreaker {
+    pub fn new(threshold: u32, cooldown: Duration) -> Self {
+        Self {
+            threshold,
+            cooldown,
+            state: Mutex::default(),
+        }
+    }
+
+    /// Why path searches are suspended, while they are.
+    pub fn open(&self) -> Option<String> {
+        let state = self.state.lock().unwrap();
+        let remaining = state.open_until?.checked_duration_since(Instant::now())?;
+        Some(format!(
+            "pathfinder suspended for another {}s after {} failures in a row",
+            remaining.as_secs() + 1,
+            state.failures
+        ))
+    }
+
+    /// Counts a search that failed with `e`, or resets the count for one that got an answer,
+    /// including that there is too little flow or an invalid path.
+    pub fn record(&self, e: Option<&(dyn std::error::Error + 'static)>) {
+        let mut state = self.state.lock().unwrap();
+        let outage =
+            e.is_some_and(|e| InsufficientFlow::find(e).is_none() && !e.is::<InvalidPath>());
+        if !outage {
+            *state = Breaker::default();
+            return;
+        }
+        state.failures += 1;
+        if self.threshold > 0 && state.failures >= self.threshold {
+            state.open_until = Some(Instant::now() + self.cooldown);
+            tracing::error!(
+                "Pathfinder failed {} times in a row, suspending path searches for {:?}",
+                state.failures,
+                self.cooldown
+            );
+        }
+    }
+}
+
+/// Runs `search` within `pathfinder_timeout`, dropping it if it runs over. That cancels its
+/// requests, and since paths are only cached once found, leaves nothing half done behind.
+/// While the circuit breaker is open, fails without searching.
+pub async fn bounded<T>(
+    config: &Config,
+    search: impl Future<Output = Result<T, Box<dyn std::error::Error>>>,
+) -> Result<T, Box<dyn std::error::Error>> {
+    if let Some(reason) = config.pathfinder_breaker.open() {
+        return Err(reason.into());
+    }
+    let started = Instant::now();
+    let result = within(config.pathfinder_timeout, search).await;
+    let e = result.as_ref().err().map(|e| e.as_ref());
+    config.pathfinder_breaker.record(e);
+    config
+        .pathfinder_metrics
+        .record(started.elapsed(), e.map_or("ok", outcome));
+    crate::metrics::write(config);
+    result
+}
+
+/// A short label for how a search that failed with `e` ended, used as a metrics label.
+fn outcome(e: &(dyn std::error::Error + 'static)) -> &'static str {
+    if InsufficientFlow::find(e).is_some() {
+        "insufficient_flow"
+    } else if e.is::<InvalidPath>() {
+        "invalid_path"
+    } else if e.is::<TimedOut>() {
+        "timeout"
+    } else {
+        "error"
+    }
+}
+
+/// Runs `search`, giving up after `timeout` if one is set.
+async fn within<T>(
+    timeout: Option<Duration>,
+    search: impl Future<Output = Result<T, Box<dyn std::error::Error>>>,
+) -> Result<T, Box<dyn std::error::Error>> {
+    match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, search)
+            .await
+            .map_err(|_| TimedOut(timeout))?,
+        None => search.await,
+    }
+}
+
+/// [`CirclesRpc`], recording each response to a cassette.
+pub struct Record(Cassette);
+
+#[async_trait::async_trait(?Send)]
+impl Pathfinder for Record {
+    async fn find_path(
+        &self,
+        config: &Config,
+        params: &FindPathParams,
+    ) -> Result<PathData, Box<dyn std::error::Error>> {
+        let response = within(
+            config.circles_rpc_timeout,
+            config.circles.find_path_json(params),
+        )
+        .await?;
+        self.0
+            .record(circles::find_path_request(params)?, response.clone())?;
+        circles::path_data_json(params, response)
+    }
+}
+
+/// Answers from a cassette only, failing searches it has no response for.
+pub struct Replay(Cassette);
+
+#[async_trait::async_trait(?Send)]
+impl Pathfinder for Replay {
+    async fn find_path(
+        &self,
+        _config: &Config,
+        params: &FindPathParams,
+    ) -> Result<PathData, Box<dyn std::error::Error>> {
+        let response = self
+            .0
+            .replay(&circles::find_path_request(params)?)
+            .ok_or_else(|| {
+                format!(
+                    "No recorded path {} -> {} for {}",
+                    params.from, params.to, params.target_flow
+                )
+            })?;
+        circles::path_data_json(params, response)
+    }
+}
+
+/// Paths for each of `searches`, in order, reusing those in the path cache and searching the
+/// rest with the configured pathfinder within `pathfinder_timeout`.
+pub async fn find_paths(
+    config: &Config,
+    searches: &[FindPathParams],
+) -> Result<Vec<Result<PathData, Box<dyn std::error::Error>>>, Box<dyn std::error::Error>> {
+    let cached: Vec<_> = searches
+        .iter()
+        .map(|params| config.path_cache.get(params))
+        .collect();
+    let missing: Vec<_> = searches
+        .iter()
+        .zip(&cached)
+        .filter(|(_, path_data)| path_data.is_none())
+        .map(|(params, _)| params)
+        .collect();
+    if missing.len() < searches.len() {
+        tracing::debug!("Using {} cached paths", searches.len() - missing.len());
+    }
+    let found = if missing.is_empty() {
+        Vec::new()
+    } else {
+        bounded(config, config.pathfinder.find_paths(config, &missing)).await?
+    };
+    let mut found = missing.iter().zip(found).map(|(params, path_data)| {
+        let path_data = path_data
+            .and_then(|path_data| check_path(config, params, &path_data).map(|()| path_data));
+        if let Ok(path_data) = &path_data {
+            config.path_cache.store(params, path_data);
+        }
+        path_data
+    });
+    Ok(cached
+        .into_iter()
+        .map(|path_data| match path_data {
+            Some(path_data) => Ok(path_data),
+            None => found
+                .next()
+                .unwrap_or_else(|| Err("missing from the pathfinder's response".into())),
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Layer;
+    use alloy::primitives::aliases::U192;
+    use std::sync::Arc;
+
+    /// Fails every search, recording its target flow.
+    struct Recorder(Arc<Mutex<Vec<U256>>>);
+
+    #[async_trait::async_trait(?Send)]
+    impl Pathfinder for Recorder {
+        async fn find_path(
+            &self,
+            _config: &Config,
+            params: &FindPathParams,
+        ) -> Result<PathData, Box<dyn std::error::Error>> {
+            self.0.lock().unwrap().push(params.target_flow);
+            Err("no path".into())
+        }
+    }
+
+    /// Never answers.
+    struct Hung;
+
+    #[async_trait::async_trait(?Send)]
+    impl Pathfinder for Hung {
+        async fn find_path(
+            &self,
+            _config: &Config,
+            _params: &FindPathParams,
+        ) -> Result<PathData, Box<dyn std::error::Error>> {
+            std::future::pending().await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_pathfinder_timeout() {
+        let mut config = Config::from_layer(Layer {
+            pathfinder_timeout: Some(std::time::Duration::from_millis(10)),
+            ..Layer::defaults(None)
+        })
+        .unwrap();
+        config.pathfinder = Box::new(Hung);
+        let (from, to) = (Address::with_last_byte(1), Address::with_last_byte(2));
+        let e = crate::redeem::find_path(&config, from, to, U256::from(1))
+            .await
+            .unwrap_err();
+        assert!(e.to_string().contains("did not answer"));
+        let params = crate::redeem::find_path_params(&config, from, to, U256::from(1));
+        assert!(config.path_cache.get(&params).is_none());
+        let found = find_paths(&config, &[params]).await;
+        assert!(found.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_replay_find_path() {
+        let cassette = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/find_path.json");
+        let mut config = Config::from_layer(Layer::defaults(None)).unwrap();
+        config.pathfinder = Backend::Replay.pathfinder(Some(&cassette)).unwrap();
+        let (from, middle, to) = (
+            Address::with_last_byte(1),
+            Address::with_last_byte(3),
+            Address::with_last_byte(2),
+        );
+        let amount = U256::from(10).pow(U256::from(18));
+
+        let path_data = crate::redeem::find_path(&config, from, to, amount)
+            .await
+            .unwrap();
+        assert_eq!(path_data.flow_vertices.len(), 3);
+        assert!(path_data.flow_vertices.contains(&middle));
+        assert_eq!(
+            PathStats::of(&path_data),
+            PathStats {
+                transfers: 2,
+                token_owners: 2,
+                largest_transfer: amount,
+            }
+        );
+        assert!(
+            crate::redeem::find_path(&config, to, from, amount)
+                .await
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_circuit_breaker() {
+        let breaker = CircuitBreaker::new(2, Duration::from_secs(60));
+        let down: Box<dyn std::error::Error> = "connection refused".into();
+        let short = InsufficientFlow {
+            max_flow: U256::ZERO,
+            target: U256::from(1),
+        };
+        breaker.record(Some(down.as_ref()));
+        // Finding too little flow is an answer, not an outage.
+        breaker.record(Some(&short));
+        breaker.record(Some(down.as_ref()));
+        assert!(breaker.open().is_none());
+        breaker.record(Some(down.as_ref()));
+        assert!(breaker.open().is_some());
+        breaker.record(None);
+        assert!(breaker.open().is_none());
+
+        let disabled = CircuitBreaker::new(0, Duration::from_secs(60));
+        for _ in 0..10 {
+            disabled.record(Some(down.as_ref()));
+        }
+        assert!(disabled.open().is_none());
+    }
+
+    #[test]
+    fn test_validate() {
+        let (from, to, middle) = (
+            Address::with_last_byte(1),
+            Address::with_last_byte(2),
+            Address::with_last_byte(3),
+        );
+        let params = crate::redeem::find_path_params(
+            &Config::from_layer(Layer::defaults(None)).unwrap(),
+            from,
+            to,
+            U256::from(100),
+        );
+        let transfer = |from_address, to_address, value: u64| TransferStep {
+            from_address,
+            to_address,
+            token_owner: from_address,
+            value: U192::from(value),
+        };
+
+        let path = [transfer(from, middle, 100), transfer(middle, to, 100)];
+        assert_eq!(validate(&params, &path), Ok(()));
+        assert_eq!(validate(&params, &[]), Ok(()));
+        // The intermediary keeps some.
+        let leaky = [transfer(from, middle, 100), transfer(middle, to, 60)];
+        assert!(validate(&params, &leaky).is_err());
+        let empty = [transfer(from, to, 0)];
+        assert!(validate(&params, &empty).is_err());
+        let lost = [transfer(from, middle, 100), transfer(middle, from, 100)];
+        assert!(validate(&params, &lost).is_err());
+    }
+
+    #[test]
+    fn test_path_stats() {
+        // Three transfers, of the tokens at vertex 0 twice and vertex 2 once.
+        let packed = [0, 0, 0, 0, 0, 1, 0, 0, 0, 1, 0, 2, 0, 2, 0, 2, 0, 3];
+        let amounts = [U256::from(50), U256::from(80), U256::from(30)];
+        let stats = PathStats::from_parts(amounts.into_iter(), &packed);
+        assert_eq!(
+            stats,
+            PathStats {
+                transfers: 3,
+                token_owners: 2,
+                largest_transfer: U256::from(80),
+            }
+        );
+    }
+
+    #[test]
+    fn test_coordinates() {
+        let packed = [0, 0, 0, 1, 1, 2, 0, 3];
+        assert_eq!(coordinates(&packed).collect::<Vec<_>>(), [[0, 1, 258]]);
+    }
+
+    #[tokio::test]
+    async fn test_find_paths_with_custom_pathfinder() {
+        let searched = Arc::new(Mutex::new(Vec::new()));
+        let mut config = Config::from_layer(Layer::defaults(None)).unwrap();
+        config.pathfinder = Box::new(Recorder(searched.clone()));
+        let params = |target_flow: u64| {
+            crate::redeem::find_path_params(
+                &config,
+                Address::with_last_byte(1),
+                Address::with_last_byte(2),
+                U256::from(target_flow),
+            )
+        };
+        let searches = [params(1), params(2)];
+        let found = find_paths(&config, &searches).await.unwrap();
+        assert_eq!(found.len(), 2);
+        assert!(found.iter().all(Result::is_err));
+        assert_eq!(*searched.lock().unwrap(), [U256::from(1), U256::from(2)]);
+    }
+}
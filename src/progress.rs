@@ -0,0 +1,64 @@
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use std::{
+    io::{IsTerminal, Write},
+    sync::OnceLock,
+};
+
+use crate::report::{Status, Summary};
+
+fn bars() -> &'static MultiProgress {
+    static BARS: OnceLock<MultiProgress> = OnceLock::new();
+    BARS.get_or_init(|| {
+        if std::io::stderr().is_terminal() {
+            MultiProgress::new()
+        } else {
+            MultiProgress::with_draw_target(ProgressDrawTarget::hidden())
+        }
+    })
+}
+
+/// Stderr writer for logs that keeps active progress bars intact.
+pub struct LogWriter;
+
+impl Write for LogWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        bars().suspend(|| std::io::stderr().write(buf))
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        std::io::stderr().flush()
+    }
+}
+
+/// Progress of a batch of `len` subscriptions; hidden when stderr is not a terminal.
+pub struct Progress(ProgressBar);
+
+impl Progress {
+    pub fn new(len: usize) -> Self {
+        let bar = bars().add(ProgressBar::new(len as u64));
+        bar.set_style(
+            ProgressStyle::with_template("{bar:40.cyan/blue} {pos}/{len} {msg} [ETA {eta}]")
+                .expect("valid progress template"),
+        );
+        Self(bar)
+    }
+
+    /// Moves to the number of subscriptions processed so far, however many each chunk or
+    /// batch settled, and refreshes the outcome counts.
+    pub fn update(&self, summary: &Summary) {
+        self.0.set_position(summary.outcomes.len() as u64);
+        self.0.set_message(format!(
+            "ok: {} failed: {} skipped: {}",
+            summary.count(Status::Redeemed) + summary.count(Status::DryRun),
+            summary.count(Status::Failed),
+            summary.count(Status::Skipped),
+        ));
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        self.0.finish_and_clear();
+        bars().remove(&self.0);
+    }
+}
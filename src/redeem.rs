@@ -1,25 +1,101 @@
 use alloy::{
-    primitives::{Address, U256},
-    providers::ProviderBuilder,
-    signers::local::PrivateKeySigner,
+    consensus::Transaction,
+    eips::Encodable2718,
+    network::Ethereum,
+    primitives::{Address, Bytes, U256, address, b256, hex, utils::format_ether},
+    providers::{
+        PendingTransactionBuilder, PendingTransactionError, Provider, ProviderBuilder,
+        RootProvider, WatchTxError,
+        fillers::{FillProvider, TxFiller},
+    },
+    rpc::types::TransactionRequest,
     sol,
+    sol_types::{GenericContractError, SolCall, SolError, SolInterface, decode_revert_reason},
 };
 use serde::{Deserialize, Serialize};
 
+use crate::circles;
+use crate::config::Config;
+use crate::pathfinder::{self, InsufficientFlow};
 use alloy::primitives::B256;
-use circles_pathfinder::{FindPathParams, encode_redeem_trusted_data, prepare_flow_for_contract};
-use std::str::FromStr;
+use circles_pathfinder::{FindPathParams, PathData, encode_redeem_trusted_data};
+use std::collections::HashMap;
+use std::sync::atomic::Ordering;
+use std::time::Instant;
 
 sol!(
     #[allow(missing_docs)]
     #[sol(rpc)]
     contract SubscriptionModule {
         function redeem(bytes32 id, bytes calldata data) external;
+        function isValidOrRedeemable(bytes32 id) external view returns (uint256);
+        function getSubscription(bytes32 id) external view returns (Subscription memory);
+
+        struct Subscription {
+            address subscriber;
+            address recipient;
+            uint256 amount;
+            uint256 nextRedeemAt;
+            uint256 frequency;
+            uint8 category;
+        }
+
+        event SubscriptionCreated(
+            bytes32 indexed id,
+            address indexed subscriber,
+            address indexed recipient,
+            uint256 amount,
+            uint256 frequency,
+            uint8 category,
+            uint256 nextRedeemAt
+        );
+        event Redeemed(
+            bytes32 indexed id,
+            address indexed subscriber,
+            address indexed recipient,
+            uint256 nextRedeemAt
+        );
+        event Unsubscribed(bytes32 indexed id, address indexed subscriber);
+
+        error NotRedeemable();
+        error NotSubscriber();
+        error IdentifierExists();
+        error IdentifierNonexistent();
+        error InvalidAmount();
+        error InvalidCategory();
+        error InvalidFrequency();
+        error InvalidRecipient();
+        error InvalidStreamSource();
+        error ExecutionFailed();
     }
 );
 
-const GNOSIS_RPC: &str = "https://rpc.gnosischain.com/";
-const CIRCLES_RPC: &str = "https://rpc.aboutcircles.com/";
+sol!(
+    #[allow(missing_docs)]
+    #[sol(rpc)]
+    contract Multicall3 {
+        struct Call3 {
+            address target;
+            bool allowFailure;
+            bytes callData;
+        }
+        struct Result {
+            bool success;
+            bytes returnData;
+        }
+        function aggregate3(Call3[] calldata calls) external payable returns (Result[] memory returnData);
+    }
+);
+
+sol!(
+    /// What the Circles hub reverts with when a transfer spends more of a token than its
+    /// sender holds, as a path does once the balances it was searched over have moved.
+    #[allow(missing_docs)]
+    error ERC1155InsufficientBalance(address sender, uint256 balance, uint256 needed, uint256 tokenId);
+);
+
+/// Multicall3, deployed at the same address on Gnosis Chain, Chiado and most other chains.
+const MULTICALL3: Address = address!("0xcA11bde05977b3631167028862bE2a173976CA11");
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(rename_all = "lowercase")]
@@ -35,65 +111,1470 @@ pub struct RedeemableSubscription {
     pub id: B256,
     pub recipient: Address,
     pub subscriber: Address,
-    pub amount: String,
+    /// Amount due per period, in atto-circles.
+    #[serde(with = "circles::decimal")]
+    pub amount: U256,
     pub periods: i32,
     pub category: Category,
 }
 
-pub async fn redeem_payment(
-    signer: PrivateKeySigner,
+impl RedeemableSubscription {
+    /// Total amount due for all outstanding periods.
+    pub fn value(&self) -> U256 {
+        self.amount
+            .saturating_mul(U256::from(u64::try_from(self.periods).unwrap_or_default()))
+    }
+}
+
+/// A signing provider for the configured RPC, bound to the configured chain ID so that a
+/// misconfigured RPC cannot get transactions signed for another chain.
+pub fn provider(
+    config: &Config,
+) -> anyhow::Result<FillProvider<impl TxFiller<Ethereum>, RootProvider>> {
+    Ok(ProviderBuilder::new()
+        .disable_recommended_fillers()
+        .with_gas_estimation()
+        .with_nonce_management(config.nonces.clone())
+        .with_chain_id(config.chain_id)
+        .wallet(config.wallet()?)
+        .connect_client(config.rpc()))
+}
+
+/// Fills, signs and sends `tx` from the executor. With a `send_rpc_url`, the signed
+/// transaction goes only to that endpoint, while filling and watching it use `rpc_url`.
+pub async fn submit(
+    config: &Config,
+    tx: TransactionRequest,
+) -> Result<PendingTransactionBuilder<Ethereum>, Box<dyn std::error::Error>> {
+    let provider = provider(config)?;
+    let sent: Result<_, Box<dyn std::error::Error>> = async {
+        let Some(send_rpc_url) = &config.send_rpc_url else {
+            return Ok(provider.send_transaction(tx).await?);
+        };
+        let envelope = provider.fill(tx).await?.try_into_envelope()?;
+        let sent = RootProvider::<Ethereum>::new(config.rpc_client(send_rpc_url))
+            .send_raw_transaction(&envelope.encoded_2718())
+            .await?;
+        // Watched through the public RPC, which sees the transaction once it is mined.
+        Ok(PendingTransactionBuilder::new(
+            provider.root().clone(),
+            *sent.tx_hash(),
+        ))
+    }
+    .await;
+    if sent.is_err() {
+        // The nonce may have been used up without reaching the chain; fetch it again.
+        config.nonces.reset().await;
+    }
+    sent
+}
+
+/// The executor's address and native balance.
+pub async fn executor_balance(config: &Config) -> anyhow::Result<(Address, U256)> {
+    let executor = config.caller()?;
+    Ok((executor, balance(config, executor).await?))
+}
+
+/// Checks the balance of whoever pays for gas (the executor, or the smart account with a
+/// bundler; nobody with Gelato Relay) against `min_balance`, warning while it is below.
+/// [`Config::halted`] then stops redemptions if `pause_on_low_balance` is set.
+pub async fn check_balance(config: &Config) -> anyhow::Result<()> {
+    let Some(min_balance) = config.min_balance else {
+        return Ok(());
+    };
+    if config.gelato.is_some() {
+        return Ok(());
+    }
+    let payer = match &config.bundler {
+        Some(bundler) => bundler.account,
+        None => config.executor()?,
+    };
+    let balance = balance(config, payer).await?;
+    let low = balance < min_balance;
+    if low {
+        tracing::warn!(
+            "{payer} has {} xDAI, below min_balance of {} xDAI{}",
+            format_ether(balance),
+            format_ether(min_balance),
+            if config.pause_on_low_balance {
+                "; pausing redemptions"
+            } else {
+                ""
+            }
+        );
+    } else if config.low_balance.load(Ordering::Relaxed) {
+        tracing::info!("{payer} has {} xDAI again", format_ether(balance));
+    }
+    config.low_balance.store(low, Ordering::Relaxed);
+    Ok(())
+}
+
+/// The native balance of `address`.
+pub async fn balance(config: &Config, address: Address) -> anyhow::Result<U256> {
+    let provider = ProviderBuilder::new().connect_client(config.rpc());
+    Ok(provider.get_balance(address).await?)
+}
+
+/// The pathfinder parameters for a path from `from` to `to`, with the token settings of the
+/// `path_rules` matching them.
+pub fn find_path_params(
+    config: &Config,
+    from: Address,
+    to: Address,
+    target_flow: U256,
+) -> FindPathParams {
+    let rule = config.path_rule(from, to);
+    FindPathParams {
+        from,
+        to,
+        target_flow,
+        use_wrapped_balances: Some(rule.use_wrapped_balances.unwrap_or(false)),
+        from_tokens: rule.from_tokens,
+        to_tokens: rule.to_tokens.or(rule.group.map(|group| vec![group])),
+        exclude_from_tokens: excluded(rule.exclude_from_tokens, &config.exclude_from_tokens),
+        exclude_to_tokens: excluded(rule.exclude_to_tokens, &config.exclude_to_tokens),
+        simulated_balances: None,
+        simulated_trusts: None,
+        max_transfers: None,
+    }
+}
+
+/// The tokens a path rule excludes together with those excluded globally, if any.
+fn excluded(rule: Option<Vec<Address>>, global: &[Address]) -> Option<Vec<Address>> {
+    let mut tokens = rule.unwrap_or_default();
+    for token in global {
+        if !tokens.contains(token) {
+            tokens.push(*token);
+        }
+    }
+    (!tokens.is_empty()).then_some(tokens)
+}
+
+/// Finds a transfer path from `from` to `to` and prepares the flow matrix for the contract,
+/// giving up after `pathfinder_timeout`.
+pub async fn find_path(
+    config: &Config,
+    from: Address,
+    to: Address,
+    target_flow: U256,
+) -> Result<PathData, Box<dyn std::error::Error>> {
+    let params = find_path_params(config, from, to, target_flow);
+    if let Some(path_data) = config.path_cache.get(&params) {
+        tracing::debug!("Using cached path {from} -> {to} for {target_flow}");
+        return Ok(path_data);
+    }
+    tracing::debug!("Finding path {from} -> {to} for {target_flow}");
+    let path_data =
+        pathfinder::bounded(config, config.pathfinder.find_path(config, &params)).await?;
+    tracing::trace!("{:#?}", path_data);
+    pathfinder::check_path(config, &params, &path_data)?;
+    config.path_cache.store(&params, &path_data);
+    Ok(path_data)
+}
+
+/// Whether `subscription` is redeemed with a path: trusted ones always, and group ones if a
+/// path rule names the group whose token pays them.
+pub fn needs_path(config: &Config, subscription: &RedeemableSubscription) -> bool {
+    match subscription.category {
+        Category::Trusted => true,
+        Category::Group => config
+            .path_rule(subscription.subscriber, subscription.recipient)
+            .group
+            .is_some(),
+        Category::Untrusted => false,
+    }
+}
+
+/// Builds the `data` argument for `redeem`: the encoded flow matrix for subscriptions that
+/// [need a path](needs_path), empty otherwise.
+pub async fn redeem_data(
+    config: &Config,
+    subscription: &RedeemableSubscription,
+) -> Result<Bytes, Box<dyn std::error::Error>> {
+    if !needs_path(config, subscription) {
+        return Ok(Bytes::new());
+    }
+    let (from, to) = (subscription.subscriber, subscription.recipient);
+    // Group payments reach the recipient through the group, not its trust in the subscriber.
+    if config.trust_preflight
+        && subscription.category == Category::Trusted
+        && let Some(reason) = check_trust(config, from, to).await
+    {
+        return Err(reason.into());
+    }
+    let path_data = match find_path(config, from, to, subscription.value()).await {
+        Ok(path_data) => path_data,
+        Err(e) => match partial_target(config, subscription, e.as_ref())
+            .or_else(|| reduced_target(config, e.as_ref()))
+        {
+            Some(target) => find_path(config, from, to, target).await?,
+            None => return Err(e),
+        },
+    };
+    Ok(encode_path(path_data))
+}
+
+/// Why no path from `from` to `to` can exist, if `to` accepts none of the tokens `from` holds.
+/// A path can also end in tokens of accounts in between, so this only rules out the common
+/// case. If the Circles RPC cannot answer, the path is searched anyway.
+async fn check_trust(config: &Config, from: Address, to: Address) -> Option<String> {
+    match config.circles.accepted_tokens(from, to).await {
+        Ok((0, held)) => Some(format!(
+            "no trust path: {to} accepts none of the {held} tokens {from} holds"
+        )),
+        Ok(_) => None,
+        Err(e) => {
+            tracing::debug!("Cannot check trust from {from} to {to}: {e:#}");
+            None
+        }
+    }
+}
+
+/// [`redeem_data`] for several subscriptions, in order, searching the paths of the trusted
+/// ones in a single JSON-RPC batch. A path the batch does not yield, because the pathfinder
+/// failed or found too little flow, is searched again on its own.
+pub async fn redeem_data_batch(
+    config: &Config,
+    subscriptions: &[&RedeemableSubscription],
+) -> Vec<Result<Bytes, Box<dyn std::error::Error>>> {
+    let searches: Vec<_> = subscriptions
+        .iter()
+        .map(|subscription| {
+            needs_path(config, subscription).then(|| {
+                find_path_params(
+                    config,
+                    subscription.subscriber,
+                    subscription.recipient,
+                    subscription.value(),
+                )
+            })
+        })
+        .collect();
+    let batch: Vec<_> = searches.iter().flatten().cloned().collect();
+    let mut found = match pathfinder::find_paths(config, &batch).await {
+        Ok(found) => found.into_iter(),
+        Err(e) => {
+            tracing::debug!("Batched pathfinding failed, searching one by one: {e}");
+            Vec::new().into_iter()
+        }
+    };
+    let mut data = Vec::with_capacity(subscriptions.len());
+    for (subscription, search) in subscriptions.iter().zip(&searches) {
+        let path_data = search.as_ref().and_then(|_| found.next());
+        data.push(match path_data {
+            Some(Ok(path_data)) => Ok(encode_path(path_data)),
+            Some(Err(e)) => {
+                tracing::debug!("No batched path for {}: {e}", subscription.id);
+                redeem_data(config, subscription).await
+            }
+            None => redeem_data(config, subscription).await,
+        });
+    }
+    data
+}
+
+/// The encoded flow matrix that `redeem` takes for a trusted subscription.
+fn encode_path(path_data: PathData) -> Bytes {
+    encode_redeem_trusted_data(
+        path_data.flow_vertices,
+        path_data.flow_edges,
+        path_data.streams,
+        path_data.packed_coordinates,
+        path_data.source_coordinate,
+    )
+    .into()
+}
+
+/// The size in bytes of the `redeem` calldata that would carry `path_data`.
+pub fn redeem_calldata_size(path_data: PathData) -> usize {
+    SubscriptionModule::redeemCall {
+        id: B256::ZERO,
+        data: encode_path(path_data),
+    }
+    .abi_encode()
+    .len()
+}
+
+/// The amount to route instead when the pathfinder found less flow than `subscription` is
+/// due, as reported by `e`: the whole periods that flow covers, if `partial_redemption` is
+/// set and they make up at least `partial_min_fraction` of the periods due.
+fn partial_target(
+    config: &Config,
+    subscription: &RedeemableSubscription,
+    e: &(dyn std::error::Error + 'static),
+) -> Option<U256> {
+    if !config.partial_redemption {
+        return None;
+    }
+    let shortfall = InsufficientFlow::find(e)?;
+    let amount = subscription.amount;
+    let due = u64::try_from(subscription.periods).ok()?;
+    let periods = u64::try_from(shortfall.max_flow.checked_div(amount)?)
+        .unwrap_or(u64::MAX)
+        .min(due);
+    if periods == 0 || (periods as f64) < config.partial_min_fraction * due as f64 {
+        return None;
+    }
+    tracing::warn!("Only {periods} of {due} periods can be routed, redeeming those");
+    Some(amount * U256::from(periods))
+}
+
+/// The amount to route instead when the pathfinder found less flow than the target, as
+/// reported by `e`: `reduce_target` of that flow, if set. Unlike [`partial_target`], this need
+/// not be whole periods.
+pub fn reduced_target(config: &Config, e: &(dyn std::error::Error + 'static)) -> Option<U256> {
+    let fraction = config.reduce_target?;
+    let shortfall = InsufficientFlow::find(e)?;
+    // In millionths, which is as precise as the setting needs to be.
+    let target = shortfall
+        .max_flow
+        .saturating_mul(U256::from((fraction * 1e6) as u64))
+        / U256::from(1_000_000);
+    if target.is_zero() {
+        return None;
+    }
+    tracing::warn!(
+        "Only {} of {} can be routed, searching a path for {target}",
+        shortfall.max_flow,
+        shortfall.target
+    );
+    Some(target)
+}
+
+/// A redemption that was prepared but not sent: everything a signer needs to submit it.
+#[derive(Debug, Clone, Serialize)]
+pub struct PreparedRedemption {
+    pub chain_id: u64,
+    pub to: Address,
+    pub value: U256,
+    pub data: Bytes,
+    pub gas: u64,
+}
+
+/// The pre-flight check predicts that a redemption would fail, and why.
+#[derive(Debug)]
+pub struct Rejected(pub String);
+
+impl std::fmt::Display for Rejected {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for Rejected {}
+
+/// Why [`redeem_payment`] failed, so callers can react to each class of failure.
+#[derive(Debug, thiserror::Error)]
+pub enum RedeemError {
+    /// The amount could not be parsed, or the pathfinder found no path or did not answer.
+    #[error("Pathfinding failed: {0}")]
+    Pathfinding(#[source] Box<dyn std::error::Error>),
+    /// The gas estimate reverted: the redemption would fail on-chain.
+    #[error("would revert: {0}")]
+    Simulation(String),
+    /// The transaction could not be estimated, signed or sent, or a relay, bundler or Safe
+    /// failed to execute it.
+    #[error("Failed to submit: {0}")]
+    Submission(#[source] Box<dyn std::error::Error>),
+    /// Sent, but still not mined after every rebroadcast and fee bump.
+    #[error(
+        "transaction {tx_hash} stuck: not mined after {rebroadcasts} rebroadcasts and {bumps} fee bumps"
+    )]
+    Timeout {
+        tx_hash: B256,
+        rebroadcasts: u32,
+        bumps: u32,
+    },
+    /// Mined, but reverted.
+    #[error("transaction {tx_hash} reverted in block {block}")]
+    Reverted { tx_hash: B256, block: u64 },
+    /// Sent, but the RPC failed while it was being watched; it may still be mined.
+    #[error("Failed to watch {tx_hash}: {source}")]
+    Confirmation {
+        tx_hash: B256,
+        #[source]
+        source: Box<dyn std::error::Error>,
+    },
+}
+
+impl RedeemError {
+    /// Short name of the failure class, as reported in `error_kind`.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            RedeemError::Pathfinding(_) => "pathfinding",
+            RedeemError::Simulation(_) => "simulation",
+            RedeemError::Submission(_) => "submission",
+            RedeemError::Timeout { .. } => "timeout",
+            RedeemError::Reverted { .. } => "reverted",
+            RedeemError::Confirmation { .. } => "confirmation",
+        }
+    }
+
+    /// The transaction the failure concerns, if it was sent.
+    pub fn tx_hash(&self) -> Option<B256> {
+        match self {
+            RedeemError::Timeout { tx_hash, .. }
+            | RedeemError::Reverted { tx_hash, .. }
+            | RedeemError::Confirmation { tx_hash, .. } => Some(*tx_hash),
+            _ => None,
+        }
+    }
+}
+
+/// A redemption transaction that went through.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sent {
+    pub tx_hash: B256,
+    /// Gas used, from the receipt; unknown when confirmations are not awaited, or when a
+    /// relay, bundler or Safe proposal sent it.
+    pub gas_used: Option<u64>,
+}
+
+impl From<B256> for Sent {
+    fn from(tx_hash: B256) -> Self {
+        Self {
+            tx_hash,
+            gas_used: None,
+        }
+    }
+}
+
+/// Why [`send_batch`] failed: before anything was sent, when its calls can still be redeemed
+/// one by one, or once the transaction was handed over and may yet be mined.
+#[derive(Debug, thiserror::Error)]
+pub enum BatchError {
+    /// The batch could not be simulated or estimated.
+    #[error("{0}")]
+    Unsent(Box<dyn std::error::Error>),
+    /// The batch was signed and submitted, or handed to a relay, bundler or Safe.
+    #[error("{0}")]
+    Sent(RedeemError),
+}
+
+/// Prepares the redemption exactly like [`redeem_payment`] and estimates its gas as if sent
+/// from `from`, without signing or sending anything. With `verify` set, the exact calldata is
+/// first run through [`check_onchain`]; a predicted revert is returned as [`Rejected`].
+pub async fn prepare_payment(
+    config: &Config,
+    subscription: &RedeemableSubscription,
+    from: Address,
+) -> Result<PreparedRedemption, Box<dyn std::error::Error>> {
+    let provider = ProviderBuilder::new().connect_client(config.rpc());
+    let contract = SubscriptionModule::new(subscription.contract_address, provider);
+    let (data, fallback) = redeem_data_or_fallback(config, subscription).await?;
+    if config.verify
+        && let Some(reason) = check_onchain(config, subscription, &data, from).await?
+    {
+        return Err(match fallback {
+            Some(e) => e,
+            None => Box::new(Rejected(reason)),
+        });
+    }
+    let call = contract.redeem(subscription.id, data).from(from);
+    let estimate = call
+        .estimate_gas()
+        .await
+        .map_err(|e| refused(estimate_failed(e), fallback))?;
+    Ok(PreparedRedemption {
+        chain_id: config.chain_id,
+        to: subscription.contract_address,
+        value: U256::ZERO,
+        data: call.calldata().clone(),
+        gas: gas_limit(config, estimate)?,
+    })
+}
+
+/// The gas limit for a transaction estimated at `estimate`: the estimate times
+/// `tx.gas_multiplier`, refused if it exceeds `tx.gas_cap`.
+pub fn gas_limit(config: &Config, estimate: u64) -> Result<u64, String> {
+    let limit = (estimate as f64 * config.tx.gas_multiplier).ceil() as u64;
+    match config.tx.gas_cap {
+        Some(cap) if limit > cap => Err(format!(
+            "gas limit {limit} (estimate {estimate}) exceeds the cap of {cap}"
+        )),
+        _ => Ok(limit),
+    }
+}
+
+/// Prepares the redemption for the configured executor without sending it.
+pub async fn dry_run_payment(
+    config: &Config,
     subscription: RedeemableSubscription,
-) -> Result<B256, Box<dyn std::error::Error>> {
-    let provider = ProviderBuilder::new()
-        .wallet(signer)
-        .connect_http(GNOSIS_RPC.parse()?);
+) -> Result<PreparedRedemption, Box<dyn std::error::Error>> {
+    prepare_payment(config, &subscription, config.caller()?).await
+}
+
+/// Checks an indexer entry against the chain: the module must be a deployed contract, hold
+/// the subscription with the same recipient and amount, and the redemption with `data` must
+/// not revert when called from `from`. Returns why the chain disagrees, if it does; RPC
+/// failures are errors.
+pub async fn check_onchain(
+    config: &Config,
+    subscription: &RedeemableSubscription,
+    data: &Bytes,
+    from: Address,
+) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    let provider = ProviderBuilder::new().connect_client(config.rpc());
+    if provider
+        .get_code_at(subscription.contract_address)
+        .await?
+        .is_empty()
+    {
+        return Ok(Some(format!(
+            "no contract at {}",
+            subscription.contract_address
+        )));
+    }
     let contract = SubscriptionModule::new(subscription.contract_address, provider);
-    let tx;
-    if subscription.category != Category::Trusted {
-        tx = contract
-            .redeem(subscription.id, vec![].into())
-            .send()
-            .await?;
+    // A module without the view function cannot be checked; the redemption call still is.
+    match contract.getSubscription(subscription.id).call().await {
+        Ok(onchain) => {
+            if let Some(mismatch) = terms_mismatch(subscription, &onchain) {
+                tracing::warn!("Skipping subscription {}: {mismatch}", subscription.id);
+                return Ok(Some(mismatch));
+            }
+        }
+        Err(e) if unanswered(&e) => {}
+        Err(e) => match e.as_revert_data() {
+            Some(data) => return Ok(Some(format!("not found: {}", revert_reason(&data)))),
+            None => return Err(e.into()),
+        },
+    }
+    match contract
+        .redeem(subscription.id, data.clone())
+        .from(from)
+        .call()
+        .await
+    {
+        Ok(_) => Ok(None),
+        Err(e) => match e.as_revert_data() {
+            Some(data) => Ok(Some(format!("would revert: {}", revert_reason(&data)))),
+            None => Err(e.into()),
+        },
+    }
+}
+
+/// How the indexer's recipient or amount differ from the module's record, if they do.
+fn terms_mismatch(
+    subscription: &RedeemableSubscription,
+    onchain: &SubscriptionModule::Subscription,
+) -> Option<String> {
+    if onchain.recipient != subscription.recipient {
+        Some(format!(
+            "recipient is {} on chain, not {} as indexed",
+            onchain.recipient, subscription.recipient
+        ))
+    } else if onchain.amount != subscription.amount {
+        Some(format!(
+            "amount is {} on chain, not {} as indexed",
+            onchain.amount, subscription.amount
+        ))
     } else {
-        let amount = U256::from_str(&subscription.amount)?;
-        let periods = U256::from(subscription.periods as u64);
-        let params = FindPathParams {
-            from: subscription.subscriber,
-            to: subscription.recipient,
-            target_flow: amount * periods,
-            use_wrapped_balances: Some(false),
-            from_tokens: None,
-            to_tokens: None,
-            exclude_from_tokens: None,
-            exclude_to_tokens: None,
-            simulated_balances: None,
-            simulated_trusts: None,
-            max_transfers: None,
-        };
+        None
+    }
+}
+
+/// A readable reason for a revert: the message of `Error(string)`, the panic code, the name of
+/// one of the module's custom errors, or else the selector and arguments of an unknown one.
+pub fn revert_reason(data: &[u8]) -> String {
+    if let Ok(error) = GenericContractError::abi_decode(data) {
+        return error.to_string();
+    }
+    if let Some((selector, _)) = data.split_first_chunk::<4>()
+        && let Some(name) =
+            SubscriptionModule::SubscriptionModuleErrors::name_by_selector(*selector)
+    {
+        return name.to_string();
+    }
+    match data.split_first_chunk::<4>() {
+        Some((selector, [])) => format!("custom error {}", hex::encode_prefixed(selector)),
+        Some((selector, args)) => format!(
+            "custom error {} ({} bytes of arguments: {})",
+            hex::encode_prefixed(selector),
+            args.len(),
+            hex::encode_prefixed(args)
+        ),
+        None if data.is_empty() => "no reason given".to_string(),
+        None => decode_revert_reason(data).unwrap_or_else(|| hex::encode_prefixed(data)),
+    }
+}
+
+/// A failed gas estimate, with the revert reason decoded if the call reverted.
+fn estimate_failed(e: alloy::contract::Error) -> RedeemError {
+    match e.as_revert_data() {
+        Some(data) => RedeemError::Simulation(revert_reason(&data)),
+        None => RedeemError::Submission(e.into()),
+    }
+}
+
+/// Asks the module whether `subscription` is due, since the indexer's clock and the chain's
+/// can disagree. Returns why it is not, if the module says no period can be redeemed yet or
+/// rejects the subscription outright. A module without this view function reverts without
+/// data, and the redemption goes ahead; any other failure is an error.
+pub async fn check_due(
+    config: &Config,
+    subscription: &RedeemableSubscription,
+) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    let provider = ProviderBuilder::new().connect_client(config.rpc());
+    let contract = SubscriptionModule::new(subscription.contract_address, provider);
+    match contract.isValidOrRedeemable(subscription.id).call().await {
+        Ok(periods) if periods.is_zero() => Ok(Some("not yet due on-chain".to_string())),
+        Ok(_) => Ok(None),
+        Err(e) => match e.as_revert_data() {
+            Some(data) if !data.is_empty() => Ok(Some(format!(
+                "not redeemable on-chain: {}",
+                revert_reason(&data)
+            ))),
+            _ if unanswered(&e) => {
+                tracing::debug!(
+                    "{} cannot tell if it is due: {e}",
+                    subscription.contract_address
+                );
+                Ok(None)
+            }
+            _ => Err(e.into()),
+        },
+    }
+}
+
+/// Whether a view call failed because the contract does not implement it: execution reverted
+/// without any return data. Anything else, including an RPC failure or an undecodable answer,
+/// is not mistaken for a missing function.
+fn unanswered(e: &alloy::contract::Error) -> bool {
+    let alloy::contract::Error::TransportError(e) = e else {
+        return false;
+    };
+    e.as_error_resp().is_some_and(|resp| {
+        resp.message.contains("execution reverted")
+            && resp.as_revert_data().is_none_or(|data| data.is_empty())
+    })
+}
+
+/// ERC-1967 storage slot holding a proxy's implementation address.
+const IMPLEMENTATION_SLOT: B256 =
+    b256!("0x360894a13ba1a3210667c828492db98dca3e2076cc3735a920a3ca505d382bbc");
+
+/// Verifies that `module` is a deployed SubscriptionModule: it must have code, and that code
+/// (or, for an ERC-1967 proxy, its implementation's) must dispatch `redeem(bytes32,bytes)`.
+/// Returns a short description of what was found.
+pub async fn check_module(config: &Config, module: Address) -> anyhow::Result<String> {
+    let provider = ProviderBuilder::new().connect_client(config.rpc());
+    let code = provider.get_code_at(module).await?;
+    if code.is_empty() {
+        return Err(anyhow::anyhow!("no contract deployed at {module}"));
+    }
+    if implements_redeem(&code) {
+        return Ok(format!("{module} ({} bytes of code)", code.len()));
+    }
+    let slot = provider
+        .get_storage_at(module, IMPLEMENTATION_SLOT.into())
+        .await?;
+    let implementation = Address::from_word(slot.into());
+    if !implementation.is_zero() && implements_redeem(&provider.get_code_at(implementation).await?)
+    {
+        return Ok(format!("{module} (proxy to {implementation})"));
+    }
+    Err(anyhow::anyhow!(
+        "contract at {module} does not implement SubscriptionModule.redeem"
+    ))
+}
+
+/// Whether `code` contains the `redeem` selector, as a function dispatcher would.
+fn implements_redeem(code: &[u8]) -> bool {
+    code.windows(4)
+        .any(|window| window == SubscriptionModule::redeemCall::SELECTOR)
+}
+
+/// Runs the redemption through `eth_call` as if sent from `from`, without changing any state.
+/// A revert is returned as an error carrying the decoded reason.
+pub async fn simulate_payment(
+    config: &Config,
+    subscription: &RedeemableSubscription,
+    from: Address,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let data = redeem_data(config, subscription).await?;
+    match check_onchain(config, subscription, &data, from).await? {
+        None => Ok(()),
+        Some(reason) => Err(reason.into()),
+    }
+}
 
-        // This automatically:
-        // - Finds the optimal path
-        // - Creates the flow matrix
-        // - Converts to contract-compatible types
-        // - Handles flow balancing
-        let path_data = prepare_flow_for_contract(CIRCLES_RPC, params).await?;
-        let data = encode_redeem_trusted_data(
-            path_data.flow_vertices,
-            path_data.flow_edges,
-            path_data.streams,
-            path_data.packed_coordinates,
-            path_data.source_coordinate,
+/// Signs and sends a redemption prepared by [`prepare_payment`].
+pub async fn send_prepared(
+    config: &Config,
+    prepared: &PreparedRedemption,
+) -> Result<Sent, Box<dyn std::error::Error>> {
+    let tx = TransactionRequest::default()
+        .to(prepared.to)
+        .value(prepared.value)
+        .input(prepared.data.clone().into())
+        .gas_limit(prepared.gas);
+    if let Some(sent) = delegate(config, prepared.to, &prepared.data).await {
+        return sent;
+    }
+    Ok(confirm(config, submit(config, tx).await?).await?)
+}
+
+/// Sends a call of `data` on `to` through Gelato Relay, an ERC-4337 bundler or a Safe instead
+/// of from the executor, if one is configured, and waits until it is included.
+async fn delegate(
+    config: &Config,
+    to: Address,
+    data: &Bytes,
+) -> Option<Result<Sent, Box<dyn std::error::Error>>> {
+    if let Some(gelato) = &config.gelato {
+        return Some(
+            gelato
+                .sponsored_call(config, to, data)
+                .await
+                .map(Sent::from)
+                .map_err(Into::into),
         );
-        tx = contract.redeem(subscription.id, data.into()).send().await?;
     }
+    if let Some(bundler) = &config.bundler {
+        return Some(
+            bundler
+                .send(config, to, data)
+                .await
+                .map(Sent::from)
+                .map_err(Into::into),
+        );
+    }
+    if let Some(safe) = &config.safe {
+        return Some(safe.send(config, to, data).await);
+    }
+    None
+}
+
+pub async fn redeem_payment(
+    config: &Config,
+    subscription: RedeemableSubscription,
+) -> Result<Sent, RedeemError> {
+    let found = Instant::now();
+    let (data, fallback) = redeem_data_or_fallback(config, &subscription)
+        .await
+        .map_err(RedeemError::Pathfinding)?;
+    send_redeem(config, &subscription, data, found)
+        .await
+        .map_err(|e| refused(e, fallback))
+}
+
+/// Whether a path searched at `found` is older than `max_path_age` allows to submit.
+pub fn path_expired(config: &Config, found: Instant) -> bool {
+    config
+        .max_path_age
+        .is_some_and(|max_age| found.elapsed() > max_age)
+}
+
+/// Whether the gas estimate failed because a balance the path spends is gone.
+fn short_of_balance(e: &alloy::contract::Error) -> bool {
+    e.as_revert_data()
+        .is_some_and(|data| data.starts_with(&ERC1155InsufficientBalance::SELECTOR))
+}
+
+/// The calldata for `subscription` with a newly searched path, replacing a stale one.
+async fn repath(
+    config: &Config,
+    subscription: &RedeemableSubscription,
+) -> Result<Bytes, RedeemError> {
+    // The cached path may be the stale one.
+    config.path_cache.clear();
+    redeem_data(config, subscription)
+        .await
+        .map_err(RedeemError::Pathfinding)
+}
+
+/// The calldata from [`redeem_data`], or none at all for a trusted subscription without a
+/// path when `untrusted_fallback` is set. The pathfinding error then comes along, to be
+/// reported instead should the module refuse the untrusted redemption.
+pub async fn redeem_data_or_fallback(
+    config: &Config,
+    subscription: &RedeemableSubscription,
+) -> Result<(Bytes, Option<Box<dyn std::error::Error>>), Box<dyn std::error::Error>> {
+    match redeem_data(config, subscription).await {
+        Ok(data) => Ok((data, None)),
+        Err(e) if config.untrusted_fallback && subscription.category == Category::Trusted => {
+            tracing::warn!("Pathfinding failed, redeeming as untrusted instead: {e}");
+            Ok((Bytes::new(), Some(e)))
+        }
+        Err(e) => Err(e),
+    }
+}
 
-    Ok(*tx.tx_hash())
+/// `error`, unless it is the module refusing an untrusted fallback: the pathfinder's failure
+/// is what counts then.
+pub fn refused(error: RedeemError, fallback: Option<Box<dyn std::error::Error>>) -> RedeemError {
+    match (error, fallback) {
+        (RedeemError::Simulation(reason), Some(e)) => {
+            tracing::debug!("Untrusted redemption refused: {reason}");
+            RedeemError::Pathfinding(e)
+        }
+        (error, _) => error,
+    }
+}
+
+/// Runs `attempt` until it succeeds or its class of failure has been retried as often as
+/// `redeem_retry` allows, waiting `redeem_retry.backoff` in between. `repath` says whether
+/// each attempt searches a new path, which is what makes retrying a revert worthwhile.
+pub async fn retrying<F, Fut>(
+    config: &Config,
+    repath: bool,
+    mut attempt: F,
+) -> Result<Sent, RedeemError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<Sent, RedeemError>>,
+{
+    let mut retried: HashMap<&'static str, u32> = HashMap::new();
+    loop {
+        let error = match attempt().await {
+            Ok(sent) => return Ok(sent),
+            Err(e) => e,
+        };
+        let (class, limit) = config.redeem_retry.limit(&error, repath);
+        let count = retried.entry(class).or_default();
+        if *count >= limit || config.halted().is_some() {
+            return Err(error);
+        }
+        *count += 1;
+        if class == "stale_path" {
+            // The point of the retry is a fresh path, not the cached one that reverts.
+            config.path_cache.clear();
+        }
+        tracing::warn!("Retrying after {class} failure ({count}/{limit}): {error}");
+        tokio::time::sleep(config.redeem_retry.backoff).await;
+    }
+}
+
+/// Waits until `pending` is mined and `config.confirmations` blocks deep, failing if it
+/// reverted, and returns the mined transaction with the gas its receipt shows. With no
+/// confirmations required, returns as soon as the transaction is sent, gas unknown.
+///
+/// A transaction still pending after `config.tx.timeout` is replaced with higher fees if the
+/// base fee has risen above what it offers (up to `config.tx.max_bumps` times, never above
+/// `config.tx.fee_ceiling`), and otherwise rebroadcast, up to `config.tx.rebroadcasts` times,
+/// in case it dropped out of the mempool. After that the redemption fails, since its nonce
+/// blocks every later transaction.
+pub async fn confirm(
+    config: &Config,
+    pending: PendingTransactionBuilder<Ethereum>,
+) -> Result<Sent, RedeemError> {
+    let tx_hash = *pending.tx_hash();
+    if config.confirmations == 0 {
+        return Ok(tx_hash.into());
+    }
+    tracing::debug!(
+        "Sent {tx_hash}, waiting for {} confirmations",
+        config.confirmations
+    );
+    let provider = pending.provider().clone();
+    // Receipts are awaited on the WebSocket if there is one; everything else stays on HTTP.
+    let watcher = match &config.ws {
+        Some(ws) => match ws.provider(config.chain_id).await {
+            Ok(watcher) => watcher,
+            Err(e) => {
+                tracing::warn!("Watching {tx_hash} by polling instead: {e:#}");
+                provider.clone()
+            }
+        },
+        None => provider.clone(),
+    };
+    // Every version sent with this nonce; any one of them may be the one that is mined.
+    let mut sent = vec![tx_hash];
+    let (mut rebroadcasts, mut bumps) = (0, 0);
+    let watch_failed = |tx_hash, source| RedeemError::Confirmation { tx_hash, source };
+    let receipt = loop {
+        let tx_hash = *sent.last().unwrap_or(&tx_hash);
+        let result = PendingTransactionBuilder::new(watcher.clone(), tx_hash)
+            .with_required_confirmations(config.confirmations)
+            .with_timeout(config.tx.timeout)
+            .get_receipt()
+            .await;
+        match result {
+            Ok(receipt) => break receipt,
+            Err(PendingTransactionError::TxWatcher(WatchTxError::Timeout))
+                if let Some(mined) = mined(&provider, &sent)
+                    .await
+                    .map_err(|e| watch_failed(tx_hash, e))? =>
+            {
+                // Mined but not yet deep enough, or an earlier version won the race.
+                if mined != tx_hash {
+                    tracing::info!("{mined}, replaced by {tx_hash}, was mined instead");
+                    sent.push(mined);
+                }
+            }
+            Err(PendingTransactionError::TxWatcher(WatchTxError::Timeout))
+                if bumps < config.tx.max_bumps
+                    && let Some(replacement) = bump(config, &provider, tx_hash)
+                        .await
+                        .map_err(|e| watch_failed(tx_hash, e))? =>
+            {
+                bumps += 1;
+                tracing::warn!(
+                    "{tx_hash} priced out, replaced by {replacement} ({bumps}/{})",
+                    config.tx.max_bumps
+                );
+                sent.push(replacement);
+            }
+            Err(PendingTransactionError::TxWatcher(WatchTxError::Timeout))
+                if rebroadcasts < config.tx.rebroadcasts =>
+            {
+                rebroadcasts += 1;
+                tracing::warn!(
+                    "{tx_hash} still pending, rebroadcasting ({rebroadcasts}/{})",
+                    config.tx.rebroadcasts
+                );
+                rebroadcast(config, &provider, tx_hash).await;
+            }
+            Err(PendingTransactionError::TxWatcher(WatchTxError::Timeout)) => {
+                return Err(RedeemError::Timeout {
+                    tx_hash,
+                    rebroadcasts,
+                    bumps,
+                });
+            }
+            Err(e) => return Err(watch_failed(tx_hash, e.into())),
+        }
+    };
+    let tx_hash = receipt.transaction_hash;
+    if !receipt.status() {
+        return Err(RedeemError::Reverted {
+            tx_hash,
+            block: receipt.block_number.unwrap_or_default(),
+        });
+    }
+    tracing::info!(
+        "Confirmed {tx_hash} in block {} ({} gas)",
+        receipt.block_number.unwrap_or_default(),
+        receipt.gas_used
+    );
+    Ok(Sent {
+        tx_hash,
+        gas_used: Some(receipt.gas_used),
+    })
+}
+
+/// The first of `sent` that has been mined, if any.
+async fn mined(
+    provider: &impl Provider,
+    sent: &[B256],
+) -> Result<Option<B256>, Box<dyn std::error::Error>> {
+    for &tx_hash in sent {
+        if provider.get_transaction_receipt(tx_hash).await?.is_some() {
+            return Ok(Some(tx_hash));
+        }
+    }
+    Ok(None)
+}
+
+/// Replaces `tx_hash` with a copy offering higher fees, if the current fee estimate exceeds
+/// its max fee. Returns the replacement's hash, or `None` if no bump is needed or the bumped
+/// fee would exceed the ceiling.
+async fn bump(
+    config: &Config,
+    root: &impl Provider,
+    tx_hash: B256,
+) -> Result<Option<B256>, Box<dyn std::error::Error>> {
+    let Some(tx) = root.get_transaction_by_hash(tx_hash).await? else {
+        return Ok(None);
+    };
+    let estimate = root.estimate_eip1559_fees().await?;
+    let max_fee = tx.max_fee_per_gas();
+    if estimate.max_fee_per_gas <= max_fee {
+        return Ok(None);
+    }
+    let priority_fee = tx.max_priority_fee_per_gas().unwrap_or_default();
+    let Some((max_fee, priority_fee)) = bumped_fees(
+        (max_fee, priority_fee),
+        (estimate.max_fee_per_gas, estimate.max_priority_fee_per_gas),
+        config.tx.fee_ceiling,
+    ) else {
+        tracing::warn!("Cannot bump {tx_hash}: the fee ceiling is reached");
+        return Ok(None);
+    };
+    let mut replacement = TransactionRequest::default()
+        .value(tx.value())
+        .input(tx.input().clone().into())
+        .gas_limit(tx.gas_limit())
+        .nonce(tx.nonce())
+        .max_fee_per_gas(max_fee)
+        .max_priority_fee_per_gas(priority_fee);
+    if let Some(to) = tx.to() {
+        replacement = replacement.to(to);
+    }
+    let pending = submit(config, replacement).await?;
+    Ok(Some(*pending.tx_hash()))
+}
+
+/// Fees for a replacement of a transaction offering `current` (max fee, priority fee) when
+/// the market asks for `estimate`: at least 12.5% more than before, as nodes require for
+/// replacements, and at least the estimate. `None` if that exceeds `ceiling` (in wei).
+fn bumped_fees(
+    current: (u128, u128),
+    estimate: (u128, u128),
+    ceiling: Option<u128>,
+) -> Option<(u128, u128)> {
+    let bump = |fee: u128| fee + fee.div_ceil(8).max(1);
+    let max_fee = bump(current.0).max(estimate.0);
+    let priority_fee = bump(current.1).max(estimate.1).min(max_fee);
+    match ceiling {
+        Some(ceiling) if max_fee > ceiling => None,
+        _ => Some((max_fee, priority_fee)),
+    }
+}
+
+/// Sends the signed transaction `tx_hash` to the RPC (or `send_rpc_url`) again. Failures are
+/// only logged: most likely the node already has it.
+async fn rebroadcast(config: &Config, provider: &impl Provider, tx_hash: B256) {
+    match provider.get_raw_transaction_by_hash(tx_hash).await {
+        Ok(Some(raw)) => {
+            let sent = match &config.send_rpc_url {
+                Some(url) => RootProvider::<Ethereum>::new(config.rpc_client(url))
+                    .send_raw_transaction(&raw)
+                    .await
+                    .map(drop),
+                None => provider.send_raw_transaction(&raw).await.map(drop),
+            };
+            if let Err(e) = sent {
+                tracing::debug!("Rebroadcast of {tx_hash} rejected: {e}");
+            }
+        }
+        Ok(None) => tracing::warn!("The RPC no longer knows {tx_hash}; cannot rebroadcast it"),
+        Err(e) => tracing::warn!("Failed to fetch {tx_hash} for rebroadcast: {e}"),
+    }
+}
+
+/// Sends several `redeem` calls, each with `data` built by [`redeem_data`], in one Multicall3
+/// transaction. The batch is simulated first; if any call would revert, nothing is sent and
+/// `None` is returned so the caller can redeem them one by one. Failures past the gas
+/// estimate are [`BatchError::Sent`]: redeeming the calls again could pay for them twice.
+pub async fn send_batch(
+    config: &Config,
+    calls: &[(&RedeemableSubscription, Bytes)],
+) -> Result<Option<Sent>, BatchError> {
+    let unsent = |e: Box<dyn std::error::Error>| BatchError::Unsent(e);
+    let from = config.caller().map_err(|e| unsent(e.into()))?;
+    let calls3 = |allow_failure| -> Vec<Multicall3::Call3> {
+        calls
+            .iter()
+            .map(|(subscription, data)| Multicall3::Call3 {
+                target: subscription.contract_address,
+                allowFailure: allow_failure,
+                callData: SubscriptionModule::redeemCall {
+                    id: subscription.id,
+                    data: data.clone(),
+                }
+                .abi_encode()
+                .into(),
+            })
+            .collect()
+    };
+    let reader = ProviderBuilder::new().connect_client(config.rpc());
+    let results = Multicall3::new(MULTICALL3, reader)
+        .aggregate3(calls3(true))
+        .from(from)
+        .call()
+        .await
+        .map_err(|e| unsent(e.into()))?;
+    for ((subscription, _), result) in calls.iter().zip(&results) {
+        if !result.success {
+            tracing::info!(
+                "Not batching, {} would revert: {}",
+                subscription.id,
+                revert_reason(&result.returnData)
+            );
+            return Ok(None);
+        }
+    }
+
+    let multicall = Multicall3::new(MULTICALL3, provider(config).map_err(|e| unsent(e.into()))?);
+    let call = multicall.aggregate3(calls3(false)).from(from);
+    let estimate = call
+        .estimate_gas()
+        .await
+        .map_err(|e| unsent(estimate_failed(e).into()))?;
+    let gas = gas_limit(config, estimate).map_err(|e| unsent(e.into()))?;
+    tracing::info!("Sending {} redemptions in one transaction", calls.len());
+    let sent = |e| BatchError::Sent(RedeemError::Submission(e));
+    if let Some(delegated) = delegate(config, MULTICALL3, call.calldata()).await {
+        return delegated.map(Some).map_err(sent);
+    }
+    let pending = submit(config, call.gas(gas).into_transaction_request())
+        .await
+        .map_err(sent)?;
+    Ok(Some(
+        confirm(config, pending).await.map_err(BatchError::Sent)?,
+    ))
+}
+
+/// Sends `redeem` with `data` built by [`redeem_data`].
+pub async fn send_redeem(
+    config: &Config,
+    subscription: &RedeemableSubscription,
+    data: Bytes,
+    found: Instant,
+) -> Result<Sent, RedeemError> {
+    let submission = |e: Box<dyn std::error::Error>| RedeemError::Submission(e);
+    let provider = provider(config).map_err(|e| submission(e.into()))?;
+    let contract = SubscriptionModule::new(subscription.contract_address, provider);
+    let from = config.caller().map_err(|e| submission(e.into()))?;
+    // Empty data carries no path, so there is nothing to search again.
+    let has_path = !data.is_empty();
+    let data = if has_path && path_expired(config, found) {
+        tracing::info!(
+            "Path for {} is {}s old, searching a new one",
+            subscription.id,
+            found.elapsed().as_secs()
+        );
+        repath(config, subscription).await?
+    } else {
+        data
+    };
+    let mut call = contract.redeem(subscription.id, data).from(from);
+    let estimate = call.estimate_gas().await;
+    let estimate = match estimate {
+        Err(e) if has_path && short_of_balance(&e) => {
+            tracing::info!(
+                "Path for {} spends more than is left, searching a new one",
+                subscription.id
+            );
+            call = contract
+                .redeem(subscription.id, repath(config, subscription).await?)
+                .from(from);
+            call.estimate_gas().await
+        }
+        estimate => estimate,
+    };
+    let gas =
+        gas_limit(config, estimate.map_err(estimate_failed)?).map_err(|e| submission(e.into()))?;
+    tracing::debug!(
+        "Sending redeem with {} bytes of data and {gas} gas",
+        call.calldata().len()
+    );
+    if let Some(sent) = delegate(config, subscription.contract_address, call.calldata()).await {
+        return sent.map_err(submission);
+    }
+    let pending = submit(config, call.gas(gas).into_transaction_request())
+        .await
+        .map_err(submission)?;
+    confirm(config, pending).await
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[tokio::test]
+    async fn test_unanswered() {
+        use alloy::rpc::json_rpc::ErrorPayload;
+        use alloy::transports::mock::Asserter;
+        let asserter = Asserter::new();
+        let provider = ProviderBuilder::new().connect_mocked_client(asserter.clone());
+        let contract = SubscriptionModule::new(Address::ZERO, provider);
+        let call = contract.isValidOrRedeemable(B256::ZERO);
+
+        // A module without the function reverts without a reason.
+        asserter.push_failure_msg("execution reverted");
+        assert!(unanswered(&call.call().await.unwrap_err()));
+
+        // Other failures are not taken for a missing function.
+        asserter.push_success(&Bytes::new());
+        assert!(!unanswered(&call.call().await.unwrap_err()));
+        asserter.push_failure_msg("header not found");
+        assert!(!unanswered(&call.call().await.unwrap_err()));
+        asserter.push_failure(ErrorPayload {
+            code: 3,
+            message: "execution reverted".into(),
+            data: Some(serde_json::value::to_raw_value("0xdeadbeef").unwrap()),
+        });
+        assert!(!unanswered(&call.call().await.unwrap_err()));
+
+        asserter.push_success(&B256::with_last_byte(2));
+        assert_eq!(call.call().await.unwrap(), U256::from(2));
+    }
+
+    #[tokio::test]
+    async fn test_short_of_balance() {
+        use alloy::rpc::json_rpc::ErrorPayload;
+        use alloy::transports::mock::Asserter;
+        let asserter = Asserter::new();
+        let provider = ProviderBuilder::new().connect_mocked_client(asserter.clone());
+        let contract = SubscriptionModule::new(Address::ZERO, provider);
+        let call = contract.redeem(B256::ZERO, Bytes::new());
+        let reverted = |data: Vec<u8>| ErrorPayload {
+            code: 3,
+            message: "execution reverted".into(),
+            data: Some(serde_json::value::to_raw_value(&hex::encode_prefixed(data)).unwrap()),
+        };
+
+        let insufficient = ERC1155InsufficientBalance {
+            sender: Address::with_last_byte(1),
+            balance: U256::from(1),
+            needed: U256::from(2),
+            tokenId: U256::ZERO,
+        };
+        asserter.push_failure(reverted(insufficient.abi_encode()));
+        assert!(short_of_balance(&call.call().await.unwrap_err()));
+        asserter.push_failure(reverted(hex::decode("0xdeadbeef").unwrap()));
+        assert!(!short_of_balance(&call.call().await.unwrap_err()));
+    }
+
+    #[test]
+    fn test_path_expired() {
+        use crate::config::Layer;
+        let config = |max_path_age| {
+            Config::from_layer(Layer {
+                max_path_age,
+                ..Layer::defaults(None)
+            })
+            .unwrap()
+        };
+        let found = Instant::now() - std::time::Duration::from_secs(60);
+        assert!(!path_expired(&config(None), found));
+        assert!(!path_expired(
+            &config(Some(std::time::Duration::from_secs(120))),
+            found
+        ));
+        assert!(path_expired(
+            &config(Some(std::time::Duration::from_secs(30))),
+            found
+        ));
+    }
+
+    #[test]
+    fn test_partial_target() {
+        use crate::config::Layer;
+        use alloy::primitives::aliases::U192;
+        use circles_pathfinder::PathfinderError;
+        let subscription = RedeemableSubscription {
+            contract_address: Address::ZERO,
+            id: B256::ZERO,
+            recipient: Address::ZERO,
+            subscriber: Address::ZERO,
+            amount: U256::from(100),
+            periods: 4,
+            category: Category::Trusted,
+        };
+        let short = |max_flow: u64| InsufficientFlow {
+            max_flow: U256::from(max_flow),
+            target: U256::from(400),
+        };
+        let config = |enabled| {
+            Config::from_layer(Layer {
+                partial_redemption: Some(enabled),
+                ..Layer::defaults(None)
+            })
+            .unwrap()
+        };
+
+        let enabled = config(true);
+        assert_eq!(
+            partial_target(&enabled, &subscription, &short(250)),
+            Some(U256::from(200))
+        );
+        // One of four periods is below the default half.
+        assert_eq!(partial_target(&enabled, &subscription, &short(150)), None);
+        assert_eq!(
+            partial_target(&config(false), &subscription, &short(250)),
+            None
+        );
+        // The flow matrix reports a shortfall of its own.
+        let imbalanced = PathfinderError::Imbalanced {
+            terminal_sum: U192::from(250),
+            expected: U192::from(400),
+        };
+        assert_eq!(
+            partial_target(&enabled, &subscription, &imbalanced),
+            Some(U256::from(200))
+        );
+    }
+
+    #[test]
+    fn test_find_path_params_exclusions() {
+        use crate::config::{Layer, PathRule};
+        let (subscriber, recipient) = (Address::with_last_byte(1), Address::with_last_byte(2));
+        let (dust, group) = (Address::with_last_byte(3), Address::with_last_byte(4));
+        let config = Config::from_layer(Layer {
+            exclude_from_tokens: Some(vec![dust]),
+            exclude_to_tokens: Some(vec![group]),
+            path_rules: Some(vec![PathRule {
+                recipient: Some(recipient),
+                exclude_from_tokens: Some(vec![group, dust]),
+                ..Default::default()
+            }]),
+            ..Layer::defaults(None)
+        })
+        .unwrap();
+
+        let params = find_path_params(&config, subscriber, recipient, U256::from(1));
+        assert_eq!(params.exclude_from_tokens, Some(vec![group, dust]));
+        assert_eq!(params.exclude_to_tokens, Some(vec![group]));
+        let params = find_path_params(&config, subscriber, Address::ZERO, U256::from(1));
+        assert_eq!(params.exclude_from_tokens, Some(vec![dust]));
+
+        let config = Config::from_layer(Layer::defaults(None)).unwrap();
+        let params = find_path_params(&config, subscriber, recipient, U256::from(1));
+        assert_eq!(params.exclude_from_tokens, None);
+    }
+
+    #[test]
+    fn test_group_paths() {
+        use crate::config::{Layer, PathRule};
+        let (group, other) = (Address::with_last_byte(4), Address::with_last_byte(5));
+        let subscription = |recipient| RedeemableSubscription {
+            contract_address: Address::ZERO,
+            id: B256::ZERO,
+            recipient,
+            subscriber: Address::with_last_byte(1),
+            amount: U256::from(100),
+            periods: 1,
+            category: Category::Group,
+        };
+        let config = Config::from_layer(Layer {
+            path_rules: Some(vec![PathRule {
+                recipient: Some(Address::with_last_byte(2)),
+                group: Some(group),
+                ..Default::default()
+            }]),
+            ..Layer::defaults(None)
+        })
+        .unwrap();
+
+        let paid = subscription(Address::with_last_byte(2));
+        assert!(needs_path(&config, &paid));
+        let params = find_path_params(&config, paid.subscriber, paid.recipient, paid.value());
+        assert_eq!(params.to_tokens, Some(vec![group]));
+        // Without a group to pay in, group subscriptions are redeemed without a path.
+        assert!(!needs_path(&config, &subscription(other)));
+    }
+
+    #[test]
+    fn test_reduced_target() {
+        use crate::config::Layer;
+        let short = InsufficientFlow {
+            max_flow: U256::from(1000),
+            target: U256::from(4000),
+        };
+        let config = |reduce_target| {
+            Config::from_layer(Layer {
+                reduce_target,
+                ..Layer::defaults(None)
+            })
+            .unwrap()
+        };
+        assert_eq!(
+            reduced_target(&config(Some(0.95)), &short),
+            Some(U256::from(950))
+        );
+        assert_eq!(
+            reduced_target(&config(Some(1.0)), &short),
+            Some(U256::from(1000))
+        );
+        assert_eq!(reduced_target(&config(None), &short), None);
+        let no_flow = InsufficientFlow {
+            max_flow: U256::ZERO,
+            ..short
+        };
+        assert_eq!(reduced_target(&config(Some(0.95)), &no_flow), None);
+        assert!(
+            Config::from_layer(Layer {
+                reduce_target: Some(1.5),
+                ..Layer::defaults(None)
+            })
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_refused_fallback() {
+        let revert = || RedeemError::Simulation("revert: PathRequired".to_string());
+        let error = refused(revert(), Some("terminal sum 800 != expected 1000".into()));
+        assert_eq!(error.kind(), "pathfinding");
+        assert_eq!(refused(revert(), None).kind(), "simulation");
+        let error = RedeemError::Submission("nonce too low".into());
+        assert_eq!(refused(error, Some("no path".into())).kind(), "submission");
+    }
+
+    #[test]
+    fn test_terms_mismatch() {
+        let subscription = RedeemableSubscription {
+            contract_address: Address::ZERO,
+            id: B256::ZERO,
+            recipient: Address::with_last_byte(2),
+            subscriber: Address::with_last_byte(1),
+            amount: U256::from(10),
+            periods: 1,
+            category: Category::Untrusted,
+        };
+        let mut onchain = SubscriptionModule::Subscription {
+            subscriber: subscription.subscriber,
+            recipient: subscription.recipient,
+            amount: subscription.amount,
+            nextRedeemAt: U256::ZERO,
+            frequency: U256::from(60),
+            category: 0,
+        };
+        assert_eq!(terms_mismatch(&subscription, &onchain), None);
+        onchain.amount = U256::from(11);
+        assert!(
+            terms_mismatch(&subscription, &onchain)
+                .unwrap()
+                .starts_with("amount is 11 on chain")
+        );
+        onchain.recipient = Address::with_last_byte(3);
+        assert!(
+            terms_mismatch(&subscription, &onchain)
+                .unwrap()
+                .starts_with("recipient is")
+        );
+    }
+
+    #[test]
+    fn test_revert_reason() {
+        use alloy::sol_types::{Revert, SolError};
+        let revert = Revert::from("subscription not redeemable").abi_encode();
+        assert_eq!(
+            revert_reason(&revert),
+            "revert: subscription not redeemable"
+        );
+        assert_eq!(
+            revert_reason(&[0xde, 0xad, 0xbe, 0xef]),
+            "custom error 0xdeadbeef"
+        );
+        assert_eq!(
+            revert_reason(&SubscriptionModule::NotRedeemable {}.abi_encode()),
+            "NotRedeemable"
+        );
+        assert_eq!(revert_reason(&[]), "no reason given");
+    }
+
+    #[test]
+    fn test_gas_limit() {
+        let mut config = Config::from_layer(crate::config::Layer::defaults(None)).unwrap();
+        config.tx.gas_multiplier = 1.5;
+        assert_eq!(gas_limit(&config, 100_000), Ok(150_000));
+        config.tx.gas_cap = Some(120_000);
+        assert!(gas_limit(&config, 100_000).is_err());
+    }
+
+    #[test]
+    fn test_bumped_fees() {
+        // At least 12.5% above the current fees.
+        assert_eq!(bumped_fees((80, 8), (50, 2), None), Some((90, 9)));
+        // At least the market estimate.
+        assert_eq!(bumped_fees((80, 8), (200, 20), None), Some((200, 20)));
+        assert_eq!(bumped_fees((80, 8), (200, 20), Some(150)), None);
+    }
+
+    #[test]
+    fn test_implements_redeem() {
+        let selector = SubscriptionModule::redeemCall::SELECTOR;
+        // PUSH4 <selector> EQ, as emitted by a Solidity function dispatcher.
+        let code = [&[0x63][..], &selector, &[0x14]].concat();
+        assert!(implements_redeem(&code));
+        assert!(!implements_redeem(&[0x60, 0x80, 0x60, 0x40, 0x52]));
+    }
+
     #[test]
     fn test_deserialize_redeemable_subscription() {
         let json = r#"{
@@ -126,7 +1607,7 @@ mod tests {
                 .parse::<Address>()
                 .unwrap()
         );
-        assert_eq!(sub.amount, "10000000000000000");
+        assert_eq!(sub.amount, U256::from(10_000_000_000_000_000u64));
         assert_eq!(sub.periods, 5);
         assert_eq!(sub.category, Category::Trusted);
     }
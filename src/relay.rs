@@ -0,0 +1,118 @@
+use alloy::primitives::{Address, B256, Bytes};
+use anyhow::{Context, Result, anyhow};
+use reqwest::Url;
+use serde::Deserialize;
+use tokio::time::{Duration, Instant};
+
+use crate::{config::Config, fetch};
+
+/// How often a relay task's status is polled.
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Gelato Relay, submitting redemptions as sponsored calls paid from the operator's Gelato
+/// balance, so the executor key needs no xDAI. The relay, not the executor, is then the
+/// caller seen by the module.
+pub struct Gelato {
+    pub url: Url,
+    pub api_key: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Task {
+    task_id: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct TaskStatus {
+    task: TaskState,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TaskState {
+    task_state: String,
+    transaction_hash: Option<B256>,
+    last_check_message: Option<String>,
+}
+
+impl Gelato {
+    /// Submits a call of `data` on `to` and waits until the relay has executed it, failing if
+    /// it reverted, was cancelled, or is still pending after `tx.timeout`. Returns the hash of
+    /// the relay's transaction.
+    pub async fn sponsored_call(&self, config: &Config, to: Address, data: &Bytes) -> Result<B256> {
+        let url = self.url.join("relays/v2/sponsored-call")?;
+        let request = config.http.post(url).json(&serde_json::json!({
+            "chainId": config.chain_id.to_string(),
+            "target": to,
+            "data": data,
+            "sponsorApiKey": self.api_key,
+        }));
+        let response = fetch::timed(config, request)
+            .send()
+            .await
+            .context("Failed to submit to Gelato Relay")?
+            .error_for_status()
+            .context("Gelato Relay refused the call")?;
+        let task: Task = fetch::read_json(response, config.http_limits.max_response_size).await?;
+        tracing::info!("Submitted to Gelato Relay as task {}", task.task_id);
+        self.wait(config, &task.task_id).await
+    }
+
+    async fn wait(&self, config: &Config, task_id: &str) -> Result<B256> {
+        let url = self.url.join(&format!("tasks/status/{task_id}"))?;
+        let deadline = config.tx.timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            tokio::time::sleep(POLL_INTERVAL).await;
+            let response = fetch::timed(config, config.http.get(url.clone()))
+                .send()
+                .await
+                .and_then(|response| response.error_for_status());
+            let status: TaskStatus = match response {
+                Ok(response) => {
+                    fetch::read_json(response, config.http_limits.max_response_size).await?
+                }
+                Err(e) => {
+                    tracing::debug!("Failed to poll Gelato task {task_id}: {e}");
+                    continue;
+                }
+            };
+            let task = status.task;
+            match (task.task_state.as_str(), task.transaction_hash) {
+                ("ExecSuccess", Some(tx_hash)) => return Ok(tx_hash),
+                ("ExecReverted" | "Cancelled", _) => {
+                    return Err(anyhow!(
+                        "Gelato task {task_id} {}: {}",
+                        task.task_state,
+                        task.last_check_message.unwrap_or_default()
+                    ));
+                }
+                (state, _) => tracing::debug!("Gelato task {task_id} is {state}"),
+            }
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return Err(anyhow!("Gelato task {task_id} still pending"));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deserialize_task_status() {
+        let status: TaskStatus = serde_json::from_str(
+            r#"{"task": {
+                "chainId": 100,
+                "taskId": "0x93a3",
+                "taskState": "ExecSuccess",
+                "creationDate": "2024-01-01T00:00:00.000Z",
+                "transactionHash": "0x50ede65601819b8885dc3dbf4676204fcd318c26b8281d82af20f69d55b4ca75"
+            }}"#,
+        )
+        .unwrap();
+        assert_eq!(status.task.task_state, "ExecSuccess");
+        assert!(status.task.transaction_hash.is_some());
+    }
+}
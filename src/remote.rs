@@ -0,0 +1,103 @@
+use alloy::{
+    consensus::{SignableTransaction, TxEnvelope},
+    eips::Decodable2718,
+    network::TxSigner,
+    primitives::{Address, Bytes, Signature, U256},
+    rpc::client::RpcClient,
+    signers,
+};
+
+/// A signer that has a remote service such as Web3Signer sign every transaction through its
+/// `eth_signTransaction` endpoint, so the key never exists on this host. Only transactions
+/// can be signed this way, not Safe transactions or user operations.
+#[derive(Debug, Clone)]
+pub struct RemoteSigner {
+    pub address: Address,
+    pub client: RpcClient,
+}
+
+#[async_trait::async_trait]
+impl TxSigner<Signature> for RemoteSigner {
+    fn address(&self) -> Address {
+        self.address
+    }
+
+    async fn sign_transaction(
+        &self,
+        tx: &mut dyn SignableTransaction<Signature>,
+    ) -> signers::Result<Signature> {
+        let mut request = serde_json::json!({
+            "from": self.address,
+            "to": tx.to(),
+            "gas": U256::from(tx.gas_limit()),
+            "nonce": U256::from(tx.nonce()),
+            "value": tx.value(),
+            "data": tx.input(),
+        });
+        if let Some(chain_id) = tx.chain_id() {
+            request["chainId"] = serde_json::json!(U256::from(chain_id));
+        }
+        if tx.is_dynamic_fee() {
+            request["maxFeePerGas"] = serde_json::json!(U256::from(tx.max_fee_per_gas()));
+            request["maxPriorityFeePerGas"] = serde_json::json!(U256::from(
+                tx.max_priority_fee_per_gas().unwrap_or_default()
+            ));
+        } else {
+            request["gasPrice"] = serde_json::json!(U256::from(tx.gas_price().unwrap_or_default()));
+        }
+        let raw: Bytes = self
+            .client
+            .request("eth_signTransaction", (request,))
+            .await
+            .map_err(signers::Error::other)?;
+        let signed = TxEnvelope::decode_2718(&mut raw.as_ref()).map_err(signers::Error::other)?;
+        // Only the signature is used, so it must be over exactly this transaction.
+        if signed.signature_hash() != tx.signature_hash() {
+            return Err(signers::Error::other(
+                "the remote signer signed a different transaction",
+            ));
+        }
+        Ok(*signed.signature())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy::{
+        consensus::TxEip1559,
+        eips::Encodable2718,
+        primitives::{TxKind, address},
+        signers::{SignerSync, local::PrivateKeySigner},
+        transports::mock::Asserter,
+    };
+
+    #[tokio::test]
+    async fn test_sign_transaction() {
+        let key = PrivateKeySigner::random();
+        let mut tx = TxEip1559 {
+            chain_id: 100,
+            nonce: 7,
+            gas_limit: 100_000,
+            max_fee_per_gas: 2_000_000_000,
+            max_priority_fee_per_gas: 1_000_000_000,
+            to: TxKind::Call(address!("0xcebe4b6d50ce877a9689ce4516fe96911e099a78")),
+            ..Default::default()
+        };
+        let signature = key.sign_hash_sync(&tx.signature_hash()).unwrap();
+        let raw = TxEnvelope::from(tx.clone().into_signed(signature)).encoded_2718();
+
+        let asserter = Asserter::new();
+        let remote = RemoteSigner {
+            address: key.address(),
+            client: RpcClient::mocked(asserter.clone()),
+        };
+        asserter.push_success(&Bytes::from(raw.clone()));
+        assert_eq!(remote.sign_transaction(&mut tx).await.unwrap(), signature);
+
+        // A signature over any other transaction is refused.
+        tx.nonce = 8;
+        asserter.push_success(&Bytes::from(raw));
+        assert!(remote.sign_transaction(&mut tx).await.is_err());
+    }
+}
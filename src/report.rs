@@ -0,0 +1,350 @@
+use crate::cli::OutputFormat;
+use crate::redeem::{RedeemError, Rejected, Sent};
+use alloy::primitives::{B256, U256};
+use reqwest::Url;
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Redeemed,
+    DryRun,
+    Simulated,
+    Skipped,
+    Failed,
+}
+
+impl std::fmt::Display for Status {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Status::Redeemed => "redeemed",
+            Status::DryRun => "dry-run",
+            Status::Simulated => "simulated",
+            Status::Skipped => "skipped",
+            Status::Failed => "failed",
+        })
+    }
+}
+
+/// Reason prefix of subscriptions skipped because the chain rejected the indexer's entry.
+pub const REJECTED: &str = "on-chain check failed";
+
+/// The result of processing a single subscription.
+#[derive(Debug, Clone, Serialize)]
+pub struct Outcome {
+    pub id: B256,
+    pub status: Status,
+    /// Total amount redeemed (amount × periods), where known.
+    pub value: Option<U256>,
+    pub tx_hash: Option<B256>,
+    /// Gas used by the transaction (an equal share of it in a batch); the estimate for dry
+    /// runs.
+    pub gas_used: Option<u64>,
+    pub error: Option<String>,
+    /// Class of a failed redemption, as named by [`RedeemError::kind`].
+    pub error_kind: Option<&'static str>,
+}
+
+impl Outcome {
+    /// Redeemed by `sent`, a transaction hash or one with the gas its receipt shows.
+    pub fn redeemed(id: B256, sent: impl Into<Sent>) -> Self {
+        let sent = sent.into();
+        Self {
+            id,
+            status: Status::Redeemed,
+            value: None,
+            tx_hash: Some(sent.tx_hash),
+            gas_used: sent.gas_used,
+            error: None,
+            error_kind: None,
+        }
+    }
+
+    pub fn dry_run(id: B256, gas: u64) -> Self {
+        Self {
+            id,
+            status: Status::DryRun,
+            value: None,
+            tx_hash: None,
+            gas_used: Some(gas),
+            error: None,
+            error_kind: None,
+        }
+    }
+
+    pub fn simulated(id: B256) -> Self {
+        Self {
+            id,
+            status: Status::Simulated,
+            value: None,
+            tx_hash: None,
+            gas_used: None,
+            error: None,
+            error_kind: None,
+        }
+    }
+
+    /// Whether retrying would be pointless: redeemed, or rejected by the on-chain check.
+    pub fn is_settled(&self) -> bool {
+        match self.status {
+            Status::Redeemed => true,
+            Status::Skipped => self
+                .error
+                .as_deref()
+                .is_some_and(|reason| reason.starts_with(REJECTED)),
+            _ => false,
+        }
+    }
+
+    pub fn with_value(mut self, value: Option<U256>) -> Self {
+        self.value = value;
+        self
+    }
+
+    pub fn with_tx_hash(mut self, tx_hash: Option<B256>) -> Self {
+        self.tx_hash = tx_hash;
+        self
+    }
+
+    pub fn skipped(id: B256, reason: impl ToString) -> Self {
+        Self {
+            id,
+            status: Status::Skipped,
+            value: None,
+            tx_hash: None,
+            gas_used: None,
+            error: Some(reason.to_string()),
+            error_kind: None,
+        }
+    }
+
+    pub fn failed(id: B256, error: impl ToString) -> Self {
+        Self {
+            id,
+            status: Status::Failed,
+            value: None,
+            tx_hash: None,
+            gas_used: None,
+            error: Some(error.to_string()),
+            error_kind: None,
+        }
+    }
+
+    /// Failed, with the class of failure reported alongside the error.
+    pub fn redemption_failed(id: B256, error: RedeemError) -> Self {
+        Self {
+            error_kind: Some(error.kind()),
+            ..Self::failed(id, error)
+        }
+    }
+
+    /// Skipped because the chain predicts the redemption would fail.
+    pub fn rejected(id: B256, reason: impl std::fmt::Display) -> Self {
+        Self::skipped(id, format!("{REJECTED}: {reason}"))
+    }
+
+    /// Failed, unless `error` is the pre-flight check [rejecting](Rejected) the redemption.
+    pub fn failed_or_rejected(id: B256, error: Box<dyn std::error::Error>) -> Self {
+        match error.downcast_ref::<Rejected>() {
+            Some(Rejected(reason)) => Self::rejected(id, reason),
+            None => Self::failed(id, error),
+        }
+    }
+}
+
+/// The page of `tx_hash` on the block explorer at `explorer_url`.
+pub fn tx_url(explorer_url: &Url, tx_hash: B256) -> String {
+    format!(
+        "{}/tx/{tx_hash}",
+        explorer_url.as_str().trim_end_matches('/')
+    )
+}
+
+/// Reports an outcome as it completes: a log line in text mode, one JSON object per line
+/// on stdout in JSON mode. Transaction links point to `explorer_url`.
+pub fn emit(outcome: &Outcome, format: OutputFormat, explorer_url: &Url) {
+    match format {
+        OutputFormat::Json => match serde_json::to_string(outcome) {
+            Ok(line) => println!("{line}"),
+            Err(e) => tracing::error!("Failed to serialize outcome: {e}"),
+        },
+        OutputFormat::Text => match outcome.status {
+            Status::Redeemed => tracing::info!(
+                "Redeemed at: {}",
+                tx_url(explorer_url, outcome.tx_hash.unwrap_or_default())
+            ),
+            Status::DryRun => tracing::info!(
+                "Dry run of {} estimated {} gas",
+                outcome.id,
+                outcome.gas_used.unwrap_or_default()
+            ),
+            Status::Simulated => tracing::info!("Simulation of {} succeeded", outcome.id),
+            Status::Skipped => tracing::warn!(
+                "Skipped {}: {}",
+                outcome.id,
+                outcome.error.as_deref().unwrap_or_default()
+            ),
+            Status::Failed => tracing::error!(
+                "Failed to redeem {}: {}",
+                outcome.id,
+                outcome.error.as_deref().unwrap_or_default()
+            ),
+        },
+    }
+}
+
+/// Aggregated outcomes of a run.
+#[derive(Debug, Default)]
+pub struct Summary {
+    pub outcomes: Vec<Outcome>,
+}
+
+impl Summary {
+    pub fn push(&mut self, outcome: Outcome) {
+        self.outcomes.push(outcome);
+    }
+
+    pub fn count(&self, status: Status) -> usize {
+        self.outcomes.iter().filter(|o| o.status == status).count()
+    }
+
+    /// Value of all redeemed (or, for dry runs and simulations, prepared) subscriptions.
+    pub fn total_value(&self) -> U256 {
+        self.outcomes
+            .iter()
+            .filter(|o| {
+                matches!(
+                    o.status,
+                    Status::Redeemed | Status::DryRun | Status::Simulated
+                )
+            })
+            .filter_map(|o| o.value)
+            .sum()
+    }
+
+    pub fn total_gas(&self) -> u64 {
+        self.outcomes.iter().filter_map(|o| o.gas_used).sum()
+    }
+
+    /// Renders a per-subscription table followed by the totals.
+    pub fn table(&self) -> String {
+        let mut out = format!(
+            "{:<66}  {:<8}  {:>24}  {:>10}  {}\n",
+            "ID", "STATUS", "VALUE", "GAS", "TX / ERROR"
+        );
+        for o in &self.outcomes {
+            let detail = match (&o.tx_hash, &o.error) {
+                (Some(tx_hash), _) => tx_hash.to_string(),
+                (None, Some(error)) => error.clone(),
+                (None, None) => String::new(),
+            };
+            out += &format!(
+                "{:<66}  {:<8}  {:>24}  {:>10}  {}\n",
+                o.id.to_string(),
+                o.status.to_string(),
+                o.value.map(|v| v.to_string()).unwrap_or_else(|| "-".into()),
+                o.gas_used
+                    .map(|g| g.to_string())
+                    .unwrap_or_else(|| "-".into()),
+                detail
+            );
+        }
+        out += &format!(
+            "\nredeemed: {}  dry-run: {}  simulated: {}  skipped: {}  failed: {}  total value: {}  total gas: {}\n",
+            self.count(Status::Redeemed),
+            self.count(Status::DryRun),
+            self.count(Status::Simulated),
+            self.count(Status::Skipped),
+            self.count(Status::Failed),
+            self.total_value(),
+            self.total_gas()
+        );
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serialize_outcome() {
+        let outcome = Outcome::failed(B256::ZERO, "reverted");
+        let json = serde_json::to_value(&outcome).unwrap();
+        assert_eq!(json["status"], "failed");
+        assert_eq!(json["error"], "reverted");
+        assert!(json["tx_hash"].is_null());
+    }
+
+    #[test]
+    fn test_tx_url() {
+        use crate::network::Network;
+        let tx_hash = B256::with_last_byte(1);
+        let url = |network: Network| tx_url(&network.explorer_url().parse().unwrap(), tx_hash);
+        assert_eq!(
+            url(Network::Gnosis),
+            format!("https://gnosisscan.io/tx/{tx_hash}")
+        );
+        assert_eq!(
+            url(Network::Chiado),
+            format!("https://gnosis-chiado.blockscout.com/tx/{tx_hash}")
+        );
+    }
+
+    #[test]
+    fn test_failed_or_rejected() {
+        let rejected = Outcome::failed_or_rejected(
+            B256::ZERO,
+            Box::new(Rejected("would revert: NotRedeemable".to_string())),
+        );
+        assert_eq!(rejected.status, Status::Skipped);
+        assert!(rejected.is_settled());
+        let failed = Outcome::failed_or_rejected(B256::ZERO, "connection refused".into());
+        assert_eq!(failed.status, Status::Failed);
+    }
+
+    #[test]
+    fn test_redemption_failed() {
+        let outcome = Outcome::redemption_failed(
+            B256::ZERO,
+            RedeemError::Simulation("revert: NotRedeemable".to_string()),
+        );
+        let json = serde_json::to_value(&outcome).unwrap();
+        assert_eq!(json["status"], "failed");
+        assert_eq!(json["error"], "would revert: revert: NotRedeemable");
+        assert_eq!(json["error_kind"], "simulation");
+    }
+
+    #[test]
+    fn test_summary_totals() {
+        let mut summary = Summary::default();
+        summary.push(Outcome::dry_run(B256::ZERO, 100_000).with_value(Some(U256::from(5))));
+        summary.push(Outcome::failed(B256::ZERO, "reverted").with_value(Some(U256::from(7))));
+        summary.push(Outcome::skipped(B256::ZERO, "aborted"));
+        assert_eq!(summary.count(Status::DryRun), 1);
+        assert_eq!(summary.count(Status::Failed), 1);
+        assert_eq!(summary.count(Status::Skipped), 1);
+        assert_eq!(summary.total_value(), U256::from(5));
+        assert_eq!(summary.total_gas(), 100_000);
+        assert!(summary.table().contains("skipped: 1"));
+    }
+
+    #[test]
+    fn test_summary_counts_redeemed_gas() {
+        let mut summary = Summary::default();
+        let sent = Sent {
+            tx_hash: B256::with_last_byte(1),
+            gas_used: Some(84_000),
+        };
+        summary.push(Outcome::redeemed(B256::ZERO, sent));
+        summary.push(Outcome::redeemed(
+            B256::with_last_byte(2),
+            B256::with_last_byte(3),
+        ));
+        assert_eq!(summary.total_gas(), 84_000);
+        let table = summary.table();
+        let row = table.lines().nth(1).unwrap();
+        assert!(row.contains("84000"));
+        assert!(table.contains("total gas: 84000"));
+    }
+}
@@ -0,0 +1,300 @@
+use alloy::{
+    primitives::{Address, B256, Bytes, U256},
+    providers::ProviderBuilder,
+    signers::Signer,
+    sol,
+};
+use anyhow::{Context, anyhow};
+use futures::lock::Mutex;
+use reqwest::Url;
+use serde::Deserialize;
+use std::error::Error;
+use tokio::time::{Duration, Instant};
+
+use crate::{config::Config, fetch, redeem};
+
+/// How often the Safe Transaction Service is asked whether a proposal was executed.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+sol!(
+    #[allow(missing_docs, clippy::too_many_arguments)]
+    #[sol(rpc)]
+    contract GnosisSafe {
+        function nonce() external view returns (uint256);
+        function getThreshold() external view returns (uint256);
+        function getTransactionHash(
+            address to,
+            uint256 value,
+            bytes calldata data,
+            uint8 operation,
+            uint256 safeTxGas,
+            uint256 baseGas,
+            uint256 gasPrice,
+            address gasToken,
+            address refundReceiver,
+            uint256 _nonce
+        ) external view returns (bytes32);
+        function execTransaction(
+            address to,
+            uint256 value,
+            bytes calldata data,
+            uint8 operation,
+            uint256 safeTxGas,
+            uint256 baseGas,
+            uint256 gasPrice,
+            address gasToken,
+            address refundReceiver,
+            bytes memory signatures
+        ) external payable returns (bool success);
+    }
+);
+
+/// A Safe that redeems in the executor's place; the executor key must be one of its owners.
+/// With a threshold of 1 the executor executes each redemption itself, otherwise it proposes
+/// them to the Safe Transaction Service for the other owners to confirm and execute.
+pub struct Safe {
+    pub address: Address,
+    pub service_url: Url,
+    /// Held while a Safe nonce is chosen and used, since concurrent redemptions would
+    /// otherwise take the same one.
+    pub lock: Mutex<()>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Page {
+    count: u64,
+    results: Vec<Proposal>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Proposal {
+    safe_tx_hash: B256,
+    to: Address,
+    data: Option<Bytes>,
+    #[serde(default)]
+    is_executed: bool,
+    is_successful: Option<bool>,
+    transaction_hash: Option<B256>,
+    #[serde(default)]
+    confirmations: Vec<serde_json::Value>,
+    confirmations_required: Option<u64>,
+}
+
+impl Safe {
+    /// Has the Safe call `data` on `to` and waits until that is executed, failing if it
+    /// reverted or, for a proposal, is still awaiting execution after `tx.timeout`.
+    pub async fn send(
+        &self,
+        config: &Config,
+        to: Address,
+        data: &Bytes,
+    ) -> Result<redeem::Sent, Box<dyn Error>> {
+        let guard = self.lock.lock().await;
+        let reader = ProviderBuilder::new().connect_client(config.rpc());
+        let safe = GnosisSafe::new(self.address, &reader);
+        let threshold = safe.getThreshold().call().await?;
+        let nonce = safe.nonce().call().await?;
+
+        if threshold == U256::from(1) {
+            let safe_tx_hash = self.hash(config, to, data, nonce).await?;
+            let signature = sign(config, safe_tx_hash).await?;
+            let safe = GnosisSafe::new(self.address, redeem::provider(config)?);
+            let call = safe
+                .execTransaction(
+                    to,
+                    U256::ZERO,
+                    data.clone(),
+                    0,
+                    U256::ZERO,
+                    U256::ZERO,
+                    U256::ZERO,
+                    Address::ZERO,
+                    Address::ZERO,
+                    signature,
+                )
+                .from(config.executor()?);
+            let gas = redeem::gas_limit(config, call.estimate_gas().await?)?;
+            tracing::debug!("Executing Safe transaction {nonce} with {gas} gas");
+            let pending = redeem::submit(config, call.gas(gas).into_transaction_request()).await?;
+            // Still holding the lock: the next transaction needs this one's nonce used.
+            return Ok(redeem::confirm(config, pending).await?);
+        }
+
+        let service_url = &self.service_url;
+        let queue: Page = self
+            .get(
+                config,
+                service_url.join(&format!(
+                    "api/v1/safes/{}/multisig-transactions/?executed=false&nonce__gte={nonce}&limit=100",
+                    self.address
+                ))?,
+            )
+            .await?;
+        let safe_tx_hash = match queue
+            .results
+            .iter()
+            .find(|proposal| proposal.to == to && proposal.data.as_ref() == Some(data))
+        {
+            Some(proposal) => {
+                tracing::info!(
+                    "Already proposed as Safe transaction {}",
+                    proposal.safe_tx_hash
+                );
+                proposal.safe_tx_hash
+            }
+            None => {
+                // Queued proposals are assumed to take the nonces right after the Safe's.
+                let nonce = nonce + U256::from(queue.count);
+                let safe_tx_hash = self.hash(config, to, data, nonce).await?;
+                let signature = sign(config, safe_tx_hash).await?;
+                let url = service_url.join(&format!(
+                    "api/v1/safes/{}/multisig-transactions/",
+                    self.address
+                ))?;
+                let request = config.http.post(url).json(&serde_json::json!({
+                    "to": to,
+                    "value": "0",
+                    "data": data,
+                    "operation": 0,
+                    "safeTxGas": "0",
+                    "baseGas": "0",
+                    "gasPrice": "0",
+                    "gasToken": Address::ZERO,
+                    "refundReceiver": Address::ZERO,
+                    "nonce": nonce.to_string(),
+                    "contractTransactionHash": safe_tx_hash,
+                    "sender": config.executor()?,
+                    "signature": signature,
+                    "origin": "redeem-rs",
+                }));
+                fetch::timed(config, request)
+                    .send()
+                    .await
+                    .context("Failed to propose to the Safe Transaction Service")?
+                    .error_for_status()
+                    .context("Safe Transaction Service refused the proposal")?;
+                tracing::info!("Proposed Safe transaction {safe_tx_hash} with nonce {nonce}");
+                safe_tx_hash
+            }
+        };
+        drop(guard);
+        Ok(self.wait(config, service_url, safe_tx_hash).await?.into())
+    }
+
+    async fn hash(
+        &self,
+        config: &Config,
+        to: Address,
+        data: &Bytes,
+        nonce: U256,
+    ) -> anyhow::Result<B256> {
+        let reader = ProviderBuilder::new().connect_client(config.rpc());
+        Ok(GnosisSafe::new(self.address, reader)
+            .getTransactionHash(
+                to,
+                U256::ZERO,
+                data.clone(),
+                0,
+                U256::ZERO,
+                U256::ZERO,
+                U256::ZERO,
+                Address::ZERO,
+                Address::ZERO,
+                nonce,
+            )
+            .call()
+            .await?)
+    }
+
+    async fn wait(
+        &self,
+        config: &Config,
+        service_url: &Url,
+        safe_tx_hash: B256,
+    ) -> anyhow::Result<B256> {
+        let url = service_url.join(&format!("api/v1/multisig-transactions/{safe_tx_hash}/"))?;
+        let deadline = config.tx.timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            tokio::time::sleep(POLL_INTERVAL).await;
+            let proposal: Proposal = match self.get(config, url.clone()).await {
+                Ok(proposal) => proposal,
+                Err(e) => {
+                    tracing::debug!("Failed to poll Safe transaction {safe_tx_hash}: {e:#}");
+                    continue;
+                }
+            };
+            match (proposal.is_executed, proposal.transaction_hash) {
+                (true, Some(tx_hash)) if proposal.is_successful != Some(false) => {
+                    return Ok(tx_hash);
+                }
+                (true, _) => {
+                    return Err(anyhow!("Safe transaction {safe_tx_hash} reverted"));
+                }
+                (false, _) if deadline.is_some_and(|deadline| Instant::now() >= deadline) => {
+                    return Err(anyhow!(
+                        "Safe transaction {safe_tx_hash} still awaiting execution ({} of {} confirmations)",
+                        proposal.confirmations.len(),
+                        proposal.confirmations_required.unwrap_or_default()
+                    ));
+                }
+                (false, _) => {}
+            }
+        }
+    }
+
+    async fn get<T: serde::de::DeserializeOwned>(
+        &self,
+        config: &Config,
+        url: Url,
+    ) -> anyhow::Result<T> {
+        let response = fetch::timed(config, config.http.get(url))
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(fetch::read_json(response, config.http_limits.max_response_size).await?)
+    }
+}
+
+/// The executor's owner signature of a Safe transaction hash.
+async fn sign(config: &Config, safe_tx_hash: B256) -> anyhow::Result<Bytes> {
+    let signature = config.signer()?.sign_hash(&safe_tx_hash).await?;
+    Ok(signature.as_bytes().into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deserialize_queue() {
+        let page: Page = serde_json::from_str(
+            r#"{
+                "count": 1,
+                "next": null,
+                "previous": null,
+                "results": [{
+                    "safe": "0x0000000000000000000000000000000000000001",
+                    "to": "0x0000000000000000000000000000000000000002",
+                    "value": "0",
+                    "data": "0x1234",
+                    "operation": 0,
+                    "nonce": 7,
+                    "safeTxHash": "0x50ede65601819b8885dc3dbf4676204fcd318c26b8281d82af20f69d55b4ca75",
+                    "isExecuted": false,
+                    "isSuccessful": null,
+                    "transactionHash": null,
+                    "confirmationsRequired": 2,
+                    "confirmations": [{"owner": "0x0000000000000000000000000000000000000003"}]
+                }]
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(page.count, 1);
+        let proposal = &page.results[0];
+        assert_eq!(proposal.to, Address::with_last_byte(2));
+        assert_eq!(proposal.data, Some(Bytes::from_static(&[0x12, 0x34])));
+        assert_eq!(proposal.confirmations.len(), 1);
+        assert!(!proposal.is_executed);
+    }
+}
@@ -0,0 +1,398 @@
+use alloy::primitives::{Address, U256, aliases::U192};
+use anyhow::Result;
+use circles_pathfinder::{FindPathParams, TransferStep};
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use crate::circles::CirclesClient;
+
+/// How many transfers away from the sender the graph is explored.
+const MAX_HOPS: usize = 3;
+/// Most avatars whose balances are loaded for one search, to bound the RPC requests it makes.
+const MAX_AVATARS: usize = 200;
+
+/// Who holds how much of which Circles token, and who accepts which tokens: the part of the
+/// trust graph around one sender that a path can run through.
+#[derive(Debug, Default)]
+pub struct Graph {
+    /// Tokens (by owner) and amounts each avatar holds.
+    balances: HashMap<Address, Vec<(Address, U256)>>,
+    /// Avatars that accept each token (by owner), besides the owner itself.
+    acceptors: HashMap<Address, HashSet<Address>>,
+    /// Groups, which mint their token for any member token they receive.
+    groups: HashSet<Address>,
+}
+
+impl Graph {
+    pub fn add_balance(&mut self, holder: Address, owner: Address, amount: U256) {
+        let balances = self.balances.entry(holder).or_default();
+        match balances.iter_mut().find(|(token, _)| *token == owner) {
+            Some((_, balance)) => *balance = balance.saturating_add(amount),
+            None => balances.push((owner, amount)),
+        }
+    }
+
+    pub fn add_acceptor(&mut self, owner: Address, avatar: Address) {
+        self.acceptors.entry(owner).or_default().insert(avatar);
+    }
+
+    pub fn add_group(&mut self, group: Address) {
+        self.groups.insert(group);
+    }
+
+    /// Loads the balances and trust relations reachable from `params.from` within
+    /// [`MAX_HOPS`] transfers from the Circles RPC. Wrapped balances only count with
+    /// `use_wrapped_balances`. Groups among `params.to_tokens` can mint their token on the way.
+    pub async fn load(circles: &CirclesClient, params: &FindPathParams) -> Result<Graph> {
+        let wrapped = params.use_wrapped_balances.unwrap_or(false);
+        let mut graph = Graph::default();
+        let mut visited = HashSet::from([params.from]);
+        let mut loaded_tokens = HashSet::new();
+        for &token in params.to_tokens.iter().flatten() {
+            if circles.is_group(token).await? {
+                graph.add_group(token);
+                let relations = circles.trust_relations(token).await?;
+                for avatar in relations.trusted_by {
+                    graph.add_acceptor(token, avatar);
+                }
+                loaded_tokens.insert(token);
+            }
+        }
+        let mut frontier = vec![params.from];
+        for _ in 0..MAX_HOPS {
+            let mut next = Vec::new();
+            for holder in frontier {
+                // The receiver's balances are not spent on the way to it.
+                if holder == params.to {
+                    continue;
+                }
+                for balance in circles.token_balances(holder).await? {
+                    if balance.atto_circles.is_zero() || (balance.is_wrapped && !wrapped) {
+                        continue;
+                    }
+                    graph.add_balance(holder, balance.token_owner, balance.atto_circles);
+                    if balance.is_group {
+                        graph.add_group(balance.token_owner);
+                    }
+                    if !loaded_tokens.insert(balance.token_owner) {
+                        continue;
+                    }
+                    let relations = circles.trust_relations(balance.token_owner).await?;
+                    for avatar in relations.trusted_by {
+                        graph.add_acceptor(balance.token_owner, avatar);
+                    }
+                    let acceptors = graph.acceptors(balance.token_owner);
+                    for avatar in acceptors {
+                        if visited.len() < MAX_AVATARS && visited.insert(avatar) {
+                            next.push(avatar);
+                        }
+                    }
+                }
+            }
+            frontier = next;
+        }
+        tracing::debug!(
+            "Loaded {} avatars and {} tokens around {}",
+            visited.len(),
+            loaded_tokens.len(),
+            params.from
+        );
+        Ok(graph)
+    }
+
+    /// Every avatar that accepts the token of `owner`, including `owner`.
+    fn acceptors(&self, owner: Address) -> Vec<Address> {
+        let mut acceptors: Vec<_> = self
+            .acceptors
+            .get(&owner)
+            .into_iter()
+            .flatten()
+            .copied()
+            .collect();
+        if !acceptors.contains(&owner) {
+            acceptors.push(owner);
+        }
+        acceptors
+    }
+
+    /// The transfers of the largest flow from `params.from` to `params.to`, up to
+    /// `params.target_flow`, honouring the token filters. Every intermediate avatar passes on
+    /// as much as it receives, paying in tokens it held before; a group pays in the token it
+    /// mints for what it receives.
+    pub fn max_flow(&self, params: &FindPathParams) -> Vec<TransferStep> {
+        let mut network = Network::default();
+        let source = network.node(Node::Avatar(params.from));
+        let sink = network.node(Node::Avatar(params.to));
+        for (&holder, balances) in &self.balances {
+            if holder == params.to {
+                continue;
+            }
+            for &(owner, amount) in balances {
+                if holder == params.from
+                    && !allowed(&params.from_tokens, &params.exclude_from_tokens, owner)
+                {
+                    continue;
+                }
+                self.spend(&mut network, params, holder, owner, amount);
+            }
+        }
+        // The hub passes the member tokens a group receives on to the group's treasury as
+        // collateral, so in the flow matrix the group vertex stands in for its treasury.
+        for &group in &self.groups {
+            if group != params.to {
+                self.spend(&mut network, params, group, group, U256::MAX);
+            }
+        }
+        network.solve(source, sink, params.target_flow);
+        network.transfers()
+    }
+
+    /// Lets `holder` pay up to `amount` of the token of `owner` to everyone accepting it.
+    fn spend(
+        &self,
+        network: &mut Network,
+        params: &FindPathParams,
+        holder: Address,
+        owner: Address,
+        amount: U256,
+    ) {
+        let avatar = network.node(Node::Avatar(holder));
+        let balance = network.node(Node::Balance(holder, owner));
+        network.edge(avatar, balance, amount);
+        for acceptor in self.acceptors(owner) {
+            if acceptor == holder
+                || (acceptor == params.to
+                    && !allowed(&params.to_tokens, &params.exclude_to_tokens, owner))
+            {
+                continue;
+            }
+            let acceptor = network.node(Node::Avatar(acceptor));
+            network.edge(balance, acceptor, U256::MAX);
+        }
+    }
+}
+
+/// Whether a token filter lets the token of `owner` through: it must be among `tokens`, if
+/// given, and not among `excluded`.
+fn allowed(tokens: &Option<Vec<Address>>, excluded: &Option<Vec<Address>>, owner: Address) -> bool {
+    tokens.as_ref().is_none_or(|tokens| tokens.contains(&owner))
+        && excluded
+            .as_ref()
+            .is_none_or(|tokens| !tokens.contains(&owner))
+}
+
+/// A vertex of the flow network: an avatar, or the balance one avatar holds of a token.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Node {
+    Avatar(Address),
+    Balance(Address, Address),
+}
+
+#[derive(Debug)]
+struct Edge {
+    to: usize,
+    /// Zero for the reverse edges that let flow be taken back.
+    capacity: U256,
+    /// Capacity left; for a forward edge, its flow is `capacity - residual`.
+    residual: U256,
+    /// Index of the reverse edge in `to`'s list.
+    reverse: usize,
+}
+
+/// A flow network with residual edges, solved with Edmonds-Karp.
+#[derive(Debug, Default)]
+struct Network {
+    nodes: Vec<Node>,
+    index: HashMap<Node, usize>,
+    edges: Vec<Vec<Edge>>,
+}
+
+impl Network {
+    fn node(&mut self, node: Node) -> usize {
+        if let Some(&index) = self.index.get(&node) {
+            return index;
+        }
+        self.nodes.push(node);
+        self.edges.push(Vec::new());
+        self.index.insert(node, self.nodes.len() - 1);
+        self.nodes.len() - 1
+    }
+
+    fn edge(&mut self, from: usize, to: usize, capacity: U256) {
+        let (forward, reverse) = (self.edges[from].len(), self.edges[to].len());
+        self.edges[from].push(Edge {
+            to,
+            capacity,
+            residual: capacity,
+            reverse,
+        });
+        self.edges[to].push(Edge {
+            to: from,
+            capacity: U256::ZERO,
+            residual: U256::ZERO,
+            reverse: forward,
+        });
+    }
+
+    /// Pushes flow from `source` to `sink` along shortest augmenting paths until `target` is
+    /// reached or no augmenting path is left.
+    fn solve(&mut self, source: usize, sink: usize, target: U256) {
+        let mut total = U256::ZERO;
+        while total < target {
+            let Some(path) = self.augmenting_path(source, sink) else {
+                break;
+            };
+            let bottleneck = path
+                .iter()
+                .map(|&(node, edge)| self.edges[node][edge].residual)
+                .fold(target - total, U256::min);
+            for (node, edge) in path {
+                let (to, reverse) = (self.edges[node][edge].to, self.edges[node][edge].reverse);
+                self.edges[node][edge].residual -= bottleneck;
+                self.edges[to][reverse].residual += bottleneck;
+            }
+            total += bottleneck;
+        }
+    }
+
+    /// The edges, as (node, edge index) pairs, of a shortest path with residual capacity.
+    fn augmenting_path(&self, source: usize, sink: usize) -> Option<Vec<(usize, usize)>> {
+        let mut previous: Vec<Option<(usize, usize)>> = vec![None; self.nodes.len()];
+        let mut queue = VecDeque::from([source]);
+        while let Some(node) = queue.pop_front() {
+            if node == sink {
+                let mut path = Vec::new();
+                let mut current = sink;
+                while let Some((node, edge)) = previous[current] {
+                    path.push((node, edge));
+                    current = node;
+                }
+                path.reverse();
+                return Some(path);
+            }
+            for (index, edge) in self.edges[node].iter().enumerate() {
+                if edge.to != source && previous[edge.to].is_none() && !edge.residual.is_zero() {
+                    previous[edge.to] = Some((node, index));
+                    queue.push_back(edge.to);
+                }
+            }
+        }
+        None
+    }
+
+    /// The flow from each balance to the avatar receiving it, as transfers.
+    fn transfers(&self) -> Vec<TransferStep> {
+        let mut transfers = Vec::new();
+        for (node, edges) in self.nodes.iter().zip(&self.edges) {
+            let Node::Balance(holder, owner) = *node else {
+                continue;
+            };
+            for edge in edges {
+                let Node::Avatar(receiver) = self.nodes[edge.to] else {
+                    continue;
+                };
+                let flow = edge.capacity - edge.residual;
+                if edge.capacity.is_zero() || flow.is_zero() {
+                    continue;
+                }
+                transfers.push(TransferStep {
+                    from_address: holder,
+                    to_address: receiver,
+                    token_owner: owner,
+                    value: U192::saturating_from(flow),
+                });
+            }
+        }
+        transfers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(target_flow: u64) -> FindPathParams {
+        FindPathParams {
+            from: Address::with_last_byte(1),
+            to: Address::with_last_byte(2),
+            target_flow: U256::from(target_flow),
+            use_wrapped_balances: None,
+            from_tokens: None,
+            to_tokens: None,
+            exclude_from_tokens: None,
+            exclude_to_tokens: None,
+            simulated_balances: None,
+            simulated_trusts: None,
+            max_transfers: None,
+        }
+    }
+
+    fn total(transfers: &[TransferStep], to: Address) -> U192 {
+        transfers
+            .iter()
+            .filter(|transfer| transfer.to_address == to)
+            .map(|transfer| transfer.value)
+            .fold(U192::ZERO, |total, value| total + value)
+    }
+
+    #[test]
+    fn test_direct_flow() {
+        let (from, to) = (Address::with_last_byte(1), Address::with_last_byte(2));
+        let mut graph = Graph::default();
+        graph.add_balance(from, from, U256::from(100));
+        graph.add_acceptor(from, to);
+        let transfers = graph.max_flow(&params(60));
+        assert_eq!(transfers.len(), 1);
+        assert_eq!(total(&transfers, to), U192::from(60));
+        assert_eq!(total(&graph.max_flow(&params(500)), to), U192::from(100));
+    }
+
+    #[test]
+    fn test_flow_through_intermediary() {
+        let (from, to, middle) = (
+            Address::with_last_byte(1),
+            Address::with_last_byte(2),
+            Address::with_last_byte(3),
+        );
+        let mut graph = Graph::default();
+        // The receiver only accepts the intermediary's token, which accepts the sender's.
+        graph.add_balance(from, from, U256::from(100));
+        graph.add_acceptor(from, middle);
+        graph.add_balance(middle, middle, U256::from(40));
+        graph.add_acceptor(middle, to);
+        let transfers = graph.max_flow(&params(100));
+        assert_eq!(total(&transfers, to), U192::from(40));
+        assert_eq!(total(&transfers, middle), U192::from(40));
+
+        let mut excluded = params(100);
+        excluded.exclude_to_tokens = Some(vec![middle]);
+        assert!(graph.max_flow(&excluded).is_empty());
+    }
+
+    #[test]
+    fn test_group_mint() {
+        let (from, to, group) = (
+            Address::with_last_byte(1),
+            Address::with_last_byte(2),
+            Address::with_last_byte(4),
+        );
+        let mut graph = Graph::default();
+        // The group accepts the sender's token and the receiver only the group's.
+        graph.add_balance(from, from, U256::from(100));
+        graph.add_acceptor(from, group);
+        graph.add_acceptor(group, to);
+        let mut params = params(70);
+        params.to_tokens = Some(vec![group]);
+        assert!(graph.max_flow(&params).is_empty());
+
+        graph.add_group(group);
+        let transfers = graph.max_flow(&params);
+        assert_eq!(total(&transfers, group), U192::from(70));
+        assert_eq!(total(&transfers, to), U192::from(70));
+        assert!(
+            transfers
+                .iter()
+                .filter(|transfer| transfer.to_address == to)
+                .all(|transfer| transfer.from_address == group && transfer.token_owner == group)
+        );
+    }
+}
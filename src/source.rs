@@ -0,0 +1,601 @@
+use alloy::{
+    eips::BlockNumberOrTag,
+    primitives::{Address, B256, U256},
+    providers::{Provider, ProviderBuilder},
+    rpc::types::{Filter, Log},
+    sol_types::{SolEvent, SolEventInterface},
+};
+use anyhow::{Context, Result, anyhow};
+use reqwest::Url;
+use std::{
+    collections::BTreeMap,
+    path::PathBuf,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    cli::FilterArgs,
+    config::Config,
+    fetch,
+    redeem::{Category, RedeemableSubscription, SubscriptionModule},
+};
+
+/// Where redeemable subscriptions come from.
+pub trait SubscriptionSource {
+    /// The currently redeemable subscriptions. Sources may use `filter` and `since` to narrow
+    /// the result down, but need not: callers apply the filter again.
+    async fn fetch(
+        &self,
+        config: &Config,
+        filter: &FilterArgs,
+        since: Option<u64>,
+    ) -> Result<Vec<RedeemableSubscription>>;
+
+    /// The redeemable subscription with the given ID, if the source currently lists it.
+    async fn fetch_one(&self, config: &Config, id: B256) -> Result<Option<RedeemableSubscription>> {
+        Ok(self
+            .fetch(config, &FilterArgs::default(), None)
+            .await?
+            .into_iter()
+            .find(|subscription| subscription.id == id))
+    }
+}
+
+/// The SubIndexer HTTP API at `api_url`.
+pub struct Indexer;
+
+impl SubscriptionSource for Indexer {
+    async fn fetch(
+        &self,
+        config: &Config,
+        filter: &FilterArgs,
+        since: Option<u64>,
+    ) -> Result<Vec<RedeemableSubscription>> {
+        fetch::fetch_redeemable_since(config, filter, since).await
+    }
+}
+
+/// A hand-crafted or saved list of subscriptions: a JSON array in the indexer's format, or a
+/// `.csv` file with a header row naming the same fields.
+pub struct LocalFile(pub PathBuf);
+
+impl SubscriptionSource for LocalFile {
+    async fn fetch(
+        &self,
+        _config: &Config,
+        _filter: &FilterArgs,
+        _since: Option<u64>,
+    ) -> Result<Vec<RedeemableSubscription>> {
+        let contents = std::fs::read_to_string(&self.0)
+            .with_context(|| format!("Failed to read {}", self.0.display()))?;
+        let csv = self
+            .0
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("csv"));
+        if csv {
+            parse_csv(&contents)
+        } else {
+            serde_json::from_str(&contents).map_err(Into::into)
+        }
+        .with_context(|| format!("Failed to parse {}", self.0.display()))
+    }
+}
+
+/// Parses CSV rows into subscriptions by turning each into a JSON object keyed by the header.
+fn parse_csv(contents: &str) -> Result<Vec<RedeemableSubscription>> {
+    let mut lines = contents.lines().filter(|line| !line.trim().is_empty());
+    let header: Vec<&str> = lines
+        .next()
+        .ok_or_else(|| anyhow!("missing header row"))?
+        .split(',')
+        .map(str::trim)
+        .collect();
+    lines
+        .enumerate()
+        .map(|(row, line)| {
+            let values: Vec<&str> = line.split(',').map(str::trim).collect();
+            if values.len() != header.len() {
+                return Err(anyhow!(
+                    "row {} has {} columns, expected {}",
+                    row + 1,
+                    values.len(),
+                    header.len()
+                ));
+            }
+            let object: serde_json::Map<_, _> = header
+                .iter()
+                .zip(values)
+                .map(|(name, value)| {
+                    let value = match (*name, value.parse::<i64>()) {
+                        ("periods", Ok(periods)) => periods.into(),
+                        _ => value.into(),
+                    };
+                    (name.to_string(), value)
+                })
+                .collect();
+            serde_json::from_value(object.into()).with_context(|| format!("row {}", row + 1))
+        })
+        .collect()
+}
+
+/// A subgraph queried with `subgraph_query`. The subscriptions are read from the query's
+/// first top-level field; use GraphQL aliases to map the subgraph's field names onto the
+/// indexer's (e.g. `contract_address: module`).
+pub struct Subgraph(pub Url);
+
+impl SubscriptionSource for Subgraph {
+    async fn fetch(
+        &self,
+        config: &Config,
+        _filter: &FilterArgs,
+        _since: Option<u64>,
+    ) -> Result<Vec<RedeemableSubscription>> {
+        config.api_rate_limit.acquire().await;
+        tracing::debug!("Querying subgraph {}", self.0);
+        let response = fetch::request(config, config.http.post(self.0.clone()))
+            .json(&serde_json::json!({ "query": config.subgraph_query }))
+            .send()
+            .await
+            .context("Failed to send GraphQL request")?
+            .error_for_status()?;
+        let response = fetch::read_json(response, config.http_limits.max_response_size)
+            .await
+            .context("Failed to deserialize GraphQL response")?;
+        subgraph_subscriptions(response)
+    }
+}
+
+fn subgraph_subscriptions(mut response: serde_json::Value) -> Result<Vec<RedeemableSubscription>> {
+    if let Some(errors) = response.get("errors") {
+        return Err(anyhow!("GraphQL errors: {errors}"));
+    }
+    let list = response
+        .get_mut("data")
+        .and_then(|data| data.as_object_mut())
+        .and_then(|data| data.values_mut().next())
+        .map(serde_json::Value::take)
+        .ok_or_else(|| anyhow!("GraphQL response has no data"))?;
+    let entries =
+        serde_json::from_value(list).context("Failed to deserialize subgraph subscriptions")?;
+    Ok(fetch::parse_entries(entries))
+}
+
+/// The modules' event logs, scanned from `logs_from_block` on through `rpc_url`. Each fetch
+/// only scans the blocks added since the previous one.
+pub struct Logs {
+    from_block: u64,
+    scanned: Mutex<Scanned>,
+}
+
+/// What the logs scanned so far say about each subscription, by module and ID.
+#[derive(Debug, Clone, Default)]
+struct Scanned {
+    /// The first block not scanned yet.
+    next_block: Option<u64>,
+    subscriptions: BTreeMap<(Address, B256), Tracked>,
+}
+
+/// A live subscription and when it can next be redeemed.
+#[derive(Debug, Clone)]
+struct Tracked {
+    subscription: RedeemableSubscription,
+    /// Seconds between two periods.
+    frequency: u64,
+    /// Unix timestamp from which the next period can be redeemed.
+    next_redeem_at: u64,
+}
+
+impl Logs {
+    pub fn new(from_block: u64) -> Self {
+        Self {
+            from_block,
+            scanned: Mutex::default(),
+        }
+    }
+}
+
+impl SubscriptionSource for Logs {
+    async fn fetch(
+        &self,
+        config: &Config,
+        _filter: &FilterArgs,
+        _since: Option<u64>,
+    ) -> Result<Vec<RedeemableSubscription>> {
+        if config.modules.is_empty() {
+            return Err(anyhow!("Scanning logs needs a module_address"));
+        }
+        let provider = ProviderBuilder::new().connect_client(config.rpc());
+        let latest = provider
+            .get_block_by_number(BlockNumberOrTag::Latest)
+            .await
+            .context("Failed to read the latest block")?
+            .ok_or_else(|| anyhow!("RPC returned no latest block"))?;
+        let mut scanned = self
+            .scanned
+            .lock()
+            .map_err(|_| anyhow!("Log scan is poisoned"))?
+            .clone();
+        let mut from = scanned.next_block.unwrap_or(self.from_block);
+        while from <= latest.header.number {
+            let to = latest
+                .header
+                .number
+                .min(from.saturating_add(config.logs_block_range - 1));
+            let filter = Filter::new()
+                .address(config.modules.clone())
+                .from_block(from)
+                .to_block(to)
+                .event_signature(vec![
+                    SubscriptionModule::SubscriptionCreated::SIGNATURE_HASH,
+                    SubscriptionModule::Redeemed::SIGNATURE_HASH,
+                    SubscriptionModule::Unsubscribed::SIGNATURE_HASH,
+                ]);
+            let logs = provider
+                .get_logs(&filter)
+                .await
+                .with_context(|| format!("Failed to read logs of blocks {from} to {to}"))?;
+            tracing::debug!(
+                "Scanned blocks {from} to {to}: {} module events",
+                logs.len()
+            );
+            logs.iter().for_each(|log| scanned.apply(log));
+            from = to + 1;
+        }
+        scanned.next_block = Some(from);
+        let subscriptions = scanned.redeemable(latest.header.timestamp);
+        if let Ok(mut stored) = self.scanned.lock() {
+            *stored = scanned;
+        }
+        Ok(subscriptions)
+    }
+}
+
+impl Scanned {
+    /// Updates the subscriptions with the module event in `log`; other logs are skipped.
+    fn apply(&mut self, log: &Log) {
+        use SubscriptionModule::SubscriptionModuleEvents as Event;
+        let module = log.address();
+        let event = match Event::decode_log(&log.inner) {
+            Ok(event) => event.data,
+            Err(e) => {
+                tracing::warn!("Skipping undecodable log of {module}: {e}");
+                return;
+            }
+        };
+        match event {
+            Event::SubscriptionCreated(created) => {
+                let Some(category) = category(created.category) else {
+                    tracing::warn!(
+                        "Skipping subscription {} of unknown category {}",
+                        created.id,
+                        created.category
+                    );
+                    return;
+                };
+                let subscription = RedeemableSubscription {
+                    contract_address: module,
+                    id: created.id,
+                    recipient: created.recipient,
+                    subscriber: created.subscriber,
+                    amount: created.amount,
+                    periods: 0,
+                    category,
+                };
+                self.subscriptions.insert(
+                    (module, created.id),
+                    Tracked {
+                        subscription,
+                        frequency: created.frequency.saturating_to(),
+                        next_redeem_at: created.nextRedeemAt.saturating_to(),
+                    },
+                );
+            }
+            Event::Redeemed(redeemed) => {
+                if let Some(tracked) = self.subscriptions.get_mut(&(module, redeemed.id)) {
+                    tracked.next_redeem_at = redeemed.nextRedeemAt.saturating_to();
+                }
+            }
+            Event::Unsubscribed(unsubscribed) => {
+                self.subscriptions.remove(&(module, unsubscribed.id));
+            }
+        }
+    }
+
+    /// The subscriptions with at least one period due at Unix timestamp `now`.
+    fn redeemable(&self, now: u64) -> Vec<RedeemableSubscription> {
+        self.subscriptions
+            .values()
+            .filter_map(|tracked| {
+                let periods = tracked.periods_due(now);
+                (periods > 0).then(|| RedeemableSubscription {
+                    periods,
+                    ..tracked.subscription.clone()
+                })
+            })
+            .collect()
+    }
+}
+
+impl Tracked {
+    /// Periods that can be redeemed at Unix timestamp `now`.
+    fn periods_due(&self, now: u64) -> i32 {
+        let Some(elapsed) = now.checked_sub(self.next_redeem_at) else {
+            return 0;
+        };
+        let periods = elapsed.checked_div(self.frequency).unwrap_or(0) + 1;
+        i32::try_from(periods).unwrap_or(i32::MAX)
+    }
+}
+
+/// The category encoded in a `SubscriptionCreated` event, in the order the module declares
+/// them.
+fn category(value: u8) -> Option<Category> {
+    match value {
+        0 => Some(Category::Trusted),
+        1 => Some(Category::Untrusted),
+        2 => Some(Category::Group),
+        _ => None,
+    }
+}
+
+/// The source selected by the configuration.
+pub enum Source {
+    Indexer(Indexer),
+    LocalFile(LocalFile),
+    Subgraph(Subgraph),
+    Logs(Logs),
+}
+
+impl Source {
+    /// A source file takes precedence over a subgraph, which takes precedence over scanning
+    /// logs, which takes precedence over the indexer.
+    pub fn new(config: &Config) -> Self {
+        match (
+            &config.source_file,
+            &config.subgraph_url,
+            config.logs_from_block,
+        ) {
+            (Some(path), _, _) => Source::LocalFile(LocalFile(path.clone())),
+            (None, Some(url), _) => Source::Subgraph(Subgraph(url.clone())),
+            (None, None, Some(from_block)) => Source::Logs(Logs::new(from_block)),
+            (None, None, None) => Source::Indexer(Indexer),
+        }
+    }
+}
+
+impl SubscriptionSource for Source {
+    async fn fetch(
+        &self,
+        config: &Config,
+        filter: &FilterArgs,
+        since: Option<u64>,
+    ) -> Result<Vec<RedeemableSubscription>> {
+        match self {
+            Source::Indexer(source) => source.fetch(config, filter, since).await,
+            Source::LocalFile(source) => source.fetch(config, filter, since).await,
+            Source::Subgraph(source) => source.fetch(config, filter, since).await,
+            Source::Logs(source) => source.fetch(config, filter, since).await,
+        }
+    }
+}
+
+/// Remembers the last result of `inner` for `ttl`, so that stages and passes within that
+/// window share a single fetch. A zero `ttl` disables caching.
+pub struct Cached<S> {
+    inner: S,
+    ttl: Duration,
+    entry: Mutex<Option<CacheEntry>>,
+}
+
+struct CacheEntry {
+    fetched: Instant,
+    key: (Vec<(&'static str, String)>, Option<u64>),
+    subscriptions: Vec<RedeemableSubscription>,
+}
+
+impl<S> Cached<S> {
+    pub fn new(inner: S, ttl: Duration) -> Self {
+        Self {
+            inner,
+            ttl,
+            entry: Mutex::new(None),
+        }
+    }
+}
+
+impl<S: SubscriptionSource> SubscriptionSource for Cached<S> {
+    async fn fetch(
+        &self,
+        config: &Config,
+        filter: &FilterArgs,
+        since: Option<u64>,
+    ) -> Result<Vec<RedeemableSubscription>> {
+        let key = (filter.query(), since);
+        if let Ok(entry) = self.entry.lock()
+            && let Some(entry) = entry.as_ref()
+            && entry.key == key
+            && entry.fetched.elapsed() < self.ttl
+        {
+            tracing::debug!("Using {} cached subscriptions", entry.subscriptions.len());
+            return Ok(entry.subscriptions.clone());
+        }
+        let subscriptions = self.inner.fetch(config, filter, since).await?;
+        if !self.ttl.is_zero()
+            && let Ok(mut entry) = self.entry.lock()
+        {
+            *entry = Some(CacheEntry {
+                fetched: Instant::now(),
+                key,
+                subscriptions: subscriptions.clone(),
+            });
+        }
+        Ok(subscriptions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Layer;
+    use alloy::primitives::LogData;
+
+    const SUBSCRIPTIONS: &str = r#"[{
+        "contract_address": "0xcebe4b6d50ce877a9689ce4516fe96911e099a78",
+        "id": "0x50ede65601819b8885dc3dbf4676204fcd318c26b8281d82af20f69d55b4ca75",
+        "subscriber": "0xcf6dc192dc292d5f2789da2db02d6dd4f41f4214",
+        "recipient": "0x6b69683c8897e3d18e74b1ba117b49f80423da5d",
+        "amount": "10000000000000000",
+        "periods": 5,
+        "category": "trusted"
+    }]"#;
+
+    struct Mock(Vec<RedeemableSubscription>);
+
+    impl SubscriptionSource for Mock {
+        async fn fetch(
+            &self,
+            _config: &Config,
+            _filter: &FilterArgs,
+            _since: Option<u64>,
+        ) -> Result<Vec<RedeemableSubscription>> {
+            Ok(self.0.clone())
+        }
+    }
+
+    /// Counts fetches.
+    #[derive(Default)]
+    struct Counter(Mutex<usize>);
+
+    impl SubscriptionSource for Counter {
+        async fn fetch(
+            &self,
+            _config: &Config,
+            _filter: &FilterArgs,
+            _since: Option<u64>,
+        ) -> Result<Vec<RedeemableSubscription>> {
+            *self.0.lock().unwrap() += 1;
+            Ok(Vec::new())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cached() {
+        let config = Config::from_layer(Layer::defaults(None)).unwrap();
+        let filter = FilterArgs::default();
+        let cached = Cached::new(Counter::default(), Duration::from_secs(60));
+        cached.fetch(&config, &filter, None).await.unwrap();
+        cached.fetch(&config, &filter, None).await.unwrap();
+        assert_eq!(*cached.inner.0.lock().unwrap(), 1);
+        cached.fetch(&config, &filter, Some(1)).await.unwrap();
+        assert_eq!(*cached.inner.0.lock().unwrap(), 2);
+
+        let uncached = Cached::new(Counter::default(), Duration::ZERO);
+        uncached.fetch(&config, &filter, None).await.unwrap();
+        uncached.fetch(&config, &filter, None).await.unwrap();
+        assert_eq!(*uncached.inner.0.lock().unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_one() {
+        let config = Config::from_layer(Layer::defaults(None)).unwrap();
+        let source = Mock(vec![RedeemableSubscription {
+            contract_address: Address::ZERO,
+            id: B256::repeat_byte(1),
+            recipient: Address::ZERO,
+            subscriber: Address::ZERO,
+            amount: U256::from(1),
+            periods: 1,
+            category: Category::Untrusted,
+        }]);
+        let found = source.fetch_one(&config, B256::repeat_byte(1)).await;
+        assert!(found.unwrap().is_some());
+        let missing = source.fetch_one(&config, B256::ZERO).await;
+        assert!(missing.unwrap().is_none());
+    }
+
+    #[test]
+    fn test_subgraph_response() {
+        let response = serde_json::from_str(&format!(
+            r#"{{"data": {{"redeemableSubscriptions": {SUBSCRIPTIONS}}}}}"#
+        ))
+        .unwrap();
+        assert_eq!(subgraph_subscriptions(response).unwrap().len(), 1);
+        let errors = serde_json::json!({"errors": [{"message": "boom"}]});
+        assert!(subgraph_subscriptions(errors).is_err());
+    }
+
+    #[test]
+    fn test_scanned_logs() {
+        let module = Address::with_last_byte(9);
+        let log = |data: LogData| Log {
+            inner: alloy::primitives::Log {
+                address: module,
+                data,
+            },
+            ..Default::default()
+        };
+        let created = |id: u8| {
+            log(SubscriptionModule::SubscriptionCreated {
+                id: B256::repeat_byte(id),
+                subscriber: Address::with_last_byte(1),
+                recipient: Address::with_last_byte(2),
+                amount: U256::from(100),
+                frequency: U256::from(60),
+                category: 1,
+                nextRedeemAt: U256::from(1000),
+            }
+            .encode_log_data())
+        };
+        let mut scanned = Scanned::default();
+        scanned.apply(&created(1));
+        scanned.apply(&created(2));
+        scanned.apply(&created(3));
+        scanned.apply(&log(SubscriptionModule::Redeemed {
+            id: B256::repeat_byte(2),
+            subscriber: Address::with_last_byte(1),
+            recipient: Address::with_last_byte(2),
+            nextRedeemAt: U256::from(1200),
+        }
+        .encode_log_data()));
+        scanned.apply(&log(SubscriptionModule::Unsubscribed {
+            id: B256::repeat_byte(3),
+            subscriber: Address::with_last_byte(1),
+        }
+        .encode_log_data()));
+
+        assert!(scanned.redeemable(999).is_empty());
+        let due = scanned.redeemable(1130);
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].id, B256::repeat_byte(1));
+        assert_eq!(due[0].contract_address, module);
+        assert_eq!(due[0].periods, 3);
+        assert_eq!(due[0].category, Category::Untrusted);
+        assert_eq!(scanned.redeemable(1200).len(), 2);
+    }
+
+    #[test]
+    fn test_parse_csv() {
+        let csv = "contract_address,id,recipient,subscriber,amount,periods,category
+            0xcebe4b6d50ce877a9689ce4516fe96911e099a78, 0x50ede65601819b8885dc3dbf4676204fcd318c26b8281d82af20f69d55b4ca75, 0x6b69683c8897e3d18e74b1ba117b49f80423da5d, 0xcf6dc192dc292d5f2789da2db02d6dd4f41f4214, 10000000000000000, 5, trusted
+        ";
+        let subscriptions = parse_csv(csv).unwrap();
+        assert_eq!(subscriptions.len(), 1);
+        assert_eq!(subscriptions[0].periods, 5);
+        assert!(parse_csv("id,periods\n0x01").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_json_file() {
+        let path =
+            std::env::temp_dir().join(format!("redeem-rs-source-{}.json", std::process::id()));
+        std::fs::write(&path, SUBSCRIPTIONS).unwrap();
+        let mut config = Config::from_layer(Layer::defaults(None)).unwrap();
+        config.source_file = Some(path.clone());
+        let subscriptions = Source::new(&config)
+            .fetch(&config, &FilterArgs::default(), None)
+            .await;
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(subscriptions.unwrap()[0].periods, 5);
+    }
+}
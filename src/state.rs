@@ -0,0 +1,489 @@
+use alloy::primitives::B256;
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use rusqlite::{Connection, OptionalExtension, Transaction, params};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashSet},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{redeem::RedeemableSubscription, report::Outcome};
+
+/// How the state file is stored.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum StateBackend {
+    /// A JSON document, rewritten whenever the state changes.
+    #[default]
+    Json,
+    /// A SQLite database, which also keeps a ledger of every redemption attempt.
+    Sqlite,
+}
+
+/// What a run leaves behind for the next one, so that it only has to fetch the delta.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct State {
+    /// Unix timestamp (seconds) at which the previous fetch started.
+    pub since: Option<u64>,
+    /// Subscriptions fetched earlier that have not been redeemed yet.
+    #[serde(default)]
+    pub pending: Vec<RedeemableSubscription>,
+    /// Subscriptions that were redeemed or rejected on-chain, with the Unix timestamp of the
+    /// run that settled them. They are not processed again within the dedupe window, so
+    /// restarts and indexer lag cannot cause double redemptions.
+    #[serde(default)]
+    pub processed: BTreeMap<B256, u64>,
+}
+
+/// Tables of a SQLite state file. `attempts` is the ledger: one row per attempt, never
+/// rewritten, with the fetched subscription as JSON in `inputs`.
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS meta (key TEXT PRIMARY KEY, value INTEGER NOT NULL);
+    CREATE TABLE IF NOT EXISTS pending (id TEXT PRIMARY KEY, subscription TEXT NOT NULL);
+    CREATE TABLE IF NOT EXISTS processed (id TEXT PRIMARY KEY, at INTEGER NOT NULL);
+    CREATE TABLE IF NOT EXISTS attempts (
+        at INTEGER NOT NULL,
+        id TEXT NOT NULL,
+        status TEXT NOT NULL,
+        tx_hash TEXT,
+        gas_used INTEGER,
+        value TEXT,
+        error TEXT,
+        error_kind TEXT,
+        inputs TEXT
+    );
+    CREATE INDEX IF NOT EXISTS attempts_id ON attempts (id);
+";
+
+/// A state file open for a pass, written through as the pass goes: the fetch when it starts,
+/// then every outcome as it completes, each in its own SQLite transaction (or JSON rewrite).
+/// A crash mid-pass therefore loses neither the redemptions already made nor their ledger
+/// rows. A dry run only adds its attempts to the ledger and leaves the state alone, so that
+/// it does not hide anything from the next real run.
+pub struct StateFile {
+    path: PathBuf,
+    db: Option<Connection>,
+    state: State,
+    /// Unix timestamp (seconds) of the pass or push the recorded outcomes belong to.
+    at: u64,
+    dry_run: bool,
+}
+
+impl StateFile {
+    /// Opens the state file at `path`; a missing file is an empty state and triggers a full
+    /// fetch.
+    pub fn open(path: &Path, backend: StateBackend, dry_run: bool) -> Result<Self> {
+        let (db, state) = match backend {
+            StateBackend::Json => (None, State::load(path)?),
+            StateBackend::Sqlite => {
+                let db = Connection::open(path)
+                    .and_then(|db| db.execute_batch(SCHEMA).map(|()| db))
+                    .with_context(|| format!("Failed to open state database {}", path.display()))?;
+                let state = State::load_sqlite(&db)
+                    .with_context(|| format!("Failed to read state database {}", path.display()))?;
+                (Some(db), state)
+            }
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            db,
+            state,
+            at: now(),
+            dry_run,
+        })
+    }
+
+    pub fn state(&self) -> &State {
+        &self.state
+    }
+
+    /// Starts a pass that began at `since` and fetched `fetched`, which become the pending
+    /// subscriptions. Processed entries older than `window` are forgotten.
+    pub fn start(
+        &mut self,
+        since: u64,
+        fetched: &[RedeemableSubscription],
+        window: Duration,
+    ) -> Result<()> {
+        self.at = since;
+        if self.dry_run {
+            return Ok(());
+        }
+        let cutoff = since.saturating_sub(window.as_secs());
+        self.state.since = Some(since);
+        self.state.pending = fetched.to_vec();
+        self.state.processed.retain(|_, at| *at >= cutoff);
+        let Some(db) = &mut self.db else {
+            return self.state.save(&self.path);
+        };
+        in_transaction(db, &self.path, |tx| {
+            tx.execute(
+                "INSERT OR REPLACE INTO meta (key, value) VALUES ('since', ?1)",
+                [since],
+            )?;
+            tx.execute("DELETE FROM pending", [])?;
+            for subscription in fetched {
+                insert_pending(tx, subscription)?;
+            }
+            tx.execute("DELETE FROM processed WHERE at < ?1", [cutoff])?;
+            Ok(())
+        })
+    }
+
+    /// Adds subscriptions pushed by the indexer to the pending ones; `since` is left alone, as
+    /// nothing was fetched.
+    pub fn push(&mut self, pushed: &[RedeemableSubscription]) -> Result<()> {
+        self.at = now();
+        if self.dry_run {
+            return Ok(());
+        }
+        self.state.pending = self.state.merge(pushed.to_vec());
+        let Some(db) = &mut self.db else {
+            return self.state.save(&self.path);
+        };
+        in_transaction(db, &self.path, |tx| {
+            for subscription in pushed {
+                insert_pending(tx, subscription)?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Records how redeeming `subscription` (as fetched, if known) turned out: a settled one
+    /// moves from pending to processed, and a SQLite state file adds the attempt to its ledger.
+    pub fn record(
+        &mut self,
+        subscription: Option<&RedeemableSubscription>,
+        outcome: &Outcome,
+    ) -> Result<()> {
+        let settled = !self.dry_run && outcome.is_settled();
+        if settled {
+            self.state.settle(outcome.id, self.at);
+        }
+        let Some(db) = &mut self.db else {
+            // JSON keeps no ledger.
+            if settled {
+                return self.state.save(&self.path);
+            }
+            return Ok(());
+        };
+        let at = self.at;
+        in_transaction(db, &self.path, |tx| {
+            tx.execute(
+                "INSERT INTO attempts \
+                 (at, id, status, tx_hash, gas_used, value, error, error_kind, inputs) \
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                params![
+                    at,
+                    outcome.id.to_string(),
+                    outcome.status.to_string(),
+                    outcome.tx_hash.map(|tx_hash| tx_hash.to_string()),
+                    outcome.gas_used,
+                    outcome.value.map(|value| value.to_string()),
+                    outcome.error,
+                    outcome.error_kind,
+                    subscription.map(serde_json::to_string).transpose()?,
+                ],
+            )?;
+            if settled {
+                tx.execute(
+                    "DELETE FROM pending WHERE id = ?1",
+                    [outcome.id.to_string()],
+                )?;
+                tx.execute(
+                    "INSERT OR REPLACE INTO processed (id, at) VALUES (?1, ?2)",
+                    params![outcome.id.to_string(), at],
+                )?;
+            }
+            Ok(())
+        })
+    }
+}
+
+/// Runs `write` in one transaction on the state database at `path`.
+fn in_transaction(
+    db: &mut Connection,
+    path: &Path,
+    write: impl FnOnce(&Transaction) -> Result<()>,
+) -> Result<()> {
+    let context = || format!("Failed to write state database {}", path.display());
+    let tx = db.transaction().with_context(context)?;
+    write(&tx).with_context(context)?;
+    tx.commit().with_context(context)
+}
+
+fn insert_pending(tx: &Transaction, subscription: &RedeemableSubscription) -> Result<()> {
+    tx.execute(
+        "INSERT OR REPLACE INTO pending (id, subscription) VALUES (?1, ?2)",
+        params![
+            subscription.id.to_string(),
+            serde_json::to_string(subscription)?
+        ],
+    )?;
+    Ok(())
+}
+
+/// The current Unix timestamp in seconds.
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+impl State {
+    /// Reads a JSON state file; a missing file is an empty state.
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read state file {}", path.display()))?;
+        serde_json::from_str(&contents)
+            .with_context(|| format!("Failed to parse state file {}", path.display()))
+    }
+
+    /// Writes a JSON state file, replacing it atomically so a crash mid-write cannot leave it
+    /// truncated.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let temp = path.with_extension("tmp");
+        std::fs::write(&temp, serde_json::to_string_pretty(self)?)
+            .and_then(|()| std::fs::rename(&temp, path))
+            .with_context(|| format!("Failed to write state file {}", path.display()))
+    }
+
+    fn load_sqlite(db: &Connection) -> Result<Self> {
+        let since = db
+            .query_row("SELECT value FROM meta WHERE key = 'since'", [], |row| {
+                row.get(0)
+            })
+            .optional()?;
+        let pending = db
+            .prepare("SELECT subscription FROM pending")?
+            .query_map([], |row| row.get::<_, String>(0))?
+            .map(|subscription| Ok(serde_json::from_str(&subscription?)?))
+            .collect::<Result<_>>()?;
+        let processed = db
+            .prepare("SELECT id, at FROM processed")?
+            .query_map([], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, u64>(1)?))
+            })?
+            .map(|row| {
+                let (id, at) = row?;
+                Ok((id.parse()?, at))
+            })
+            .collect::<Result<_>>()?;
+        Ok(State {
+            since,
+            pending,
+            processed,
+        })
+    }
+
+    /// Moves a subscription settled at `at` from pending to processed.
+    fn settle(&mut self, id: B256, at: u64) {
+        self.pending.retain(|s| s.id != id);
+        self.processed.insert(id, at);
+    }
+
+    /// Drops subscriptions settled within `window` of now.
+    pub fn unprocessed(
+        &self,
+        subscriptions: Vec<RedeemableSubscription>,
+        window: Duration,
+    ) -> Vec<RedeemableSubscription> {
+        let cutoff = now().saturating_sub(window.as_secs());
+        let total = subscriptions.len();
+        let unprocessed: Vec<_> = subscriptions
+            .into_iter()
+            .filter(|s| self.processed.get(&s.id).is_none_or(|&at| at < cutoff))
+            .collect();
+        if unprocessed.len() != total {
+            tracing::info!(
+                "Ignoring {} subscriptions already processed",
+                total - unprocessed.len()
+            );
+        }
+        unprocessed
+    }
+
+    /// The freshly fetched subscriptions plus the pending ones they don't supersede.
+    pub fn merge(&self, fetched: Vec<RedeemableSubscription>) -> Vec<RedeemableSubscription> {
+        let ids: HashSet<_> = fetched.iter().map(|s| s.id).collect();
+        let carried = self
+            .pending
+            .iter()
+            .filter(|s| !ids.contains(&s.id))
+            .cloned();
+        fetched.into_iter().chain(carried).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::redeem::{Category, Sent};
+    use alloy::primitives::{Address, B256, U256};
+
+    fn subscription(id: u8, periods: i32) -> RedeemableSubscription {
+        RedeemableSubscription {
+            contract_address: Address::ZERO,
+            id: B256::repeat_byte(id),
+            recipient: Address::ZERO,
+            subscriber: Address::ZERO,
+            amount: U256::from(1),
+            periods,
+            category: Category::Untrusted,
+        }
+    }
+
+    #[test]
+    fn test_merge_prefers_fetched() {
+        let state = State {
+            since: Some(1),
+            pending: vec![subscription(1, 1), subscription(2, 1)],
+            ..Default::default()
+        };
+        let merged = state.merge(vec![subscription(2, 3), subscription(3, 1)]);
+        let ids: Vec<_> = merged.iter().map(|s| (s.id, s.periods)).collect();
+        assert_eq!(
+            ids,
+            vec![
+                (B256::repeat_byte(2), 3),
+                (B256::repeat_byte(3), 1),
+                (B256::repeat_byte(1), 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_settled_subscriptions_are_deduplicated() {
+        let path =
+            std::env::temp_dir().join(format!("redeem-rs-dedupe-{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let window = Duration::from_secs(3600);
+        let mut file = StateFile::open(&path, StateBackend::Json, false).unwrap();
+        let fetched = [subscription(1, 1), subscription(2, 1)];
+        file.start(now(), &fetched, window).unwrap();
+        file.record(
+            Some(&fetched[0]),
+            &Outcome::redeemed(B256::repeat_byte(1), B256::ZERO),
+        )
+        .unwrap();
+        file.record(
+            Some(&fetched[1]),
+            &Outcome::failed(B256::repeat_byte(2), "rpc down"),
+        )
+        .unwrap();
+
+        // Written as each outcome completed.
+        let state = State::load(&path).unwrap();
+        assert_eq!(state.pending.len(), 1);
+        assert!(state.processed.contains_key(&B256::repeat_byte(1)));
+        let next = state.unprocessed(fetched.to_vec(), window);
+        assert_eq!(next.len(), 1);
+        assert_eq!(next[0].id, B256::repeat_byte(2));
+
+        file.start(now() + 7200, &[], window).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(file.state().processed.is_empty());
+    }
+
+    #[test]
+    fn test_sqlite_round_trip() {
+        let path = std::env::temp_dir().join(format!("redeem-rs-state-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let open = |dry_run| StateFile::open(&path, StateBackend::Sqlite, dry_run).unwrap();
+        assert!(open(false).state().since.is_none());
+
+        let window = Duration::from_secs(3600);
+        let fetched = [subscription(1, 2), subscription(2, 1)];
+        let mut file = open(false);
+        file.start(100, &fetched, window).unwrap();
+        let redeemed = Outcome::redeemed(
+            B256::repeat_byte(1),
+            Sent {
+                tx_hash: B256::repeat_byte(9),
+                gas_used: Some(60_000),
+            },
+        );
+        file.record(Some(&fetched[0]), &redeemed).unwrap();
+        // A crash here keeps what was recorded so far.
+        let loaded = open(false);
+        assert_eq!(loaded.state().since, Some(100));
+        assert_eq!(loaded.state().pending.len(), 1);
+        assert_eq!(loaded.state().pending[0].id, B256::repeat_byte(2));
+        assert_eq!(
+            loaded.state().processed.get(&B256::repeat_byte(1)),
+            Some(&100)
+        );
+        file.record(
+            Some(&fetched[1]),
+            &Outcome::failed(B256::repeat_byte(2), "rpc down"),
+        )
+        .unwrap();
+
+        // A dry run only adds to the ledger.
+        let mut dry = open(true);
+        dry.start(200, &[], window).unwrap();
+        dry.record(None, &Outcome::dry_run(B256::repeat_byte(2), 21_000))
+            .unwrap();
+        assert_eq!(open(false).state().since, Some(100));
+
+        // A push adds to the pending subscriptions without advancing `since`.
+        file.push(&[subscription(3, 1)]).unwrap();
+        let reloaded = open(false);
+        assert_eq!(reloaded.state().since, Some(100));
+        assert_eq!(reloaded.state().pending.len(), 2);
+        drop((file, dry, reloaded));
+
+        let db = Connection::open(&path).unwrap();
+        let ledger: Vec<(String, String, Option<String>, Option<u64>, Option<String>)> = db
+            .prepare("SELECT id, status, tx_hash, gas_used, inputs FROM attempts ORDER BY rowid")
+            .unwrap()
+            .query_map([], |row| {
+                Ok((
+                    row.get(0)?,
+                    row.get(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                    row.get(4)?,
+                ))
+            })
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        drop(db);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(ledger.len(), 3);
+        assert_eq!(ledger[0].0, B256::repeat_byte(1).to_string());
+        assert_eq!(ledger[0].1, "redeemed");
+        assert_eq!(ledger[0].2, Some(B256::repeat_byte(9).to_string()));
+        assert_eq!(ledger[0].3, Some(60_000));
+        let inputs: RedeemableSubscription =
+            serde_json::from_str(ledger[0].4.as_deref().unwrap()).unwrap();
+        assert_eq!(inputs.periods, 2);
+        assert_eq!(ledger[1].1, "failed");
+        assert_eq!(ledger[2].1, "dry-run");
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let path =
+            std::env::temp_dir().join(format!("redeem-rs-state-{}.json", std::process::id()));
+        assert!(State::load(&path).unwrap().since.is_none());
+        State {
+            since: Some(42),
+            pending: vec![subscription(1, 1)],
+            ..Default::default()
+        }
+        .save(&path)
+        .unwrap();
+        let state = State::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(state.since, Some(42));
+        assert_eq!(state.pending.len(), 1);
+    }
+}
@@ -0,0 +1,104 @@
+use reqwest::{Client, Url, header::HeaderMap};
+use std::time::Duration;
+use tokio::{sync::mpsc, task::JoinHandle};
+
+use crate::{fetch, redeem::RedeemableSubscription};
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Listens to the indexer's server-sent events stream and forwards the subscriptions each
+/// event announces. Every `data` payload is one subscription or an array of them, in the
+/// indexer's format. Reconnects after errors until the receiver is dropped or the returned
+/// task is aborted.
+pub fn spawn(
+    client: Client,
+    url: Url,
+    headers: HeaderMap,
+    tx: mpsc::Sender<Vec<RedeemableSubscription>>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        while !tx.is_closed() {
+            match listen(&client, &url, &headers, &tx).await {
+                Ok(()) => tracing::warn!("Indexer stream closed, reconnecting"),
+                Err(e) => tracing::warn!("Indexer stream failed, reconnecting: {e:#}"),
+            }
+            tokio::time::sleep(RECONNECT_DELAY).await;
+        }
+    })
+}
+
+async fn listen(
+    client: &Client,
+    url: &Url,
+    headers: &HeaderMap,
+    tx: &mpsc::Sender<Vec<RedeemableSubscription>>,
+) -> anyhow::Result<()> {
+    let mut response = client
+        .get(url.clone())
+        .headers(headers.clone())
+        .header(reqwest::header::ACCEPT, "text/event-stream")
+        .send()
+        .await?
+        .error_for_status()?;
+    tracing::info!("Listening for redeemable subscriptions on {url}");
+    let mut parser = EventParser::default();
+    while let Some(chunk) = response.chunk().await? {
+        for data in parser.feed(&String::from_utf8_lossy(&chunk)) {
+            let entries = match serde_json::from_str(&data) {
+                Ok(serde_json::Value::Array(entries)) => entries,
+                Ok(entry) => vec![entry],
+                Err(e) => {
+                    tracing::warn!("Ignoring malformed stream event: {e}: {data}");
+                    continue;
+                }
+            };
+            let subscriptions = fetch::parse_entries(entries);
+            if !subscriptions.is_empty() && tx.send(subscriptions).await.is_err() {
+                return Ok(());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Splits a server-sent events byte stream into the `data` payloads of complete events.
+#[derive(Debug, Default)]
+struct EventParser {
+    buffer: String,
+}
+
+impl EventParser {
+    fn feed(&mut self, chunk: &str) -> Vec<String> {
+        self.buffer.push_str(&chunk.replace("\r\n", "\n"));
+        let mut events = Vec::new();
+        while let Some(end) = self.buffer.find("\n\n") {
+            let event: String = self.buffer.drain(..end + 2).collect();
+            let data: Vec<&str> = event
+                .lines()
+                .filter_map(|line| line.strip_prefix("data:"))
+                .map(|data| data.strip_prefix(' ').unwrap_or(data))
+                .collect();
+            if !data.is_empty() {
+                events.push(data.join("\n"));
+            }
+        }
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_parser() {
+        let mut parser = EventParser::default();
+        assert!(
+            parser
+                .feed(": keep-alive\n\nevent: redeemable\ndata: {\"a\":")
+                .is_empty()
+        );
+        assert_eq!(parser.feed("\ndata: 1}\n\n"), vec!["{\"a\":\n1}"]);
+        assert_eq!(parser.feed("data: []\r\n\r\n"), vec!["[]"]);
+    }
+}
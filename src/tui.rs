@@ -0,0 +1,200 @@
+use alloy::primitives::{Address, B256, U256, utils::format_ether};
+use ratatui::{
+    Frame,
+    crossterm::event::{self, Event, KeyCode},
+    layout::{Constraint, Layout},
+    style::{Color, Style},
+    text::Line,
+    widgets::{Block, List, ListItem, Paragraph},
+};
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use crate::{
+    redeem::RedeemableSubscription,
+    report::{Outcome, Status},
+};
+
+const RECENT: usize = 50;
+
+/// What the daemon is doing, as shown by the dashboard.
+#[derive(Debug, Default)]
+pub struct DashboardState {
+    pub executor: Option<Address>,
+    pub balance: Option<U256>,
+    pub pending: Vec<RedeemableSubscription>,
+    pub in_flight: Vec<B256>,
+    pub recent: VecDeque<Outcome>,
+    pub last_pass: Option<SystemTime>,
+}
+
+/// Shared handle through which the daemon feeds the dashboard.
+#[derive(Debug, Clone, Default)]
+pub struct Dashboard(Arc<Mutex<DashboardState>>);
+
+impl Dashboard {
+    fn update(&self, f: impl FnOnce(&mut DashboardState)) {
+        if let Ok(mut state) = self.0.lock() {
+            f(&mut state);
+        }
+    }
+
+    pub fn set_balance(&self, executor: Address, balance: U256) {
+        self.update(|s| {
+            s.executor = Some(executor);
+            s.balance = Some(balance);
+        });
+    }
+
+    pub fn set_pending(&self, subscriptions: &[RedeemableSubscription]) {
+        self.update(|s| {
+            s.pending = subscriptions.to_vec();
+            s.last_pass = Some(SystemTime::now());
+        });
+    }
+
+    pub fn start(&self, id: B256) {
+        self.update(|s| s.in_flight.push(id));
+    }
+
+    pub fn finish(&self, outcome: &Outcome) {
+        self.update(|s| {
+            s.pending.retain(|p| p.id != outcome.id);
+            s.in_flight.retain(|id| *id != outcome.id);
+            s.recent.push_front(outcome.clone());
+            s.recent.truncate(RECENT);
+        });
+    }
+
+    /// Draws the dashboard on a separate thread until the operator presses `q`, then
+    /// restores the terminal and signals `quit`.
+    pub fn spawn(&self, quit: tokio::sync::mpsc::Sender<()>) {
+        let dashboard = self.clone();
+        std::thread::spawn(move || {
+            let mut terminal = ratatui::init();
+            loop {
+                if let Ok(state) = dashboard.0.lock() {
+                    let _ = terminal.draw(|frame| draw(frame, &state));
+                }
+                match event::poll(Duration::from_millis(250)) {
+                    Ok(true) => {
+                        if let Ok(Event::Key(key)) = event::read()
+                            && matches!(key.code, KeyCode::Char('q') | KeyCode::Esc)
+                        {
+                            break;
+                        }
+                    }
+                    Ok(false) => {}
+                    Err(_) => break,
+                }
+            }
+            ratatui::restore();
+            let _ = quit.blocking_send(());
+        });
+    }
+}
+
+fn draw(frame: &mut Frame, state: &DashboardState) {
+    let [header, body, footer] = Layout::vertical([
+        Constraint::Length(3),
+        Constraint::Min(0),
+        Constraint::Length(1),
+    ])
+    .areas(frame.area());
+    let [left, recent] =
+        Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)]).areas(body);
+    let [pending, in_flight] =
+        Layout::vertical([Constraint::Min(0), Constraint::Length(6)]).areas(left);
+
+    let executor = state
+        .executor
+        .map(|a| a.to_string())
+        .unwrap_or_else(|| "-".into());
+    let balance = state
+        .balance
+        .map(|b| format!("{} xDAI", format_ether(b)))
+        .unwrap_or_else(|| "-".into());
+    let last_pass = state
+        .last_pass
+        .map(|t| humantime::format_rfc3339_seconds(t).to_string())
+        .unwrap_or_else(|| "-".into());
+    frame.render_widget(
+        Paragraph::new(format!(
+            "executor {executor}   balance {balance}   last pass {last_pass}"
+        ))
+        .block(Block::bordered().title("redeem-rs")),
+        header,
+    );
+
+    let items: Vec<ListItem> = state
+        .pending
+        .iter()
+        .map(|s| {
+            ListItem::new(format!(
+                "{} {:?} {} -> {}",
+                s.id, s.category, s.subscriber, s.recipient
+            ))
+        })
+        .collect();
+    frame.render_widget(
+        List::new(items)
+            .block(Block::bordered().title(format!("Pending ({})", state.pending.len()))),
+        pending,
+    );
+
+    let items: Vec<ListItem> = state
+        .in_flight
+        .iter()
+        .map(|id| ListItem::new(id.to_string()))
+        .collect();
+    frame.render_widget(
+        List::new(items).block(Block::bordered().title("In flight")),
+        in_flight,
+    );
+
+    let items: Vec<ListItem> = state
+        .recent
+        .iter()
+        .map(|o| {
+            let color = match o.status {
+                Status::Redeemed | Status::DryRun | Status::Simulated => Color::Green,
+                Status::Skipped => Color::Yellow,
+                Status::Failed => Color::Red,
+            };
+            let detail = o
+                .tx_hash
+                .map(|h| h.to_string())
+                .or_else(|| o.error.clone())
+                .unwrap_or_default();
+            ListItem::new(Line::styled(
+                format!("{:<8} {} {}", o.status.to_string(), o.id, detail),
+                Style::default().fg(color),
+            ))
+        })
+        .collect();
+    frame.render_widget(
+        List::new(items).block(Block::bordered().title("Recent")),
+        recent,
+    );
+
+    frame.render_widget(Paragraph::new("q: quit"), footer);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_finish_moves_to_recent() {
+        let dashboard = Dashboard::default();
+        dashboard.start(B256::ZERO);
+        dashboard.finish(&Outcome::failed(B256::ZERO, "reverted"));
+        let state = dashboard.0.lock().unwrap();
+        assert!(state.in_flight.is_empty());
+        assert_eq!(state.recent.len(), 1);
+        assert_eq!(state.recent[0].status, Status::Failed);
+    }
+}
@@ -0,0 +1,62 @@
+use alloy::{
+    network::Ethereum,
+    providers::{Provider, RootProvider, WsConnect},
+    rpc::client::RpcClient,
+};
+use anyhow::{Context, Result, anyhow};
+use futures::lock::Mutex;
+use reqwest::Url;
+use tokio::time::Duration;
+
+/// How long a connection may take to answer before it is considered dead and replaced.
+const PING_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Consecutive failed attempts after which a dropped connection is given up until next used.
+const MAX_RETRIES: u32 = 30;
+
+/// WebSocket RPC that pending transactions are watched through: new blocks are pushed by a
+/// `newHeads` subscription instead of being polled for. A dropped connection is reconnected
+/// and its subscriptions restarted in the background; if that keeps failing, a fresh
+/// connection is made the next time one is needed.
+pub struct Ws {
+    pub url: Url,
+    provider: Mutex<Option<RootProvider<Ethereum>>>,
+}
+
+impl Ws {
+    pub fn new(url: Url) -> Self {
+        Self {
+            url,
+            provider: Mutex::new(None),
+        }
+    }
+
+    /// The connected provider, connecting first if there is none or it stopped answering.
+    pub async fn provider(&self, chain_id: u64) -> Result<RootProvider<Ethereum>> {
+        let mut provider = self.provider.lock().await;
+        if let Some(connected) = provider.as_ref() {
+            match tokio::time::timeout(PING_TIMEOUT, connected.get_block_number()).await {
+                Ok(Ok(_)) => return Ok(connected.clone()),
+                Ok(Err(e)) => tracing::warn!("WebSocket {} failed, reconnecting: {e}", self.url),
+                Err(_) => tracing::warn!("WebSocket {} not answering, reconnecting", self.url),
+            }
+            *provider = None;
+        }
+        let connect = WsConnect::new(self.url.as_str()).with_max_retries(MAX_RETRIES);
+        let connected = RootProvider::<Ethereum>::new(
+            RpcClient::connect_pubsub(connect)
+                .await
+                .with_context(|| format!("Failed to connect to {}", self.url))?,
+        );
+        let actual = connected.get_chain_id().await?;
+        if actual != chain_id {
+            return Err(anyhow!(
+                "{} is on chain {actual}, expected {chain_id}",
+                self.url
+            ));
+        }
+        tracing::debug!("Connected to {}", self.url);
+        *provider = Some(connected.clone());
+        Ok(connected)
+    }
+}